@@ -0,0 +1,440 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::anyhow;
+use tracing::{debug, warn};
+
+use crate::{
+    AiAdapter, AiError, AiService, CompletionOptions, CompletionResult, CompletionStream, CostTier,
+    EmbeddingService, Message, ModelService, Tool,
+};
+
+/// Tries an ordered list of backends, e.g. a local ollama first and openai when it fails.
+/// A request goes to the first backend which accepts it, and fails over to the next one on
+/// any error. Backends can be limited by prompt length and cost tier.
+pub struct FallbackAdapter {
+    backends: Vec<Backend>,
+    metrics: Arc<FallbackMetrics>,
+}
+
+pub struct Backend {
+    adapter: AiAdapter,
+    max_prompt_len: Option<usize>,
+    cost_tier: Option<CostTier>,
+}
+
+/// Requests, failures and failovers of fallback adapters. An adapter built for a single request
+/// can count in the metrics of a longer lived owner, see [`FallbackAdapter::metrics`].
+#[derive(Debug, Default)]
+pub struct FallbackMetrics {
+    failovers: AtomicU64,
+    backends: Mutex<BTreeMap<BackendKey, BackendStats>>,
+}
+
+/// The same model may be served by several providers, or by several servers of one provider
+type BackendKey = (&'static str, Option<String>, String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendStats {
+    pub provider: String,
+    pub host: Option<String>,
+    pub model: String,
+    pub requests: u64,
+    pub failures: u64,
+}
+
+impl FallbackAdapter {
+    pub fn new(primary: impl Into<Backend>) -> Self {
+        Self {
+            backends: vec![primary.into()],
+            metrics: Arc::default(),
+        }
+    }
+
+    /// Count in shared metrics instead of the adapter's own
+    pub fn metrics(mut self, metrics: Arc<FallbackMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Add a backend, tried after all the previous ones
+    pub fn fallback(mut self, backend: impl Into<Backend>) -> Self {
+        self.backends.push(backend.into());
+        self
+    }
+
+    /// The model of the primary backend
+    pub fn model(&self) -> &str {
+        self.backends[0].adapter.model()
+    }
+
+    /// The primary backend
+    pub fn primary(&self) -> &AiAdapter {
+        &self.backends[0].adapter
+    }
+
+    /// Number of requests which failed on a backend and were sent to the next one
+    pub fn failovers(&self) -> u64 {
+        self.metrics.failovers()
+    }
+
+    /// Requests and failures of the backends, in order
+    pub fn stats(&self) -> Vec<BackendStats> {
+        let stats = self.metrics.backends.lock().unwrap();
+        self.backends
+            .iter()
+            .map(|b| {
+                stats
+                    .get(&key(&b.adapter))
+                    .cloned()
+                    .unwrap_or_else(|| BackendStats::new(&b.adapter))
+            })
+            .collect()
+    }
+
+    /// Call `f` with the index of every backend accepting the request in turn, until one
+    /// succeeds. Returns the last error if all of them fail.
+    async fn run<T, F, Fut>(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+        f: F,
+    ) -> Result<T, AiError>
+    where
+        F: Fn(usize) -> Fut,
+        Fut: Future<Output = Result<T, AiError>>,
+    {
+        let prompt_len: usize = messages.iter().map(|m| m.content.chars().count()).sum();
+        let candidates: Vec<usize> = (0..self.backends.len())
+            .filter(|i| self.backends[*i].accepts(prompt_len, options))
+            .collect();
+        if candidates.is_empty() {
+            return Err(AiError::ContextTooLong(format!(
+                "no backend accepts a prompt of {} chars with cost tier {:?}",
+                prompt_len, options.max_cost_tier
+            )));
+        }
+
+        let mut last_err = None;
+        for (n, i) in candidates.iter().enumerate() {
+            let backend = &self.backends[*i];
+            let err = match f(*i).await {
+                Ok(v) => {
+                    self.metrics.record(&backend.adapter, false);
+                    return Ok(v);
+                }
+                Err(e) => e,
+            };
+            self.metrics.record(&backend.adapter, true);
+            if let Some(next) = candidates.get(n + 1) {
+                let failovers = self.metrics.failovers.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(
+                    "ai backend {} failed: {}, failing over to {} ({} failovers so far)",
+                    backend.adapter.model(),
+                    err,
+                    self.backends[*next].adapter.model(),
+                    failovers
+                );
+            }
+            last_err = Some(err);
+        }
+        Err(last_err.unwrap_or_else(|| anyhow!("no backend was tried").into()))
+    }
+}
+
+impl Backend {
+    pub fn new(adapter: impl Into<AiAdapter>) -> Self {
+        Self {
+            adapter: adapter.into(),
+            max_prompt_len: None,
+            cost_tier: None,
+        }
+    }
+
+    /// Skip this backend for prompts longer than `len` chars
+    pub fn max_prompt_len(mut self, len: usize) -> Self {
+        self.max_prompt_len = Some(len);
+        self
+    }
+
+    /// Skip this backend when the request asks for a cheaper tier
+    pub fn cost_tier(mut self, tier: CostTier) -> Self {
+        self.cost_tier = Some(tier);
+        self
+    }
+
+    fn accepts(&self, prompt_len: usize, options: &CompletionOptions) -> bool {
+        if self.max_prompt_len.is_some_and(|max| prompt_len > max) {
+            debug!(
+                "skip ai backend {}: prompt of {} chars is too long",
+                self.adapter.model(),
+                prompt_len
+            );
+            return false;
+        }
+        match (self.cost_tier, options.max_cost_tier) {
+            (Some(tier), Some(max)) if tier > max => {
+                debug!(
+                    "skip ai backend {}: cost tier {:?} is above {:?}",
+                    self.adapter.model(),
+                    tier,
+                    max
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+impl FallbackMetrics {
+    /// Number of requests which failed on a backend and were sent to the next one
+    pub fn failovers(&self) -> u64 {
+        self.failovers.load(Ordering::Relaxed)
+    }
+
+    /// Requests and failures of every backend, sorted by provider, host and model
+    pub fn stats(&self) -> Vec<BackendStats> {
+        self.backends.lock().unwrap().values().cloned().collect()
+    }
+
+    fn record(&self, adapter: &AiAdapter, failed: bool) {
+        let mut backends = self.backends.lock().unwrap();
+        let stats = backends
+            .entry(key(adapter))
+            .or_insert_with(|| BackendStats::new(adapter));
+        stats.requests += 1;
+        if failed {
+            stats.failures += 1;
+        }
+    }
+}
+
+impl BackendStats {
+    fn new(adapter: &AiAdapter) -> Self {
+        Self {
+            provider: adapter.provider().to_string(),
+            host: adapter.host().map(str::to_string),
+            model: adapter.model().to_string(),
+            requests: 0,
+            failures: 0,
+        }
+    }
+}
+
+fn key(adapter: &AiAdapter) -> BackendKey {
+    (
+        adapter.provider(),
+        adapter.host().map(str::to_string),
+        adapter.model().to_string(),
+    )
+}
+
+impl<T: Into<AiAdapter>> From<T> for Backend {
+    fn from(adapter: T) -> Self {
+        Backend::new(adapter)
+    }
+}
+
+impl AiService for FallbackAdapter {
+    async fn complete(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        self.run(messages, options, |i| {
+            Box::pin(self.backends[i].adapter.complete(messages, options))
+        })
+        .await
+    }
+
+    // only fails over when the stream can't be started, not in the middle of it
+    async fn complete_stream(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionStream, AiError> {
+        self.run(messages, options, |i| {
+            Box::pin(self.backends[i].adapter.complete_stream(messages, options))
+        })
+        .await
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        self.run(messages, options, |i| {
+            Box::pin(
+                self.backends[i]
+                    .adapter
+                    .complete_with_tools(messages, tools, options),
+            )
+        })
+        .await
+    }
+}
+
+// vectors of different models are not comparable, so embeddings never fail over
+impl EmbeddingService for FallbackAdapter {
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, AiError> {
+        Box::pin(self.backends[0].adapter.embed(input)).await
+    }
+}
+
+// the model of an agent is the one of the primary backend, the others have their own
+impl ModelService for FallbackAdapter {
+    async fn list_models(&self) -> Result<Vec<String>, AiError> {
+        Box::pin(self.backends[0].adapter.list_models()).await
+    }
+}
+
+impl From<FallbackAdapter> for AiAdapter {
+    fn from(adapter: FallbackAdapter) -> Self {
+        AiAdapter::Fallback(adapter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{adapters::test_utils::mock_server_with_status, MockAdapter, OllamaAdapter};
+
+    #[tokio::test]
+    async fn fallback_adapter_should_fail_over() {
+        let adapter = FallbackAdapter::new(MockAdapter::new("llama3.2").fail(AiError::Timeout))
+            .fallback(MockAdapter::new("gpt-4o-mini").reply("from openai"));
+        let messages = vec![Message::user("Hello")];
+        let options = CompletionOptions::default();
+
+        let ret = adapter.complete(&messages, &options).await.unwrap();
+        assert_eq!(ret.content, "from openai");
+        assert_eq!(ret.model, "gpt-4o-mini");
+        assert_eq!(adapter.failovers(), 1);
+
+        // the primary is back
+        let ret = adapter.complete(&messages, &options).await.unwrap();
+        assert_eq!(ret.model, "llama3.2");
+        assert_eq!(
+            adapter.stats(),
+            vec![
+                BackendStats {
+                    provider: "mock".to_string(),
+                    host: None,
+                    model: "llama3.2".to_string(),
+                    requests: 2,
+                    failures: 1,
+                },
+                BackendStats {
+                    provider: "mock".to_string(),
+                    host: None,
+                    model: "gpt-4o-mini".to_string(),
+                    requests: 1,
+                    failures: 0,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fallback_adapter_should_return_last_error() {
+        let adapter = FallbackAdapter::new(MockAdapter::new("llama3.2").fail(AiError::Timeout))
+            .fallback(
+                MockAdapter::new("gpt-4o-mini").fail(AiError::RateLimited { retry_after: None }),
+            );
+        let messages = vec![Message::user("Hello")];
+        let ret = adapter
+            .complete(&messages, &CompletionOptions::default())
+            .await;
+        assert!(matches!(ret, Err(AiError::RateLimited { .. })));
+        assert_eq!(adapter.failovers(), 1);
+    }
+
+    #[tokio::test]
+    async fn fallback_adapters_should_share_metrics() {
+        let metrics = Arc::new(FallbackMetrics::default());
+        let messages = vec![Message::user("Hello")];
+        let options = CompletionOptions::default();
+        for _ in 0..2 {
+            let adapter = FallbackAdapter::new(MockAdapter::new("llama3.2").fail(AiError::Timeout))
+                .fallback(MockAdapter::new("gpt-4o-mini"))
+                .metrics(metrics.clone());
+            adapter.complete(&messages, &options).await.unwrap();
+        }
+        assert_eq!(metrics.failovers(), 2);
+        assert_eq!(
+            metrics.stats(),
+            vec![
+                BackendStats {
+                    provider: "mock".to_string(),
+                    host: None,
+                    model: "gpt-4o-mini".to_string(),
+                    requests: 2,
+                    failures: 0,
+                },
+                BackendStats {
+                    provider: "mock".to_string(),
+                    host: None,
+                    model: "llama3.2".to_string(),
+                    requests: 2,
+                    failures: 2,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fallback_metrics_should_keep_hosts_apart() {
+        let error = (400, r#"{"error": "model is required"}"#);
+        let first = mock_server_with_status("/api/chat", "application/json", &[error]).await;
+        let second = mock_server_with_status("/api/chat", "application/json", &[error]).await;
+        let adapter = FallbackAdapter::new(OllamaAdapter::new(&first, "llama3.2"))
+            .fallback(OllamaAdapter::new(&second, "llama3.2"));
+        let messages = vec![Message::user("Hello")];
+        let ret = adapter
+            .complete(&messages, &CompletionOptions::default())
+            .await;
+        assert!(ret.is_err());
+
+        let stats = adapter.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].provider, "ollama");
+        assert_eq!(stats[0].host.as_deref(), Some(first.as_str()));
+        assert_eq!(stats[1].host.as_deref(), Some(second.as_str()));
+        assert!(stats.iter().all(|s| s.requests == 1 && s.failures == 1));
+    }
+
+    #[tokio::test]
+    async fn fallback_adapter_should_route_requests() {
+        let adapter = FallbackAdapter::new(
+            Backend::new(MockAdapter::new("llama3.2"))
+                .max_prompt_len(10)
+                .cost_tier(CostTier::Low),
+        )
+        .fallback(Backend::new(MockAdapter::new("gpt-4o")).cost_tier(CostTier::High));
+        let options = CompletionOptions::default();
+
+        let short = vec![Message::user("Hello")];
+        let ret = adapter.complete(&short, &options).await.unwrap();
+        assert_eq!(ret.model, "llama3.2");
+
+        // too long for the local model
+        let long = vec![Message::user("Hello, how are you today?")];
+        let ret = adapter.complete(&long, &options).await.unwrap();
+        assert_eq!(ret.model, "gpt-4o");
+        assert_eq!(adapter.failovers(), 0);
+
+        // too long for the local model, and too expensive for the other one
+        let options = options.max_cost_tier(CostTier::Medium);
+        let ret = adapter.complete(&long, &options).await;
+        assert!(matches!(ret, Err(AiError::ContextTooLong(_))));
+    }
+}
@@ -0,0 +1,227 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use futures::StreamExt;
+
+use crate::{
+    AiAdapter, AiError, AiService, CompletionOptions, CompletionResult, CompletionStream,
+    EmbeddingService, Message, ModelService, Tool, ToolCall, Usage,
+};
+
+const DEFAULT_DIMENSIONS: usize = 8;
+
+/// A deterministic adapter for tests. It replies with the scripted replies in order, and
+/// echoes the last message once they are used up. Every request is recorded.
+pub struct MockAdapter {
+    model: String,
+    models: Vec<String>,
+    dimensions: usize,
+    replies: Mutex<VecDeque<Result<Message, AiError>>>,
+    requests: Mutex<Vec<Vec<Message>>>,
+}
+
+impl MockAdapter {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            models: vec![],
+            dimensions: DEFAULT_DIMENSIONS,
+            replies: Mutex::new(VecDeque::new()),
+            requests: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Reply with `content` to the next request
+    pub fn reply(self, content: impl Into<String>) -> Self {
+        self.push(Ok(Message::assiatant(content)))
+    }
+
+    /// Ask to call `calls` in the next request
+    pub fn reply_tool_calls(self, calls: Vec<ToolCall>) -> Self {
+        self.push(Ok(Message::tool_calls(calls)))
+    }
+
+    /// Fail the next request with `err`
+    pub fn fail(self, err: AiError) -> Self {
+        self.push(Err(err))
+    }
+
+    /// Dimension of the vectors returned by [`EmbeddingService::embed`]
+    pub fn with_dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = dimensions.max(1);
+        self
+    }
+
+    /// Models returned by [`ModelService::list_models`], none by default
+    pub fn with_models(mut self, models: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.models = models.into_iter().map(Into::into).collect();
+        self.models.sort();
+        self
+    }
+
+    /// The messages of every request received so far
+    pub fn requests(&self) -> Vec<Vec<Message>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn push(self, reply: Result<Message, AiError>) -> Self {
+        self.replies.lock().unwrap().push_back(reply);
+        self
+    }
+
+    fn next_reply(&self, messages: &[Message]) -> Result<Message, AiError> {
+        self.requests.lock().unwrap().push(messages.to_vec());
+        match self.replies.lock().unwrap().pop_front() {
+            Some(reply) => reply,
+            None => {
+                let content = messages.last().map(|m| m.content.clone());
+                Ok(Message::assiatant(content.unwrap_or_default()))
+            }
+        }
+    }
+
+    fn result(&self, messages: &[Message], reply: Message) -> CompletionResult {
+        // count words as tokens and every tool call as one, good enough for tests
+        let prompt_tokens = messages.iter().map(|m| tokens(&m.content)).sum();
+        let completion_tokens = tokens(&reply.content) + reply.tool_calls.len() as u32;
+        CompletionResult {
+            usage: Usage::new(prompt_tokens, completion_tokens),
+            content: reply.content,
+            tool_calls: reply.tool_calls,
+            model: self.model.clone(),
+            finish_reason: Some("stop".to_string()),
+            latency: Duration::ZERO,
+        }
+    }
+}
+
+impl AiService for MockAdapter {
+    async fn complete(
+        &self,
+        messages: &[Message],
+        _options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        let reply = self.next_reply(messages)?;
+        Ok(self.result(messages, reply))
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: &[Message],
+        _options: &CompletionOptions,
+    ) -> Result<CompletionStream, AiError> {
+        let reply = self.next_reply(messages)?;
+        let deltas: Vec<_> = reply
+            .content
+            .split_inclusive(' ')
+            .map(|d| Ok(d.to_string()))
+            .collect();
+        Ok(futures::stream::iter(deltas).boxed())
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        _tools: &[Tool],
+        _options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        let reply = self.next_reply(messages)?;
+        Ok(self.result(messages, reply))
+    }
+}
+
+impl EmbeddingService for MockAdapter {
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, AiError> {
+        // the same text always gets the same vector
+        let embeddings = input
+            .iter()
+            .map(|text| {
+                let mut v = vec![0.0; self.dimensions];
+                for (i, b) in text.bytes().enumerate() {
+                    v[i % self.dimensions] += b as f32 / 255.0;
+                }
+                v
+            })
+            .collect();
+        Ok(embeddings)
+    }
+}
+
+impl ModelService for MockAdapter {
+    async fn list_models(&self) -> Result<Vec<String>, AiError> {
+        Ok(self.models.clone())
+    }
+}
+
+fn tokens(content: &str) -> u32 {
+    content.split_whitespace().count() as u32
+}
+
+impl From<MockAdapter> for AiAdapter {
+    fn from(adapter: MockAdapter) -> Self {
+        AiAdapter::Mock(adapter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::Role;
+
+    #[tokio::test]
+    async fn mock_adapter_should_reply_in_order() {
+        let call = ToolCall {
+            id: "call_0".to_string(),
+            name: "get_weather".to_string(),
+            arguments: json!({ "city": "Paris" }),
+        };
+        let adapter = MockAdapter::new("mock")
+            .reply("first")
+            .reply_tool_calls(vec![call.clone()])
+            .fail(AiError::Timeout);
+        let options = CompletionOptions::default();
+        let messages = vec![Message::user("Hello there")];
+
+        let ret = adapter.complete(&messages, &options).await.unwrap();
+        assert_eq!(ret.content, "first");
+        assert_eq!(ret.usage, Usage::new(2, 1));
+        let ret = adapter
+            .complete_with_tools(&messages, &[], &options)
+            .await
+            .unwrap();
+        assert_eq!(ret.tool_calls, vec![call]);
+        assert_eq!(ret.usage, Usage::new(2, 1));
+        let ret = adapter.complete(&messages, &options).await;
+        assert!(matches!(ret, Err(AiError::Timeout)));
+
+        // echo once the replies are used up
+        let stream = adapter.complete_stream(&messages, &options).await.unwrap();
+        let deltas: Vec<String> = stream.map(|d| d.unwrap()).collect().await;
+        assert_eq!(deltas, vec!["Hello ", "there"]);
+
+        let requests = adapter.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0][0].role, Role::User);
+    }
+
+    #[tokio::test]
+    async fn mock_adapter_should_embed_deterministically() {
+        let adapter = MockAdapter::new("mock").with_dimensions(4);
+        let input = vec![
+            "hello".to_string(),
+            "world".to_string(),
+            "hello".to_string(),
+        ];
+        let ret = adapter.embed(&input).await.unwrap();
+        assert_eq!(ret.len(), 3);
+        assert_eq!(ret[0].len(), 4);
+        assert_eq!(ret[0], ret[2]);
+        assert_ne!(ret[0], ret[1]);
+        assert_eq!(adapter.dimensions().await.unwrap(), 4);
+    }
+}
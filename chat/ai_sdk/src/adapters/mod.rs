@@ -1,5 +1,200 @@
+mod fallback;
+mod mock;
 mod ollama;
 mod openai;
+mod replay;
 
+pub use fallback::*;
+pub use mock::*;
 pub use ollama::*;
 pub use openai::*;
+pub use replay::*;
+
+use std::time::Duration;
+
+use async_stream::try_stream;
+use futures::{Stream, StreamExt};
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    RequestBuilder, Response,
+};
+
+use crate::{AiError, RetryPolicy};
+
+/// Send the request built by `request`, retrying according to `retry`. Error statuses are
+/// turned into the matching [`AiError`].
+pub(crate) async fn send<F>(retry: &RetryPolicy, request: F) -> Result<Response, AiError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    retry
+        .run(|| {
+            let request = request();
+            async move { check_response(request.send().await?).await }
+        })
+        .await
+}
+
+pub(crate) async fn check_response(response: Response) -> Result<Response, AiError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = retry_after(response.headers());
+    let body = response.text().await.unwrap_or_default();
+    Err(AiError::from_response(status.as_u16(), retry_after, &body))
+}
+
+// openai also sends `retry-after-ms`, which is more precise than `retry-after` in seconds. A
+// value which is not a duration, e.g. `inf` or `1e300`, is ignored as if it wasn't sent, and
+// the retry policy never waits longer than its max delay anyway.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+    let secs = |secs: f64| Duration::try_from_secs_f64(secs.max(0.0)).ok();
+    if let Some(ms) = value("retry-after-ms") {
+        return secs(ms / 1000.0);
+    }
+    value(RETRY_AFTER.as_str()).and_then(secs)
+}
+
+/// Split a chunked http body into lines. Both Ollama (NDJSON) and OpenAI (SSE) stream one
+/// event per line, but a network chunk may contain several lines or only part of one.
+pub(crate) fn lines<S, B>(stream: S) -> impl Stream<Item = Result<String, AiError>>
+where
+    S: Stream<Item = reqwest::Result<B>>,
+    B: AsRef<[u8]>,
+{
+    try_stream! {
+        let mut buf: Vec<u8> = Vec::new();
+        futures::pin_mut!(stream);
+        while let Some(chunk) = stream.next().await {
+            buf.extend_from_slice(chunk?.as_ref());
+            while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=pos).collect();
+                let line = String::from_utf8(line).map_err(malformed)?;
+                let line = line.trim();
+                if !line.is_empty() {
+                    yield line.to_string();
+                }
+            }
+        }
+        // the last line may not end with a newline
+        let line = String::from_utf8(buf).map_err(malformed)?;
+        let line = line.trim();
+        if !line.is_empty() {
+            yield line.to_string();
+        }
+    }
+}
+
+fn malformed(e: impl std::fmt::Display) -> AiError {
+    AiError::MalformedResponse(e.to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{
+        body::Body,
+        http::{header, StatusCode},
+        response::IntoResponse,
+        routing::any,
+        Router,
+    };
+    use tokio::net::TcpListener;
+
+    /// Start a local http server which replays `bodies` on `path`, one per request in order
+    /// (the last one is repeated). Bodies are split into small chunks so that the client has
+    /// to reassemble lines by itself. Returns the server host.
+    pub(crate) async fn mock_server(
+        path: &'static str,
+        content_type: &'static str,
+        bodies: &[&'static str],
+    ) -> String {
+        let responses: Vec<_> = bodies.iter().map(|body| (200, *body)).collect();
+        mock_server_with_status(path, content_type, &responses).await
+    }
+
+    /// Same as [`mock_server`], but every response has its own status code
+    pub(crate) async fn mock_server_with_status(
+        path: &'static str,
+        content_type: &'static str,
+        responses: &[(u16, &'static str)],
+    ) -> String {
+        let responses = responses.to_vec();
+        let count = Arc::new(AtomicUsize::new(0));
+        let handler = move || {
+            let n = count.fetch_add(1, Ordering::SeqCst);
+            let (status, body) = responses[n.min(responses.len() - 1)];
+            async move {
+                let chunks = body
+                    .as_bytes()
+                    .chunks(7)
+                    .map(|c| Ok::<_, Infallible>(c.to_vec()));
+                (
+                    StatusCode::from_u16(status).unwrap(),
+                    [(header::CONTENT_TYPE, content_type)],
+                    Body::from_stream(futures::stream::iter(chunks)),
+                )
+                    .into_response()
+            }
+        };
+        let app = Router::new().route(path, any(handler));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        format!("http://{}", addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lines_should_reassemble_chunks() {
+        let chunks = vec![
+            Ok::<_, reqwest::Error>("hel".as_bytes()),
+            Ok("lo\nwor".as_bytes()),
+            Ok("ld\n\nlast".as_bytes()),
+        ];
+        let ret: Vec<String> = lines(futures::stream::iter(chunks))
+            .map(|l| l.unwrap())
+            .collect()
+            .await;
+        assert_eq!(ret, vec!["hello", "world", "last"]);
+    }
+
+    #[test]
+    fn retry_after_should_parse_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, "2".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(2)));
+        headers.insert("retry-after-ms", "150".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(150)));
+    }
+
+    #[test]
+    fn retry_after_should_ignore_invalid_durations() {
+        for value in ["inf", "1e300", "1e30"] {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, value.parse().unwrap());
+            assert_eq!(retry_after(&headers), None, "retry-after: {}", value);
+            let mut headers = HeaderMap::new();
+            headers.insert("retry-after-ms", value.parse().unwrap());
+            assert_eq!(retry_after(&headers), None, "retry-after-ms: {}", value);
+        }
+    }
+}
@@ -1,11 +1,23 @@
-use reqwest::Client;
+use std::{borrow::Cow, time::Instant};
+
+use futures::StreamExt;
+use reqwest::{Client, Response};
 use serde::{Deserialize, Serialize};
 
-use crate::{AiAdapter, AiService, Message};
+use crate::{
+    adapters::{lines, send},
+    image, AiAdapter, AiError, AiService, CompletionOptions, CompletionResult, CompletionStream,
+    EmbeddingService, Image, Message, ModelService, ResponseFormat, RetryPolicy, Role, Tool,
+    ToolCall, Usage,
+};
+
+const DEFAULT_EMBED_BATCH_SIZE: usize = 32;
 
 pub struct OllamaAdapter {
     host: String,
     model: String,
+    embed_batch_size: usize,
+    retry: RetryPolicy,
     client: Client,
 }
 
@@ -13,13 +25,63 @@ pub struct OllamaAdapter {
 pub struct OllamaChatCompletionRequest {
     pub model: String,
     pub messages: Vec<OllamaMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<OllamaTool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<OllamaOptions>,
     pub stream: bool,
 }
 
+/// Model parameters, ollama sends them in `options` instead of top level fields
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OllamaMessage {
     pub role: String,
     pub content: String,
+    /// base64 encoded images
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaTool {
+    pub r#type: String,
+    pub function: OllamaFunction,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaFunction {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaFunctionCall {
+    pub name: String,
+    pub arguments: serde_json::Value,
 }
 
 #[derive(Debug, Deserialize)]
@@ -28,19 +90,57 @@ pub struct OllamaChatCompletionResponse {
     pub created_at: String,
     pub message: OllamaMessage,
     pub done: bool,
+    #[serde(default)]
+    pub done_reason: Option<String>,
     pub total_duration: u64,
     pub load_duration: u64,
+    // omitted when the prompt is served from the cache
+    #[serde(default)]
     pub prompt_eval_count: u32,
+    #[serde(default)]
     pub prompt_eval_duration: u64,
     pub eval_count: u32,
     pub eval_duration: u64,
 }
 
+#[derive(Debug, Serialize)]
+pub struct OllamaEmbedRequest {
+    pub model: String,
+    pub input: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaEmbedResponse {
+    pub model: String,
+    pub embeddings: Vec<Vec<f32>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaTagsResponse {
+    pub models: Vec<OllamaModel>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaModel {
+    /// name with its tag, e.g. `llama3.2:latest`
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaChatCompletionChunk {
+    pub model: String,
+    pub created_at: String,
+    pub message: Option<OllamaMessage>,
+    pub done: bool,
+}
+
 impl OllamaAdapter {
     pub fn new(host: impl Into<String>, model: impl Into<String>) -> Self {
         Self {
             host: host.into(),
             model: model.into(),
+            embed_batch_size: DEFAULT_EMBED_BATCH_SIZE,
+            retry: RetryPolicy::default(),
             client: Client::new(),
         }
     }
@@ -49,9 +149,86 @@ impl OllamaAdapter {
         Self {
             host: "http://localhost:11434".into(),
             model: model.into(),
+            embed_batch_size: DEFAULT_EMBED_BATCH_SIZE,
+            retry: RetryPolicy::default(),
             client: Client::new(),
         }
     }
+
+    /// Max number of inputs sent in one embedding request
+    pub fn with_embed_batch_size(mut self, size: usize) -> Self {
+        self.embed_batch_size = size.max(1);
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn request(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &CompletionOptions,
+        stream: bool,
+    ) -> OllamaChatCompletionRequest {
+        let format = match options.response_format {
+            ResponseFormat::Text => None,
+            ResponseFormat::Json => Some("json".to_string()),
+        };
+        let options = OllamaOptions::from(options);
+        OllamaChatCompletionRequest {
+            model: self.model.clone(),
+            messages: messages.iter().map(|m| m.into()).collect(),
+            tools: tools.iter().map(|t| t.into()).collect(),
+            format,
+            options: (options != OllamaOptions::default()).then_some(options),
+            stream,
+        }
+    }
+
+    /// Ollama only takes inline images, so the images given by a data url are decoded first
+    fn inline_images<'a>(&self, messages: &'a [Message]) -> Result<Cow<'a, [Message]>, AiError> {
+        let has_url = |m: &Message| m.images.iter().any(|i| matches!(i, Image::Url { .. }));
+        if !messages.iter().any(has_url) {
+            return Ok(Cow::Borrowed(messages));
+        }
+
+        let mut messages = messages.to_vec();
+        for image in messages.iter_mut().flat_map(|m| m.images.iter_mut()) {
+            if let Image::Url { url } = image {
+                *image = image::inline(url)?;
+            }
+        }
+        Ok(Cow::Owned(messages))
+    }
+
+    async fn post(&self, path: &str, body: &impl Serialize) -> Result<Response, AiError> {
+        let url = format!("{}{}", self.host, path);
+        send(&self.retry, || self.client.post(&url).json(body)).await
+    }
+
+    async fn get(&self, path: &str) -> Result<Response, AiError> {
+        let url = format!("{}{}", self.host, path);
+        send(&self.retry, || self.client.get(&url)).await
+    }
+
+    async fn send(
+        &self,
+        request: &OllamaChatCompletionRequest,
+    ) -> Result<OllamaChatCompletionResponse, AiError> {
+        let response = self.post("/api/chat", request).await?;
+        Ok(response.json().await?)
+    }
 }
 
 impl Default for OllamaAdapter {
@@ -61,34 +238,176 @@ impl Default for OllamaAdapter {
 }
 
 impl AiService for OllamaAdapter {
-    async fn complete(&self, messages: &[Message]) -> anyhow::Result<String> {
-        let request = OllamaChatCompletionRequest {
-            model: self.model.clone(),
-            messages: messages.iter().map(|m| m.into()).collect(),
-            stream: false,
-        };
-        let url = format!("{}/api/chat", self.host);
-        let response = self.client.post(url).json(&request).send().await?;
-        let data: OllamaChatCompletionResponse = response.json().await?;
-        let content = data.message.content;
-        Ok(content)
+    async fn complete(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        let start = Instant::now();
+        let messages = self.inline_images(messages)?;
+        let request = self.request(&messages, &[], options, false);
+        let data = self.send(&request).await?;
+        Ok(data.into_result(start))
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionStream, AiError> {
+        let messages = self.inline_images(messages)?;
+        let request = self.request(&messages, &[], options, true);
+        let response = self.post("/api/chat", &request).await?;
+        // ollama streams one json object per line, the last one has `done: true` and the stats
+        let stream = lines(response.bytes_stream())
+            .map(|line| {
+                let chunk: OllamaChatCompletionChunk = serde_json::from_str(&line?)?;
+                Ok(chunk)
+            })
+            .filter_map(|chunk| async move {
+                match chunk {
+                    Ok(chunk) => chunk
+                        .message
+                        .map(|m| m.content)
+                        .filter(|c| !c.is_empty())
+                        .map(Ok),
+                    Err(e) => Some(Err(e)),
+                }
+            });
+        Ok(stream.boxed())
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        let start = Instant::now();
+        let messages = self.inline_images(messages)?;
+        let request = self.request(&messages, tools, options, false);
+        let data = self.send(&request).await?;
+        Ok(data.into_result(start))
+    }
+}
+
+impl EmbeddingService for OllamaAdapter {
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, AiError> {
+        let mut embeddings = Vec::with_capacity(input.len());
+        for batch in input.chunks(self.embed_batch_size) {
+            let request = OllamaEmbedRequest {
+                model: self.model.clone(),
+                input: batch.to_vec(),
+            };
+            let response = self.post("/api/embed", &request).await?;
+            let data: OllamaEmbedResponse = response.json().await?;
+            embeddings.extend(data.embeddings);
+        }
+        Ok(embeddings)
+    }
+}
+
+impl ModelService for OllamaAdapter {
+    async fn list_models(&self) -> Result<Vec<String>, AiError> {
+        let data: OllamaTagsResponse = self.get("/api/tags").await?.json().await?;
+        let mut models: Vec<_> = data.models.into_iter().map(|m| m.name).collect();
+        models.sort();
+        Ok(models)
     }
 }
 
 impl From<Message> for OllamaMessage {
     fn from(m: Message) -> Self {
-        OllamaMessage {
-            role: m.role.to_string(),
-            content: m.content,
-        }
+        (&m).into()
     }
 }
 
 impl From<&Message> for OllamaMessage {
     fn from(m: &Message) -> Self {
+        // ollama has no tool call id, tool results are matched by order
         OllamaMessage {
             role: m.role.to_string(),
             content: m.content.clone(),
+            images: m
+                .images
+                .iter()
+                .filter_map(|i| match i {
+                    Image::Data { data, .. } => Some(image::encode(data)),
+                    // decoded by `inline_images` before
+                    Image::Url { .. } => None,
+                })
+                .collect(),
+            tool_calls: m
+                .tool_calls
+                .iter()
+                .map(|c| OllamaToolCall {
+                    function: OllamaFunctionCall {
+                        name: c.name.clone(),
+                        arguments: c.arguments.clone(),
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+impl OllamaChatCompletionResponse {
+    fn into_result(self, start: Instant) -> CompletionResult {
+        let message: Message = self.message.into();
+        CompletionResult {
+            content: message.content,
+            tool_calls: message.tool_calls,
+            usage: Usage::new(self.prompt_eval_count, self.eval_count),
+            model: self.model,
+            finish_reason: self.done_reason,
+            latency: start.elapsed(),
+        }
+    }
+}
+
+impl From<OllamaMessage> for Message {
+    fn from(m: OllamaMessage) -> Self {
+        let tool_calls = m
+            .tool_calls
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| ToolCall {
+                id: format!("call_{}", i),
+                name: c.function.name,
+                arguments: c.function.arguments,
+            })
+            .collect();
+        Message {
+            role: Role::Assistant,
+            content: m.content,
+            images: vec![],
+            tool_calls,
+            tool_call_id: None,
+        }
+    }
+}
+
+impl From<&CompletionOptions> for OllamaOptions {
+    fn from(o: &CompletionOptions) -> Self {
+        OllamaOptions {
+            temperature: o.temperature,
+            top_p: o.top_p,
+            num_predict: o.max_tokens,
+            stop: o.stop.clone(),
+            seed: o.seed,
+        }
+    }
+}
+
+impl From<&Tool> for OllamaTool {
+    fn from(t: &Tool) -> Self {
+        OllamaTool {
+            r#type: "function".to_string(),
+            function: OllamaFunction {
+                name: t.name.clone(),
+                description: t.description.clone(),
+                parameters: t.parameters.clone(),
+            },
         }
     }
 }
@@ -102,17 +421,212 @@ impl From<OllamaAdapter> for AiAdapter {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Role;
+    use serde_json::json;
+
+    use crate::adapters::test_utils::{mock_server, mock_server_with_status};
 
     #[ignore]
     #[tokio::test]
     async fn ollama_complete_should_work() {
         let adapter = OllamaAdapter::new_local("llama3.2");
-        let messages = vec![Message {
-            role: Role::User,
-            content: "Hello".to_string(),
-        }];
-        let content = adapter.complete(&messages).await.unwrap();
-        println!("content: {}", content);
+        let messages = vec![Message::user("Hello")];
+        let ret = adapter
+            .complete(&messages, &CompletionOptions::default())
+            .await
+            .unwrap();
+        assert!(!ret.content.is_empty());
+        assert!(ret.usage.prompt_tokens > 0);
+        assert!(ret.usage.completion_tokens > 0);
+    }
+
+    #[tokio::test]
+    async fn ollama_complete_should_return_usage() {
+        let host = mock_server(
+            "/api/chat",
+            "application/json",
+            &[include_str!("../../fixtures/ollama_tool_call.json")],
+        )
+        .await;
+        let adapter = OllamaAdapter::new(host, "llama3.2");
+        let messages = vec![Message::user("Hello")];
+        let ret = adapter
+            .complete(&messages, &CompletionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(ret.usage, Usage::new(122, 33));
+        assert_eq!(ret.usage.total_tokens, 155);
+        assert_eq!(ret.model, "llama3.2");
+        assert_eq!(ret.finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn ollama_complete_stream_should_work() {
+        let host = mock_server(
+            "/api/chat",
+            "application/x-ndjson",
+            &[include_str!("../../fixtures/ollama_stream.ndjson")],
+        )
+        .await;
+        let adapter = OllamaAdapter::new(host, "llama3.2");
+        let messages = vec![Message::user("Hello")];
+        let stream = adapter
+            .complete_stream(&messages, &CompletionOptions::default())
+            .await
+            .unwrap();
+        let deltas: Vec<String> = stream.map(|d| d.unwrap()).collect().await;
+        assert_eq!(deltas, vec!["Hello", "!", " How can I help", " you today?"]);
+    }
+
+    #[tokio::test]
+    async fn ollama_complete_with_tools_should_work() {
+        let host = mock_server(
+            "/api/chat",
+            "application/json",
+            &[include_str!("../../fixtures/ollama_tool_call.json")],
+        )
+        .await;
+        let adapter = OllamaAdapter::new(host, "llama3.2");
+        let messages = vec![Message::user("What's the weather in Paris?")];
+        let tool = Tool::new("get_weather", "Get the weather of a city", json!({}));
+        let reply = adapter
+            .complete_with_tools(&messages, &[tool], &CompletionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(reply.usage, Usage::new(122, 33));
+        assert_eq!(
+            reply.tool_calls,
+            vec![ToolCall {
+                id: "call_0".to_string(),
+                name: "get_weather".to_string(),
+                arguments: json!({ "city": "Paris" }),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn ollama_embed_should_work() {
+        let host = mock_server(
+            "/api/embed",
+            "application/json",
+            &[
+                r#"{"model":"mxbai-embed-large","embeddings":[[0.1,0.2,0.3],[0.4,0.5,0.6]]}"#,
+                r#"{"model":"mxbai-embed-large","embeddings":[[0.7,0.8,0.9]]}"#,
+            ],
+        )
+        .await;
+        let adapter = OllamaAdapter::new(host, "mxbai-embed-large").with_embed_batch_size(2);
+        let input = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let ret = adapter.embed(&input).await.unwrap();
+        assert_eq!(
+            ret,
+            vec![
+                vec![0.1, 0.2, 0.3],
+                vec![0.4, 0.5, 0.6],
+                vec![0.7, 0.8, 0.9]
+            ]
+        );
+        assert_eq!(adapter.dimensions().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn ollama_list_models_should_work() {
+        let host = mock_server(
+            "/api/tags",
+            "application/json",
+            &[
+                r#"{"models":[{"name":"qwen2.5:7b","model":"qwen2.5:7b","size":4683087332},{"name":"llama3.2:latest","model":"llama3.2:latest","size":2019393189}]}"#,
+            ],
+        )
+        .await;
+        let adapter = OllamaAdapter::new(host, "llama3.2");
+        let ret = adapter.list_models().await.unwrap();
+        assert_eq!(ret, vec!["llama3.2:latest", "qwen2.5:7b"]);
+    }
+
+    #[tokio::test]
+    async fn ollama_should_retry_and_classify_errors() {
+        let retry = RetryPolicy {
+            max_retries: 2,
+            base_delay_ms: 1,
+            max_delay_ms: 10,
+        };
+        let host = mock_server_with_status(
+            "/api/chat",
+            "application/json",
+            &[
+                (503, "server busy"),
+                (200, include_str!("../../fixtures/ollama_tool_call.json")),
+            ],
+        )
+        .await;
+        let adapter = OllamaAdapter::new(host, "llama3.2").with_retry(retry.clone());
+        let messages = vec![Message::user("What's the weather in Paris?")];
+        let reply = adapter
+            .complete_with_tools(&messages, &[], &CompletionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(reply.tool_calls.len(), 1);
+
+        let host = mock_server_with_status(
+            "/api/chat",
+            "application/json",
+            &[(
+                404,
+                r#"{"error":"model \"llama9\" not found, try pulling it first"}"#,
+            )],
+        )
+        .await;
+        let adapter = OllamaAdapter::new(host, "llama9").with_retry(retry);
+        let ret = adapter
+            .complete(&messages, &CompletionOptions::default())
+            .await;
+        assert!(matches!(ret, Err(AiError::BadRequest { status: 404, .. })));
+    }
+
+    #[test]
+    fn ollama_request_should_serialize_options() {
+        let adapter = OllamaAdapter::new_local("llama3.2");
+        let messages = vec![Message::user("Hello")];
+        let request = adapter.request(&messages, &[], &CompletionOptions::default(), false);
+        let value = serde_json::to_value(&request).unwrap();
+        assert!(value.get("options").is_none());
+        assert!(value.get("format").is_none());
+
+        let options = CompletionOptions::default()
+            .temperature(0.5)
+            .max_tokens(128)
+            .stop("\n")
+            .seed(42)
+            .json();
+        let request = adapter.request(&messages, &[], &options, false);
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(value["format"], "json");
+        assert_eq!(
+            value["options"],
+            json!({ "temperature": 0.5, "num_predict": 128, "stop": ["\n"], "seed": 42 })
+        );
+    }
+
+    #[tokio::test]
+    async fn ollama_request_should_inline_images() {
+        let adapter = OllamaAdapter::new_local("llava");
+        let messages = vec![Message::user("Describe the images").with_images([
+            Image::data("image/jpeg", b"jpg".to_vec()),
+            Image::url("data:image/png;base64,cG5n"),
+        ])];
+        let inlined = adapter.inline_images(&messages).unwrap();
+        assert_eq!(
+            inlined[0].images[1],
+            Image::data("image/png", b"png".to_vec())
+        );
+
+        let request = adapter.request(&inlined, &[], &CompletionOptions::default(), false);
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(value["messages"][0]["images"], json!(["anBn", "cG5n"]));
+
+        // ollama must not be used to fetch any url
+        let messages =
+            vec![Message::user("Describe").with_images([Image::url("http://localhost/cat.png")])];
+        assert!(adapter.inline_images(&messages).is_err());
     }
 }
@@ -1,41 +1,152 @@
-use anyhow::anyhow;
-use reqwest::Client;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Client, Proxy, Response,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::{AiAdapter, AiService, Message};
+use crate::{
+    adapters::{lines, send},
+    AiAdapter, AiError, AiService, CompletionOptions, CompletionResult, CompletionStream,
+    EmbeddingService, Message, ModelService, ResponseFormat, RetryPolicy, Role, Tool, ToolCall,
+    Usage,
+};
+
+const DEFAULT_EMBED_BATCH_SIZE: usize = 128;
+
+pub const OPENAI_DEFAULT_HOST: &str = "https://api.openai.com/v1";
 
 pub struct OpenAIAdapter {
     host: String,
     api_key: String,
     model: String,
+    embed_batch_size: usize,
+    retry: RetryPolicy,
     client: Client,
 }
 
+/// Build an [`OpenAIAdapter`] for any OpenAI compatible server, e.g. api.openai.com, Azure,
+/// vLLM or LM Studio
+#[derive(Debug, Clone)]
+pub struct OpenAIAdapterBuilder {
+    host: String,
+    api_key: String,
+    model: String,
+    organization: Option<String>,
+    headers: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    proxy: Option<String>,
+    embed_batch_size: usize,
+    retry: RetryPolicy,
+}
+
 #[derive(Debug, Serialize)]
 pub struct OpenAIChatCompletionRequest {
     pub model: String,
     pub messages: Vec<OpenAIMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<OpenAITool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<OpenAIResponseFormat>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenAIResponseFormat {
+    pub r#type: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OpenAIMessage {
     pub role: String,
-    pub content: String,
+    // null when the assistant only calls tools
+    pub content: Option<OpenAIContent>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OpenAIToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// Plain text, or a list of parts when the message has images
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OpenAIContent {
+    Text(String),
+    Parts(Vec<OpenAIContentPart>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OpenAIContentPart {
+    Text { text: String },
+    ImageUrl { image_url: OpenAIImageUrl },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenAIImageUrl {
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenAITool {
+    pub r#type: String,
+    pub function: OpenAIFunction,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenAIFunction {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenAIToolCall {
+    pub id: String,
+    pub r#type: String,
+    pub function: OpenAIFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenAIFunctionCall {
+    pub name: String,
+    // arguments are a json encoded string
+    pub arguments: String,
+}
+
+// compatible servers often leave out everything but the choices and the usage
 #[derive(Debug, Deserialize)]
 pub struct OpenAIChatCompletionResponse {
-    pub id: String,
-    pub object: String,
-    pub created: u64,
-    pub model: String,
-    pub system_fingerprint: String,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub object: Option<String>,
+    #[serde(default)]
+    pub created: Option<u64>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub system_fingerprint: Option<String>,
     pub choices: Vec<OpenAIChoice>,
     pub usage: OpenAIUsage,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct OpenAIChoice {
+    #[serde(default)]
     pub index: u32,
     pub message: OpenAIMessage,
     pub logprobs: Option<i64>,
@@ -55,57 +166,429 @@ pub struct OpenAICompletionTokensDetails {
     pub reasoning_tokens: u32,
 }
 
+#[derive(Debug, Serialize)]
+pub struct OpenAIEmbeddingRequest {
+    pub model: String,
+    pub input: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIEmbeddingResponse {
+    pub object: String,
+    pub model: String,
+    pub data: Vec<OpenAIEmbedding>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIEmbedding {
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIModelList {
+    pub data: Vec<OpenAIModel>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIModel {
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIChatCompletionChunk {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<OpenAIChunkChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIChunkChoice {
+    pub index: u32,
+    pub delta: OpenAIDelta,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAIDelta {
+    pub role: Option<String>,
+    pub content: Option<String>,
+}
+
 impl OpenAIAdapter {
     pub fn new(api_key: impl Into<String>, model: impl Into<String>) -> Self {
         Self {
-            // host: "https://api.openai.com/v1".to_string(),
-            host: "https://api.chatanywhere.tech".to_string(), // use for chinese
+            host: OPENAI_DEFAULT_HOST.to_string(),
             api_key: api_key.into(),
             model: model.into(),
+            embed_batch_size: DEFAULT_EMBED_BATCH_SIZE,
+            retry: RetryPolicy::default(),
             client: Client::new(),
         }
     }
-}
 
-impl AiService for OpenAIAdapter {
-    async fn complete(&self, messages: &[Message]) -> anyhow::Result<String> {
-        let request = OpenAIChatCompletionRequest {
+    pub fn builder(api_key: impl Into<String>, model: impl Into<String>) -> OpenAIAdapterBuilder {
+        OpenAIAdapterBuilder {
+            host: OPENAI_DEFAULT_HOST.to_string(),
+            api_key: api_key.into(),
+            model: model.into(),
+            organization: None,
+            headers: vec![],
+            timeout: None,
+            proxy: None,
+            embed_batch_size: DEFAULT_EMBED_BATCH_SIZE,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Max number of inputs sent in one embedding request
+    pub fn with_embed_batch_size(mut self, size: usize) -> Self {
+        self.embed_batch_size = size.max(1);
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn request(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &CompletionOptions,
+        stream: bool,
+    ) -> OpenAIChatCompletionRequest {
+        let response_format = match options.response_format {
+            ResponseFormat::Text => None,
+            ResponseFormat::Json => Some(OpenAIResponseFormat {
+                r#type: "json_object".to_string(),
+            }),
+        };
+        OpenAIChatCompletionRequest {
             model: self.model.clone(),
             messages: messages.iter().map(|m| m.into()).collect(),
-        };
-        let url = format!("{}/chat/completions", self.host);
-        let response = self
-            .client
-            .post(url)
-            .json(&request)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .send()
-            .await?;
-        let mut data: OpenAIChatCompletionResponse = response.json().await?;
-        let content = data
-            .choices
-            .pop()
-            .ok_or(anyhow!("No Response"))?
-            .message
-            .content;
-        Ok(content)
+            tools: tools.iter().map(|t| t.into()).collect(),
+            temperature: options.temperature,
+            top_p: options.top_p,
+            max_tokens: options.max_tokens,
+            stop: options.stop.clone(),
+            seed: options.seed,
+            response_format,
+            stream,
+        }
+    }
+
+    async fn post(&self, path: &str, body: &impl Serialize) -> Result<Response, AiError> {
+        let url = format!("{}{}", self.host, path);
+        send(&self.retry, || {
+            self.client
+                .post(&url)
+                .json(body)
+                .header("Authorization", format!("Bearer {}", self.api_key))
+        })
+        .await
+    }
+
+    async fn get(&self, path: &str) -> Result<Response, AiError> {
+        let url = format!("{}{}", self.host, path);
+        send(&self.retry, || {
+            self.client
+                .get(&url)
+                .header("Authorization", format!("Bearer {}", self.api_key))
+        })
+        .await
+    }
+
+    async fn send(
+        &self,
+        request: &OpenAIChatCompletionRequest,
+    ) -> Result<OpenAIChatCompletionResponse, AiError> {
+        let response = self.post("/chat/completions", request).await?;
+        Ok(response.json().await?)
+    }
+}
+
+impl OpenAIAdapterBuilder {
+    /// Base url of the api, requests are sent to `{host}/chat/completions`
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = host.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    /// Extra header sent with every request, e.g. `api-key` for Azure
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Max number of inputs sent in one embedding request
+    pub fn embed_batch_size(mut self, size: usize) -> Self {
+        self.embed_batch_size = size.max(1);
+        self
+    }
+
+    /// How failed requests are retried, see [`RetryPolicy`]
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<OpenAIAdapter> {
+        let mut headers = HeaderMap::new();
+        if let Some(organization) = &self.organization {
+            headers.insert("OpenAI-Organization", HeaderValue::from_str(organization)?);
+        }
+        for (name, value) in &self.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+
+        let mut builder = Client::builder().default_headers(headers);
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+
+        Ok(OpenAIAdapter {
+            host: self.host,
+            api_key: self.api_key,
+            model: self.model,
+            embed_batch_size: self.embed_batch_size,
+            retry: self.retry,
+            client: builder.build()?,
+        })
+    }
+}
+
+impl AiService for OpenAIAdapter {
+    async fn complete(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        let request = self.request(messages, &[], options, false);
+        let start = Instant::now();
+        let data = self.send(&request).await?;
+        data.into_result(&self.model, start)
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionStream, AiError> {
+        let request = self.request(messages, &[], options, true);
+        let response = self.post("/chat/completions", &request).await?;
+        // server-sent events: each chunk is a `data: {json}` line, the stream ends with `data: [DONE]`
+        let stream = lines(response.bytes_stream())
+            .take_while(|line| {
+                let done = matches!(line, Ok(line) if line == "data: [DONE]");
+                futures::future::ready(!done)
+            })
+            .filter_map(|line| async move {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => return Some(Err(e)),
+                };
+                let data = line.strip_prefix("data:")?.trim();
+                match serde_json::from_str::<OpenAIChatCompletionChunk>(data) {
+                    Ok(mut chunk) => chunk
+                        .choices
+                        .pop()
+                        .and_then(|c| c.delta.content)
+                        .filter(|c| !c.is_empty())
+                        .map(Ok),
+                    Err(e) => Some(Err(e.into())),
+                }
+            });
+        Ok(stream.boxed())
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        let request = self.request(messages, tools, options, false);
+        let start = Instant::now();
+        let data = self.send(&request).await?;
+        data.into_result(&self.model, start)
+    }
+}
+
+impl EmbeddingService for OpenAIAdapter {
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, AiError> {
+        let mut embeddings = Vec::with_capacity(input.len());
+        for batch in input.chunks(self.embed_batch_size) {
+            let request = OpenAIEmbeddingRequest {
+                model: self.model.clone(),
+                input: batch.to_vec(),
+            };
+            let response = self.post("/embeddings", &request).await?;
+            let mut data: OpenAIEmbeddingResponse = response.json().await?;
+            // data is not guaranteed to be in the input order
+            data.data.sort_by_key(|e| e.index);
+            embeddings.extend(data.data.into_iter().map(|e| e.embedding));
+        }
+        Ok(embeddings)
     }
 }
 
+impl ModelService for OpenAIAdapter {
+    async fn list_models(&self) -> Result<Vec<String>, AiError> {
+        let data: OpenAIModelList = self.get("/models").await?.json().await?;
+        let mut models: Vec<_> = data.data.into_iter().map(|m| m.id).collect();
+        models.sort();
+        Ok(models)
+    }
+}
+
+fn no_choices() -> AiError {
+    AiError::MalformedResponse("no choices in response".to_string())
+}
+
 impl From<Message> for OpenAIMessage {
     fn from(m: Message) -> Self {
-        OpenAIMessage {
-            role: m.role.to_string(),
-            content: m.content,
-        }
+        (&m).into()
     }
 }
 
 impl From<&Message> for OpenAIMessage {
     fn from(m: &Message) -> Self {
+        // an assistant message with only tool calls has no content
+        let content = if m.content.is_empty() && !m.tool_calls.is_empty() {
+            None
+        } else if m.images.is_empty() {
+            Some(OpenAIContent::Text(m.content.clone()))
+        } else {
+            // images given by bytes are sent as data urls
+            let text = OpenAIContentPart::Text {
+                text: m.content.clone(),
+            };
+            let images = m.images.iter().map(|i| OpenAIContentPart::ImageUrl {
+                image_url: OpenAIImageUrl { url: i.to_url() },
+            });
+            Some(OpenAIContent::Parts(
+                std::iter::once(text).chain(images).collect(),
+            ))
+        };
         OpenAIMessage {
             role: m.role.to_string(),
-            content: m.content.clone(),
+            content,
+            tool_calls: m.tool_calls.iter().map(|c| c.into()).collect(),
+            tool_call_id: m.tool_call_id.clone(),
+        }
+    }
+}
+
+impl From<OpenAIMessage> for Message {
+    fn from(m: OpenAIMessage) -> Self {
+        Message {
+            role: Role::Assistant,
+            content: m.content.map(OpenAIContent::into_text).unwrap_or_default(),
+            images: vec![],
+            tool_calls: m.tool_calls.into_iter().map(|c| c.into()).collect(),
+            tool_call_id: None,
+        }
+    }
+}
+
+impl OpenAIChatCompletionResponse {
+    /// `model` is the requested one, in case the server doesn't tell which one served it
+    fn into_result(mut self, model: &str, start: Instant) -> Result<CompletionResult, AiError> {
+        let choice = self.choices.pop().ok_or(no_choices())?;
+        let message: Message = choice.message.into();
+        Ok(CompletionResult {
+            content: message.content,
+            tool_calls: message.tool_calls,
+            usage: Usage {
+                prompt_tokens: self.usage.prompt_tokens,
+                completion_tokens: self.usage.completion_tokens,
+                total_tokens: self.usage.total_tokens,
+            },
+            model: self.model.unwrap_or_else(|| model.to_string()),
+            finish_reason: Some(choice.finish_reason),
+            latency: start.elapsed(),
+        })
+    }
+}
+
+impl OpenAIContent {
+    fn into_text(self) -> String {
+        match self {
+            Self::Text(text) => text,
+            Self::Parts(parts) => parts
+                .into_iter()
+                .filter_map(|p| match p {
+                    OpenAIContentPart::Text { text } => Some(text),
+                    OpenAIContentPart::ImageUrl { .. } => None,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&Tool> for OpenAITool {
+    fn from(t: &Tool) -> Self {
+        OpenAITool {
+            r#type: "function".to_string(),
+            function: OpenAIFunction {
+                name: t.name.clone(),
+                description: t.description.clone(),
+                parameters: t.parameters.clone(),
+            },
+        }
+    }
+}
+
+impl From<&ToolCall> for OpenAIToolCall {
+    fn from(c: &ToolCall) -> Self {
+        OpenAIToolCall {
+            id: c.id.clone(),
+            r#type: "function".to_string(),
+            function: OpenAIFunctionCall {
+                name: c.name.clone(),
+                arguments: c.arguments.to_string(),
+            },
+        }
+    }
+}
+
+impl From<OpenAIToolCall> for ToolCall {
+    fn from(c: OpenAIToolCall) -> Self {
+        // keep the raw string if the model produced invalid json, the tool handler decides
+        let arguments = serde_json::from_str(&c.function.arguments)
+            .unwrap_or(serde_json::Value::String(c.function.arguments));
+        ToolCall {
+            id: c.id,
+            name: c.function.name,
+            arguments,
         }
     }
 }
@@ -120,21 +603,323 @@ impl From<OpenAIAdapter> for AiAdapter {
 mod tests {
     use std::env;
 
-    use crate::Role;
+    use serde_json::json;
+
+    use crate::adapters::test_utils::{mock_server, mock_server_with_status};
 
     use super::*;
+    use crate::Image;
 
     #[ignore = "OPENAI rate limit"]
     #[tokio::test]
     async fn test_complete() {
         let api_key = env::var("OPENAI_API_KEY").unwrap();
         let adapter = OpenAIAdapter::new(api_key, "gpt-4o-mini");
-        let messages = vec![Message {
-            role: Role::User,
-            content: "Hello".to_string(),
-        }];
-        let response = adapter.complete(&messages).await.unwrap();
+        let messages = vec![Message::user("Hello")];
+        let response = adapter
+            .complete(&messages, &CompletionOptions::default())
+            .await
+            .unwrap();
         dbg!(&response);
-        assert!(!response.is_empty());
+        assert!(!response.content.is_empty());
+    }
+
+    #[tokio::test]
+    async fn openai_complete_should_return_usage() {
+        let host = mock_server(
+            "/chat/completions",
+            "application/json",
+            &[include_str!("../../fixtures/openai_tool_call.json")],
+        )
+        .await;
+        let adapter = OpenAIAdapter::builder("sk-test", "gpt-4o-mini")
+            .host(host)
+            .build()
+            .unwrap();
+        let messages = vec![Message::user("Hello")];
+        let ret = adapter
+            .complete(&messages, &CompletionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(ret.usage, Usage::new(62, 15));
+        assert_eq!(ret.model, "gpt-4o-mini-2024-07-18");
+        assert_eq!(ret.finish_reason.as_deref(), Some("tool_calls"));
+    }
+
+    #[tokio::test]
+    async fn openai_complete_should_accept_minimal_responses() {
+        let body = r#"{
+            "choices": [{
+                "message": { "role": "assistant", "content": "Hello!" },
+                "finish_reason": "stop"
+            }],
+            "usage": { "prompt_tokens": 8, "completion_tokens": 2, "total_tokens": 10 }
+        }"#;
+        let host = mock_server("/chat/completions", "application/json", &[body]).await;
+        let adapter = OpenAIAdapter::builder("sk-test", "qwen2.5")
+            .host(host)
+            .build()
+            .unwrap();
+        let messages = vec![Message::user("Hello")];
+        let ret = adapter
+            .complete(&messages, &CompletionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(ret.content, "Hello!");
+        assert_eq!(ret.usage, Usage::new(8, 2));
+        assert_eq!(ret.model, "qwen2.5");
+    }
+
+    #[tokio::test]
+    async fn openai_complete_stream_should_work() {
+        let host = mock_server(
+            "/chat/completions",
+            "text/event-stream",
+            &[include_str!("../../fixtures/openai_stream.txt")],
+        )
+        .await;
+        let adapter = OpenAIAdapter::builder("sk-test", "gpt-4o-mini")
+            .host(host)
+            .build()
+            .unwrap();
+        let messages = vec![Message::user("Hello")];
+        let stream = adapter
+            .complete_stream(&messages, &CompletionOptions::default())
+            .await
+            .unwrap();
+        let deltas: Vec<String> = stream.map(|d| d.unwrap()).collect().await;
+        assert_eq!(deltas, vec!["Hello", "!", " How can I help", " you today?"]);
+    }
+
+    #[tokio::test]
+    async fn openai_complete_with_tools_should_work() {
+        let host = mock_server(
+            "/chat/completions",
+            "application/json",
+            &[include_str!("../../fixtures/openai_tool_call.json")],
+        )
+        .await;
+        let adapter = OpenAIAdapter::builder("sk-test", "gpt-4o-mini")
+            .host(host)
+            .build()
+            .unwrap();
+        let messages = vec![Message::user("What's the weather in Paris?")];
+        let tool = Tool::new("get_weather", "Get the weather of a city", json!({}));
+        let reply = adapter
+            .complete_with_tools(&messages, &[tool], &CompletionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(reply.usage, Usage::new(62, 15));
+        assert_eq!(reply.content, "");
+        assert_eq!(
+            reply.tool_calls,
+            vec![ToolCall {
+                id: "call_Fj2d1vBHt3MkUqYqJcW4eG1A".to_string(),
+                name: "get_weather".to_string(),
+                arguments: json!({ "city": "Paris" }),
+            }]
+        );
+    }
+
+    #[test]
+    fn openai_tool_messages_should_serialize() {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "get_weather".to_string(),
+            arguments: json!({ "city": "Paris" }),
+        };
+        let messages = [
+            Message::tool_calls(vec![call]),
+            Message::tool("call_1", "sunny"),
+        ];
+        let messages: Vec<OpenAIMessage> = messages.iter().map(|m| m.into()).collect();
+        assert_eq!(
+            serde_json::to_value(&messages).unwrap(),
+            json!([
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+                    }]
+                },
+                { "role": "tool", "content": "sunny", "tool_call_id": "call_1" }
+            ])
+        );
+    }
+
+    #[test]
+    fn openai_image_messages_should_serialize() {
+        let messages = [
+            Message::user("Describe the images").with_images([
+                Image::url("https://example.com/cat.png"),
+                Image::data("image/png", b"png".to_vec()),
+            ]),
+            Message::user("Hello"),
+        ];
+        let messages: Vec<OpenAIMessage> = messages.iter().map(|m| m.into()).collect();
+        assert_eq!(
+            serde_json::to_value(&messages).unwrap(),
+            json!([
+                {
+                    "role": "user",
+                    "content": [
+                        { "type": "text", "text": "Describe the images" },
+                        { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } },
+                        { "type": "image_url", "image_url": { "url": "data:image/png;base64,cG5n" } }
+                    ]
+                },
+                { "role": "user", "content": "Hello" }
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn openai_embed_should_work() {
+        let host = mock_server(
+            "/embeddings",
+            "application/json",
+            &[
+                r#"{"object":"list","model":"text-embedding-3-small","data":[{"object":"embedding","index":1,"embedding":[0.4,0.5]},{"object":"embedding","index":0,"embedding":[0.1,0.2]}],"usage":{"prompt_tokens":2,"total_tokens":2}}"#,
+                r#"{"object":"list","model":"text-embedding-3-small","data":[{"object":"embedding","index":0,"embedding":[0.7,0.8]}],"usage":{"prompt_tokens":1,"total_tokens":1}}"#,
+            ],
+        )
+        .await;
+        let adapter = OpenAIAdapter::builder("sk-test", "text-embedding-3-small")
+            .host(host)
+            .embed_batch_size(2)
+            .build()
+            .unwrap();
+        let input = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let ret = adapter.embed(&input).await.unwrap();
+        assert_eq!(ret, vec![vec![0.1, 0.2], vec![0.4, 0.5], vec![0.7, 0.8]]);
+        assert_eq!(adapter.dimensions().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn openai_list_models_should_work() {
+        let host = mock_server(
+            "/models",
+            "application/json",
+            &[
+                r#"{"object":"list","data":[{"id":"gpt-4o-mini","object":"model","created":1721172741,"owned_by":"system"},{"id":"gpt-4o","object":"model","created":1715367049,"owned_by":"system"}]}"#,
+            ],
+        )
+        .await;
+        let adapter = OpenAIAdapter::builder("sk-test", "gpt-4o-mini")
+            .host(host)
+            .build()
+            .unwrap();
+        let ret = adapter.list_models().await.unwrap();
+        assert_eq!(ret, vec!["gpt-4o", "gpt-4o-mini"]);
+    }
+
+    #[tokio::test]
+    async fn openai_builder_should_set_headers() {
+        use axum::{http::HeaderMap, routing::post, Router};
+        use tokio::net::TcpListener;
+
+        async fn handler(headers: HeaderMap) -> &'static str {
+            assert_eq!(headers["authorization"], "Bearer sk-test");
+            assert_eq!(headers["openai-organization"], "org-acme");
+            assert_eq!(headers["api-key"], "azure-key");
+            include_str!("../../fixtures/openai_tool_call.json")
+        }
+        let app = Router::new().route("/v1/chat/completions", post(handler));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let adapter = OpenAIAdapter::builder("sk-test", "gpt-4o-mini")
+            .host(format!("http://{}/v1/", addr))
+            .organization("org-acme")
+            .header("api-key", "azure-key")
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        let messages = vec![Message::user("Hello")];
+        let reply = adapter
+            .complete_with_tools(&messages, &[], &CompletionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(reply.tool_calls.len(), 1);
+    }
+
+    #[test]
+    fn openai_builder_should_reject_invalid_proxy() {
+        let ret = OpenAIAdapter::builder("sk-test", "gpt-4o-mini")
+            .proxy("not a url")
+            .build();
+        assert!(ret.is_err());
+    }
+
+    #[tokio::test]
+    async fn openai_should_retry_and_classify_errors() {
+        let retry = RetryPolicy {
+            max_retries: 1,
+            base_delay_ms: 1,
+            max_delay_ms: 10,
+        };
+        let host = mock_server_with_status(
+            "/chat/completions",
+            "application/json",
+            &[
+                (429, r#"{"error":{"message":"Rate limit reached"}}"#),
+                (200, include_str!("../../fixtures/openai_tool_call.json")),
+            ],
+        )
+        .await;
+        let adapter = OpenAIAdapter::builder("sk-test", "gpt-4o-mini")
+            .host(host)
+            .retry(retry.clone())
+            .build()
+            .unwrap();
+        let messages = vec![Message::user("What's the weather in Paris?")];
+        let reply = adapter
+            .complete_with_tools(&messages, &[], &CompletionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(reply.tool_calls.len(), 1);
+
+        let host = mock_server_with_status(
+            "/chat/completions",
+            "application/json",
+            &[
+                (401, r#"{"error":{"message":"Incorrect API key provided"}}"#),
+                (200, include_str!("../../fixtures/openai_tool_call.json")),
+            ],
+        )
+        .await;
+        let adapter = OpenAIAdapter::builder("sk-wrong", "gpt-4o-mini")
+            .host(host)
+            .retry(retry)
+            .build()
+            .unwrap();
+        let ret = adapter
+            .complete(&messages, &CompletionOptions::default())
+            .await;
+        assert!(matches!(ret, Err(AiError::AuthFailed(_))));
+    }
+
+    #[test]
+    fn openai_request_should_serialize_options() {
+        let adapter = OpenAIAdapter::new("sk-test", "gpt-4o-mini");
+        let messages = vec![Message::user("Hello")];
+        let options = CompletionOptions::default()
+            .top_p(0.5)
+            .max_tokens(128)
+            .stop("\n")
+            .seed(42)
+            .json();
+        let request = adapter.request(&messages, &[], &options, false);
+        let value = serde_json::to_value(&request).unwrap();
+        assert!(value.get("temperature").is_none());
+        assert_eq!(value["top_p"], 0.5);
+        assert_eq!(value["max_tokens"], 128);
+        assert_eq!(value["stop"], json!(["\n"]));
+        assert_eq!(value["seed"], 42);
+        assert_eq!(value["response_format"], json!({ "type": "json_object" }));
     }
 }
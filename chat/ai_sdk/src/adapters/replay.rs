@@ -0,0 +1,327 @@
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tokio::fs;
+use tracing::info;
+
+use crate::{
+    AiAdapter, AiError, AiService, CompletionOptions, CompletionResult, CompletionStream,
+    EmbeddingService, Message, ModelService, Tool,
+};
+
+/// Saves every request and its response of the wrapped adapter to a fixture file, and replays
+/// them offline. The file name is derived from the request, so the same request always gets
+/// the same response.
+pub struct ReplayAdapter {
+    inner: Box<AiAdapter>,
+    dir: PathBuf,
+    mode: ReplayMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayMode {
+    /// call the wrapped adapter and save the responses
+    Record,
+    /// only read the saved responses, a request without one fails
+    Replay,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Request<'a> {
+    Complete {
+        model: &'a str,
+        messages: &'a [Message],
+        options: &'a CompletionOptions,
+    },
+    Stream {
+        model: &'a str,
+        messages: &'a [Message],
+        options: &'a CompletionOptions,
+    },
+    Tools {
+        model: &'a str,
+        messages: &'a [Message],
+        tools: &'a [Tool],
+        options: &'a CompletionOptions,
+    },
+    Embed {
+        model: &'a str,
+        input: &'a [String],
+    },
+    Models {
+        provider: &'a str,
+        host: Option<&'a str>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Completion(CompletionResult),
+    Stream(Vec<String>),
+    Embeddings(Vec<Vec<f32>>),
+    Models(Vec<String>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Recording {
+    request: serde_json::Value,
+    response: Response,
+}
+
+impl ReplayAdapter {
+    pub fn new(inner: impl Into<AiAdapter>, dir: impl Into<PathBuf>, mode: ReplayMode) -> Self {
+        Self {
+            inner: Box::new(inner.into()),
+            dir: dir.into(),
+            mode,
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    /// The adapter the requests are recorded from
+    pub fn inner(&self) -> &AiAdapter {
+        &self.inner
+    }
+
+    async fn run<F, Fut>(&self, request: Request<'_>, call: F) -> Result<Response, AiError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Response, AiError>>,
+    {
+        let request = serde_json::to_value(&request)?;
+        let path = self.path(&request);
+        match self.mode {
+            ReplayMode::Replay => {
+                let data = fs::read(&path)
+                    .await
+                    .map_err(|e| anyhow!("no recorded response at {}: {}", path.display(), e))?;
+                let recording: Recording = serde_json::from_slice(&data)?;
+                Ok(recording.response)
+            }
+            ReplayMode::Record => {
+                let response = call().await?;
+                let recording = Recording { request, response };
+                save(&path, &recording)
+                    .await
+                    .map_err(|e| anyhow!("failed to save {}: {}", path.display(), e))?;
+                info!("recorded response to {}", path.display());
+                Ok(recording.response)
+            }
+        }
+    }
+
+    fn path(&self, request: &serde_json::Value) -> PathBuf {
+        let kind = request["kind"].as_str().unwrap_or("request");
+        let hash = hex::encode(Sha1::digest(request.to_string().as_bytes()));
+        self.dir.join(format!("{}-{}.json", kind, &hash[..16]))
+    }
+}
+
+impl AiService for ReplayAdapter {
+    async fn complete(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        let request = Request::Complete {
+            model: self.model(),
+            messages,
+            options,
+        };
+        let response = self
+            .run(request, || async {
+                let ret = Box::pin(self.inner.complete(messages, options)).await?;
+                Ok(Response::Completion(ret))
+            })
+            .await?;
+        match response {
+            Response::Completion(ret) => Ok(ret),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionStream, AiError> {
+        let request = Request::Stream {
+            model: self.model(),
+            messages,
+            options,
+        };
+        // the deltas are collected when recording, and replayed in one go
+        let response = self
+            .run(request, || async {
+                let stream = Box::pin(self.inner.complete_stream(messages, options)).await?;
+                let deltas: Vec<_> = stream.collect().await;
+                Ok(Response::Stream(
+                    deltas.into_iter().collect::<Result<_, _>>()?,
+                ))
+            })
+            .await?;
+        match response {
+            Response::Stream(deltas) => {
+                Ok(futures::stream::iter(deltas.into_iter().map(Ok)).boxed())
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        let request = Request::Tools {
+            model: self.model(),
+            messages,
+            tools,
+            options,
+        };
+        let response = self
+            .run(request, || async {
+                let ret =
+                    Box::pin(self.inner.complete_with_tools(messages, tools, options)).await?;
+                Ok(Response::Completion(ret))
+            })
+            .await?;
+        match response {
+            Response::Completion(ret) => Ok(ret),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+impl EmbeddingService for ReplayAdapter {
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, AiError> {
+        let request = Request::Embed {
+            model: self.model(),
+            input,
+        };
+        let response = self
+            .run(request, || async {
+                let ret = Box::pin(self.inner.embed(input)).await?;
+                Ok(Response::Embeddings(ret))
+            })
+            .await?;
+        match response {
+            Response::Embeddings(ret) => Ok(ret),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+impl ModelService for ReplayAdapter {
+    async fn list_models(&self) -> Result<Vec<String>, AiError> {
+        let request = Request::Models {
+            provider: self.inner.provider(),
+            host: self.inner.host(),
+        };
+        let response = self
+            .run(request, || async {
+                let ret = Box::pin(self.inner.list_models()).await?;
+                Ok(Response::Models(ret))
+            })
+            .await?;
+        match response {
+            Response::Models(ret) => Ok(ret),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+async fn save(path: &Path, recording: &Recording) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    fs::write(path, serde_json::to_vec_pretty(recording)?).await?;
+    Ok(())
+}
+
+fn unexpected(response: Response) -> AiError {
+    AiError::MalformedResponse(format!("unexpected recorded response: {:?}", response))
+}
+
+impl From<ReplayAdapter> for AiAdapter {
+    fn from(adapter: ReplayAdapter) -> Self {
+        AiAdapter::Replay(adapter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockAdapter, OllamaAdapter, OpenAIAdapter, Usage};
+
+    #[tokio::test]
+    async fn replay_adapter_should_replay_recorded_responses() {
+        let dir = std::env::temp_dir().join(format!("ai_sdk_replay_{}", std::process::id()));
+        let options = CompletionOptions::default().temperature(0.5);
+        let messages = vec![Message::user("Hello")];
+
+        let mock = MockAdapter::new("llama3.2").reply("Hi!").reply("Hey");
+        let adapter = ReplayAdapter::new(mock, &dir, ReplayMode::Record);
+        let recorded = adapter.complete(&messages, &options).await.unwrap();
+        assert_eq!(recorded.content, "Hi!");
+        let input = vec!["hello".to_string()];
+        let embeddings = adapter.embed(&input).await.unwrap();
+
+        // the recorded adapter would reply "Hey" now, replay always returns the recording
+        let mock = MockAdapter::new("llama3.2").reply("Hey");
+        let adapter = ReplayAdapter::new(mock, &dir, ReplayMode::Replay);
+        let ret = adapter.complete(&messages, &options).await.unwrap();
+        assert_eq!(ret.content, "Hi!");
+        assert_eq!(ret.usage, Usage::new(1, 1));
+        assert_eq!(adapter.embed(&input).await.unwrap(), embeddings);
+
+        // a different request has no recording
+        let ret = adapter
+            .complete(&messages, &CompletionOptions::default())
+            .await;
+        assert!(ret.is_err());
+
+        // neither has the same request for another model
+        let adapter = ReplayAdapter::new(MockAdapter::new("qwen2.5"), &dir, ReplayMode::Replay);
+        assert!(adapter.complete(&messages, &options).await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_adapter_should_keep_model_listings_of_providers_apart() {
+        let dir = std::env::temp_dir().join(format!("ai_sdk_models_{}", std::process::id()));
+        let mock = MockAdapter::new("llama3.2").with_models(["llama3.2:latest"]);
+        let adapter = ReplayAdapter::new(mock, &dir, ReplayMode::Record);
+        assert_eq!(adapter.list_models().await.unwrap(), ["llama3.2:latest"]);
+
+        let adapter = ReplayAdapter::new(MockAdapter::new("gpt-4o"), &dir, ReplayMode::Replay);
+        assert_eq!(adapter.list_models().await.unwrap(), ["llama3.2:latest"]);
+
+        // the listings of other providers are not the recorded one
+        let ollama = OllamaAdapter::new("http://localhost:11434", "llama3.2");
+        let adapter = ReplayAdapter::new(ollama, &dir, ReplayMode::Replay);
+        assert!(adapter.list_models().await.is_err());
+        let openai = OpenAIAdapter::builder("sk-test", "gpt-4o")
+            .host("http://localhost:8080/v1")
+            .build()
+            .unwrap();
+        let adapter = ReplayAdapter::new(openai, &dir, ReplayMode::Replay);
+        assert!(adapter.list_models().await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+use std::{collections::HashMap, env, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{OllamaAdapter, OpenAIAdapter, RetryPolicy, OPENAI_DEFAULT_HOST};
+
+/// How to reach openai, shared by the services configuring their adapters from a yaml file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAIConfig {
+    #[serde(default = "default_openai_host")]
+    pub host: String,
+    // if not set, read from env OPENAI_API_KEY
+    pub api_key: Option<String>,
+    pub organization: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub timeout_secs: Option<u64>,
+    pub proxy: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaConfig {
+    #[serde(default = "default_ollama_host")]
+    pub host: String,
+}
+
+impl OpenAIConfig {
+    /// An adapter serving a model with this configuration
+    pub fn adapter(&self, model: impl Into<String>, retry: &RetryPolicy) -> Result<OpenAIAdapter> {
+        let api_key = match &self.api_key {
+            Some(api_key) => api_key.clone(),
+            None => env::var("OPENAI_API_KEY")
+                .context("openai api_key is not configured and OPENAI_API_KEY not set")?,
+        };
+        let mut builder = OpenAIAdapter::builder(api_key, model)
+            .host(&self.host)
+            .retry(retry.clone());
+        if let Some(organization) = &self.organization {
+            builder = builder.organization(organization);
+        }
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(timeout) = self.timeout_secs {
+            builder = builder.timeout(Duration::from_secs(timeout));
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy);
+        }
+        builder.build()
+    }
+}
+
+impl OllamaConfig {
+    /// An adapter serving a model with this configuration
+    pub fn adapter(&self, model: impl Into<String>, retry: &RetryPolicy) -> OllamaAdapter {
+        OllamaAdapter::new(&self.host, model).with_retry(retry.clone())
+    }
+}
+
+impl Default for OpenAIConfig {
+    fn default() -> Self {
+        Self {
+            host: default_openai_host(),
+            api_key: None,
+            organization: None,
+            headers: HashMap::new(),
+            timeout_secs: None,
+            proxy: None,
+        }
+    }
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        Self {
+            host: default_ollama_host(),
+        }
+    }
+}
+
+fn default_openai_host() -> String {
+    OPENAI_DEFAULT_HOST.to_string()
+}
+
+fn default_ollama_host() -> String {
+    "http://localhost:11434".to_string()
+}
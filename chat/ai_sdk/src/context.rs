@@ -0,0 +1,459 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::CoreBPE;
+use tracing::debug;
+
+use crate::{AiError, AiService, CompletionOptions, Message, Role, Usage};
+
+// https://github.com/openai/openai-cookbook/blob/main/examples/How_to_count_tokens_with_tiktoken.ipynb
+const TOKENS_PER_MESSAGE: usize = 4;
+const TOKENS_PER_REPLY: usize = 3;
+
+/// Tokens reserved for the completion when the request doesn't set `max_tokens`
+pub const DEFAULT_COMPLETION_TOKENS: usize = 1024;
+
+const SUMMARY_PROMPT: &str = "Summarize the following conversation in a few sentences. \
+Keep names, facts and decisions, and reply with the summary only.";
+
+/// Counts tokens with the tokenizer of OpenAI models. Other models have their own tokenizers,
+/// so the count is an approximation for them, usually within a few percent.
+#[derive(Clone, Copy)]
+pub struct TokenCounter {
+    bpe: &'static CoreBPE,
+}
+
+/// How to make a conversation fit in the context window. System messages at the start and the
+/// last message are always kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    /// drop the oldest messages until the rest fit
+    #[default]
+    DropOldest,
+    /// replace the oldest messages with a summary made by the model
+    SummarizeOldest,
+    /// only keep the last n messages, and drop more of them if they still don't fit
+    KeepLast(usize),
+}
+
+/// What to do when the messages which are always kept don't fit on their own
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    /// fail with [`AiError::ContextTooLong`]
+    #[default]
+    Reject,
+    /// cut the end of the last message
+    Trim,
+}
+
+/// Messages which fit in a context window
+#[derive(Debug, Clone)]
+pub struct Fitted {
+    pub messages: Vec<Message>,
+    /// tokens used to summarize the oldest messages, if they were
+    pub usage: Usage,
+}
+
+/// The number of tokens a model accepts for its prompt, and how to make messages fit in it
+#[derive(Clone)]
+pub struct ContextWindow {
+    counter: TokenCounter,
+    max_tokens: usize,
+    strategy: TruncationStrategy,
+    overflow: Overflow,
+}
+
+impl TokenCounter {
+    pub fn for_model(model: &str) -> Self {
+        // loading a tokenizer takes a while, so each one is only loaded once
+        static O200K: OnceLock<CoreBPE> = OnceLock::new();
+        static CL100K: OnceLock<CoreBPE> = OnceLock::new();
+        let bpe = if model.starts_with("gpt-4o") || model.starts_with("o1") {
+            O200K.get_or_init(|| tiktoken_rs::o200k_base().expect("o200k_base should load"))
+        } else {
+            CL100K.get_or_init(|| tiktoken_rs::cl100k_base().expect("cl100k_base should load"))
+        };
+        Self { bpe }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+
+    /// Tokens used by a message in a chat completion request, including the role
+    pub fn count_message(&self, message: &Message) -> usize {
+        let tool_calls: usize = message
+            .tool_calls
+            .iter()
+            .map(|c| self.count(&c.name) + self.count(&c.arguments.to_string()))
+            .sum();
+        TOKENS_PER_MESSAGE + self.count(&message.content) + tool_calls
+    }
+
+    /// Tokens used by the messages of a chat completion request
+    pub fn count_messages(&self, messages: &[Message]) -> usize {
+        let tokens: usize = messages.iter().map(|m| self.count_message(m)).sum();
+        tokens + TOKENS_PER_REPLY
+    }
+
+    /// The longest start of `text` which has at most `max_tokens` tokens
+    pub fn truncate(&self, text: &str, max_tokens: usize) -> String {
+        let mut tokens = self.bpe.encode_ordinary(text);
+        if tokens.len() <= max_tokens {
+            return text.to_string();
+        }
+        tokens.truncate(max_tokens);
+        // a token may end in the middle of a multi-byte char
+        while !tokens.is_empty() {
+            if let Ok(s) = self.bpe.decode(tokens.clone()) {
+                return s;
+            }
+            tokens.pop();
+        }
+        String::new()
+    }
+}
+
+/// The context window of a model in tokens, 8k when the model is unknown
+pub fn context_limit(model: &str) -> usize {
+    // the longest prefix wins, e.g. gpt-4o before gpt-4
+    const LIMITS: &[(&str, usize)] = &[
+        ("gpt-4o", 128_000),
+        ("gpt-4-turbo", 128_000),
+        ("gpt-4", 8_192),
+        ("gpt-3.5-turbo", 16_385),
+        ("o1", 128_000),
+        ("llama3.1", 131_072),
+        ("llama3.2", 131_072),
+        ("llama3.3", 131_072),
+        ("llama3", 8_192),
+        ("llava", 4_096),
+        ("qwen2.5", 32_768),
+        ("mistral", 32_768),
+        ("gemma2", 8_192),
+    ];
+    LIMITS
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, limit)| *limit)
+        .unwrap_or(8_192)
+}
+
+impl ContextWindow {
+    /// A window of `max_tokens` for the prompt
+    pub fn new(counter: TokenCounter, max_tokens: usize) -> Self {
+        Self {
+            counter,
+            max_tokens,
+            strategy: TruncationStrategy::default(),
+            overflow: Overflow::default(),
+        }
+    }
+
+    /// The context limit of `model`, minus the tokens reserved for the completion
+    pub fn for_model(model: &str, options: &CompletionOptions) -> Self {
+        let reserved = options
+            .max_tokens
+            .map(|n| n as usize)
+            .unwrap_or(DEFAULT_COMPLETION_TOKENS);
+        let max_tokens = context_limit(model).saturating_sub(reserved);
+        Self::new(TokenCounter::for_model(model), max_tokens)
+    }
+
+    pub fn strategy(mut self, strategy: TruncationStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    pub fn counter(&self) -> &TokenCounter {
+        &self.counter
+    }
+
+    /// Make `messages` fit in the window with the truncation strategy. `service` is only used
+    /// to summarize the oldest messages.
+    pub async fn fit(
+        &self,
+        service: &impl AiService,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<Fitted, AiError> {
+        let mut usage = Usage::default();
+        let (system, rest) = split_system(messages);
+        let mut rest = match self.strategy {
+            TruncationStrategy::KeepLast(n) => rest[rest.len().saturating_sub(n.max(1))..].to_vec(),
+            _ => rest.to_vec(),
+        };
+        let mut system = system.to_vec();
+
+        if self.strategy == TruncationStrategy::SummarizeOldest {
+            // leave a quarter of the window to the summary, if one is needed
+            let summary_tokens = self.max_tokens / 4;
+            let all = self.counter.count_messages(&system)
+                + rest
+                    .iter()
+                    .map(|m| self.counter.count_message(m))
+                    .sum::<usize>();
+            if all > self.max_tokens {
+                let dropped =
+                    self.drop_oldest(&system, &mut rest, self.max_tokens - summary_tokens);
+                if !dropped.is_empty() {
+                    let summary = self.summarize(service, &dropped, options, summary_tokens);
+                    let (summary, summary_usage) = summary.await?;
+                    system.push(summary);
+                    usage = summary_usage;
+                }
+            }
+        } else {
+            self.drop_oldest(&system, &mut rest, self.max_tokens);
+        }
+
+        let mut messages: Vec<Message> = system.into_iter().chain(rest).collect();
+        let tokens = self.counter.count_messages(&messages);
+        if tokens <= self.max_tokens {
+            return Ok(Fitted { messages, usage });
+        }
+        match self.overflow {
+            Overflow::Reject => Err(AiError::ContextTooLong(format!(
+                "prompt has {} tokens, more than the {} tokens allowed",
+                tokens, self.max_tokens
+            ))),
+            Overflow::Trim => {
+                let Some(last) = messages.last_mut() else {
+                    return Err(AiError::ContextTooLong(format!(
+                        "no message to trim to fit in the {} tokens allowed",
+                        self.max_tokens
+                    )));
+                };
+                let content_tokens = self.counter.count(&last.content);
+                let keep = content_tokens.saturating_sub(tokens - self.max_tokens);
+                if keep == 0 {
+                    return Err(AiError::ContextTooLong(format!(
+                        "system prompt alone is longer than the {} tokens allowed",
+                        self.max_tokens
+                    )));
+                }
+                last.content = self.counter.truncate(&last.content, keep);
+                Ok(Fitted { messages, usage })
+            }
+        }
+    }
+
+    /// Drop the oldest messages of `rest` until they fit in `max_tokens` with `system`, the
+    /// last one is always kept. Returns the dropped messages.
+    fn drop_oldest(
+        &self,
+        system: &[Message],
+        rest: &mut Vec<Message>,
+        max_tokens: usize,
+    ) -> Vec<Message> {
+        let system_tokens = self.counter.count_messages(system);
+        let mut tokens: usize = system_tokens
+            + rest
+                .iter()
+                .map(|m| self.counter.count_message(m))
+                .sum::<usize>();
+        let mut n = 0;
+        while tokens > max_tokens && n + 1 < rest.len() {
+            tokens -= self.counter.count_message(&rest[n]);
+            n += 1;
+            // a tool result can't be sent without the call it answers
+            while n + 1 < rest.len() && rest[n].role == Role::Tool {
+                tokens -= self.counter.count_message(&rest[n]);
+                n += 1;
+            }
+        }
+        if n > 0 {
+            debug!("dropped {} messages to fit {} tokens", n, max_tokens);
+        }
+        rest.drain(..n).collect()
+    }
+
+    /// Summarize `messages` into a system message of at most `max_tokens`, with the tokens used
+    async fn summarize(
+        &self,
+        service: &impl AiService,
+        messages: &[Message],
+        options: &CompletionOptions,
+        max_tokens: usize,
+    ) -> Result<(Message, Usage), AiError> {
+        let summary = Message::system("Summary of the earlier conversation: ");
+        let completion_tokens = max_tokens.saturating_sub(self.counter.count_message(&summary));
+
+        let conversation: Vec<String> = messages
+            .iter()
+            .map(|m| format!("{}: {}", m.role, m.content))
+            .collect();
+        let mut request = vec![Message::system(SUMMARY_PROMPT), Message::user("")];
+        // the summary request has to fit in the window as well
+        let budget = self
+            .max_tokens
+            .saturating_sub(self.counter.count_messages(&request) + completion_tokens);
+        request[1].content = self.counter.truncate(&conversation.join("\n"), budget);
+
+        let options = options.clone().max_tokens(completion_tokens.max(1) as u32);
+        let ret = service.complete(&request, &options).await?;
+        let summary = Message::system(format!("{}{}", summary.content, ret.content));
+        Ok((summary, ret.usage))
+    }
+}
+
+/// Split the leading system messages from the rest of the conversation
+fn split_system(messages: &[Message]) -> (&[Message], &[Message]) {
+    let n = messages
+        .iter()
+        .take_while(|m| m.role == Role::System)
+        .count();
+    messages.split_at(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockAdapter;
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::system("You are a helpful assistant."),
+            Message::user("My name is Alice and I live in Paris."),
+            Message::assiatant("Nice to meet you, Alice!"),
+            Message::user("What is the weather like today?"),
+            Message::assiatant("It is sunny in Paris."),
+            Message::user("Where do I live?"),
+        ]
+    }
+
+    #[test]
+    fn token_counter_should_work() {
+        let counter = TokenCounter::for_model("gpt-4o-mini");
+        assert_eq!(counter.count("hello world"), 2);
+        assert_eq!(counter.count_messages(&[Message::user("hello world")]), 9);
+        assert_eq!(counter.truncate("hello world", 1), "hello");
+        assert_eq!(counter.truncate("hello world", 5), "hello world");
+
+        let counter = TokenCounter::for_model("llama3.2");
+        assert_eq!(counter.count("hello world"), 2);
+    }
+
+    #[test]
+    fn context_limit_should_match_longest_prefix() {
+        assert_eq!(context_limit("gpt-4o-mini"), 128_000);
+        assert_eq!(context_limit("gpt-4"), 8_192);
+        assert_eq!(context_limit("llama3.2:1b"), 131_072);
+        assert_eq!(context_limit("unknown"), 8_192);
+
+        let options = CompletionOptions::default().max_tokens(1000);
+        assert_eq!(
+            ContextWindow::for_model("gpt-4", &options).max_tokens(),
+            7_192
+        );
+    }
+
+    #[tokio::test]
+    async fn context_window_should_drop_oldest() {
+        let messages = conversation();
+        let counter = TokenCounter::for_model("gpt-4o-mini");
+        let options = CompletionOptions::default();
+        let adapter = MockAdapter::new("gpt-4o-mini");
+
+        // everything fits
+        let total = counter.count_messages(&messages);
+        let window = ContextWindow::new(counter, total);
+        let ret = window
+            .fit(&adapter, &messages, &options)
+            .await
+            .unwrap()
+            .messages;
+        assert_eq!(ret.len(), 6);
+
+        // the system prompt and the last 2 messages fit
+        let keep = [&messages[..1], &messages[4..]].concat();
+        let window = ContextWindow::new(counter, counter.count_messages(&keep));
+        let ret = window
+            .fit(&adapter, &messages, &options)
+            .await
+            .unwrap()
+            .messages;
+        assert_eq!(ret.len(), 3);
+        assert_eq!(ret[0].role, Role::System);
+        assert_eq!(ret[2].content, "Where do I live?");
+
+        let window = ContextWindow::new(counter, total).strategy(TruncationStrategy::KeepLast(1));
+        let ret = window
+            .fit(&adapter, &messages, &options)
+            .await
+            .unwrap()
+            .messages;
+        assert_eq!(ret.len(), 2);
+        assert!(adapter.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn context_window_should_summarize_oldest() {
+        let messages = conversation();
+        let counter = TokenCounter::for_model("gpt-4o-mini");
+        let adapter = MockAdapter::new("gpt-4o-mini").reply("Alice lives in Paris.");
+        let window = ContextWindow::new(counter, 60).strategy(TruncationStrategy::SummarizeOldest);
+        let ret = window
+            .fit(&adapter, &messages, &CompletionOptions::default())
+            .await
+            .unwrap();
+        let usage = ret.usage;
+        let ret = ret.messages;
+
+        assert_eq!(
+            ret[1].content,
+            "Summary of the earlier conversation: Alice lives in Paris."
+        );
+        assert_eq!(ret.last().unwrap().content, "Where do I live?");
+        assert!(counter.count_messages(&ret) <= 60);
+        let requests = adapter.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0][1].content.contains("user: My name is Alice"));
+        assert_eq!(usage.completion_tokens, 4);
+    }
+
+    #[tokio::test]
+    async fn context_window_should_reject_or_trim_overflow() {
+        let counter = TokenCounter::for_model("gpt-4o-mini");
+        let adapter = MockAdapter::new("gpt-4o-mini");
+        let options = CompletionOptions::default();
+        let messages = vec![Message::user("word ".repeat(100))];
+
+        let window = ContextWindow::new(counter, 50);
+        let ret = window.fit(&adapter, &messages, &options).await;
+        assert!(matches!(ret, Err(AiError::ContextTooLong(_))));
+
+        let window = window.overflow(Overflow::Trim);
+        let ret = window
+            .fit(&adapter, &messages, &options)
+            .await
+            .unwrap()
+            .messages;
+        assert_eq!(counter.count_messages(&ret), 50);
+    }
+
+    #[tokio::test]
+    async fn context_window_should_reject_what_it_cannot_trim() {
+        let counter = TokenCounter::for_model("gpt-4o-mini");
+        let adapter = MockAdapter::new("gpt-4o-mini");
+        let options = CompletionOptions::default();
+        let window = ContextWindow::new(counter, 1).overflow(Overflow::Trim);
+
+        let ret = window.fit(&adapter, &[], &options).await;
+        assert!(matches!(ret, Err(AiError::ContextTooLong(_))));
+        let ret = window.fit(&adapter, &[Message::user("hi")], &options).await;
+        assert!(matches!(ret, Err(AiError::ContextTooLong(_))));
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AiError {
+    #[error("rate limited, retry after {retry_after:?}")]
+    RateLimited { retry_after: Option<Duration> },
+
+    #[error("authentication failed: {0}")]
+    AuthFailed(String),
+
+    #[error("context too long: {0}")]
+    ContextTooLong(String),
+
+    #[error("bad request ({status}): {message}")]
+    BadRequest { status: u16, message: String },
+
+    #[error("upstream error ({status}): {message}")]
+    Upstream { status: u16, message: String },
+
+    #[error("request timeout")]
+    Timeout,
+
+    #[error("network error: {0}")]
+    Network(String),
+
+    #[error("malformed response: {0}")]
+    MalformedResponse(String),
+
+    #[error("{0}")]
+    Other(#[from] anyhow::Error),
+}
+
+impl AiError {
+    /// Whether the same request may succeed if sent again later
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::RateLimited { .. } | Self::Upstream { .. } | Self::Timeout | Self::Network(_)
+        )
+    }
+
+    /// Classify an error response by its status and body
+    pub(crate) fn from_response(status: u16, retry_after: Option<Duration>, body: &str) -> Self {
+        let message = error_message(body);
+        let lower = message.to_lowercase();
+        match status {
+            429 => Self::RateLimited { retry_after },
+            401 | 403 => Self::AuthFailed(message),
+            413 => Self::ContextTooLong(message),
+            400 if lower.contains("context_length_exceeded")
+                || lower.contains("context length")
+                || lower.contains("maximum context") =>
+            {
+                Self::ContextTooLong(message)
+            }
+            500..=599 => Self::Upstream { status, message },
+            _ => Self::BadRequest { status, message },
+        }
+    }
+}
+
+impl From<reqwest::Error> for AiError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout
+        } else if e.is_decode() {
+            Self::MalformedResponse(e.to_string())
+        } else if let Some(status) = e.status() {
+            Self::from_response(status.as_u16(), None, &e.to_string())
+        } else {
+            Self::Network(e.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for AiError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::MalformedResponse(e.to_string())
+    }
+}
+
+// openai: {"error": {"message": "..."}}, ollama: {"error": "..."}
+fn error_message(body: &str) -> String {
+    let message = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| match &v["error"] {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Object(o) => o.get("message")?.as_str().map(|s| s.to_string()),
+            _ => None,
+        });
+    message.unwrap_or_else(|| body.chars().take(256).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ai_error_should_classify_responses() {
+        let err = AiError::from_response(429, Some(Duration::from_secs(2)), "");
+        assert!(matches!(err, AiError::RateLimited { retry_after: Some(d) } if d.as_secs() == 2));
+        assert!(err.is_retryable());
+
+        let body = r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error","code":"invalid_api_key"}}"#;
+        let err = AiError::from_response(401, None, body);
+        assert_eq!(
+            err.to_string(),
+            "authentication failed: Incorrect API key provided"
+        );
+        assert!(!err.is_retryable());
+
+        let body = r#"{"error":{"message":"This model's maximum context length is 128000 tokens.","code":"context_length_exceeded"}}"#;
+        let err = AiError::from_response(400, None, body);
+        assert!(matches!(err, AiError::ContextTooLong(_)));
+
+        let err = AiError::from_response(404, None, r#"{"error":"model \"llama9\" not found"}"#);
+        assert_eq!(
+            err.to_string(),
+            "bad request (404): model \"llama9\" not found"
+        );
+
+        let err = AiError::from_response(503, None, "Service Unavailable");
+        assert!(matches!(err, AiError::Upstream { status: 503, .. }));
+        assert!(err.is_retryable());
+    }
+}
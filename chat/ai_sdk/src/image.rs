@@ -0,0 +1,103 @@
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::AiError;
+
+/// An image attached to a message, sent along with its text content. Either the bytes of the
+/// image, or a url the provider can fetch it from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Image {
+    Url {
+        url: String,
+    },
+    Data {
+        mime_type: String,
+        #[serde(serialize_with = "to_base64", deserialize_with = "from_base64")]
+        data: Vec<u8>,
+    },
+}
+
+impl Image {
+    pub fn url(url: impl Into<String>) -> Self {
+        Self::Url { url: url.into() }
+    }
+
+    pub fn data(mime_type: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self::Data {
+            mime_type: mime_type.into(),
+            data: data.into(),
+        }
+    }
+
+    /// The url of the image, with the bytes inlined as a `data:` url
+    pub fn to_url(&self) -> String {
+        match self {
+            Self::Url { url } => url.clone(),
+            Self::Data { mime_type, data } => {
+                format!("data:{};base64,{}", mime_type, STANDARD.encode(data))
+            }
+        }
+    }
+}
+
+/// The bytes of an image given by a `data:` url. Other urls are rejected, a provider which
+/// needs the bytes must not fetch or read whatever url it is given, e.g. a local file.
+pub(crate) fn inline(url: &str) -> Result<Image, AiError> {
+    let data = url
+        .strip_prefix("data:")
+        .ok_or_else(|| anyhow!("image url {} must be a data url", url))?;
+    let (mime_type, data) = data
+        .split_once(";base64,")
+        .ok_or_else(|| anyhow!("invalid image data url"))?;
+    let data = STANDARD
+        .decode(data)
+        .map_err(|e| anyhow!("invalid image data url: {}", e))?;
+    Ok(Image::data(mime_type, data))
+}
+
+/// Base64 encode the bytes of an image
+pub(crate) fn encode(data: &[u8]) -> String {
+    STANDARD.encode(data)
+}
+
+fn to_base64<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(data))
+}
+
+fn from_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    STANDARD.decode(s).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn image_should_serialize_as_base64() {
+        let image = Image::data("image/png", b"hello".to_vec());
+        let value = serde_json::to_value(&image).unwrap();
+        assert_eq!(
+            value,
+            json!({ "type": "data", "mime_type": "image/png", "data": "aGVsbG8=" })
+        );
+        assert_eq!(serde_json::from_value::<Image>(value).unwrap(), image);
+        assert_eq!(image.to_url(), "data:image/png;base64,aGVsbG8=");
+
+        let image = Image::url("https://example.com/cat.png");
+        assert_eq!(image.to_url(), "https://example.com/cat.png");
+    }
+
+    #[test]
+    fn inline_should_only_accept_data_urls() {
+        let image = inline("data:image/png;base64,aGVsbG8=").unwrap();
+        assert_eq!(image, Image::data("image/png", b"hello".to_vec()));
+
+        assert!(inline("http://169.254.169.254/latest/meta-data").is_err());
+        assert!(inline("file:///etc/passwd").is_err());
+    }
+}
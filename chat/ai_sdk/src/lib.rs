@@ -1,38 +1,233 @@
 mod adapters;
+mod config;
+mod context;
+mod error;
+mod image;
+mod options;
+mod retry;
+mod tools;
 
 use core::fmt;
+use std::{ops::AddAssign, time::Duration};
+
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
 
 pub use adapters::*;
+pub use config::{OllamaConfig, OpenAIConfig};
+pub use context::*;
+pub use error::AiError;
+pub use image::Image;
+pub use options::*;
+pub use retry::RetryPolicy;
+pub use tools::*;
+
+/// A stream of token deltas produced by [`AiService::complete_stream`].
+pub type CompletionStream = BoxStream<'static, Result<String, AiError>>;
 
 pub enum AiAdapter {
     OpenAI(OpenAIAdapter),
     Ollama(OllamaAdapter),
+    Mock(MockAdapter),
+    Replay(ReplayAdapter),
+    Fallback(FallbackAdapter),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Role {
     User,
     Assistant,
     System,
+    Tool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: Role,
     pub content: String,
+    /// images sent along with the content, only for user messages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+    /// tools the assistant asked to call, only for assistant messages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// the call this message is the result of, only for tool messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// Tokens used by a completion, as reported by the provider
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionResult {
+    pub content: String,
+    /// tools the model asked to call, only with [`AiService::complete_with_tools`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    pub usage: Usage,
+    /// the model which actually served the request, may be more specific than the requested one
+    pub model: String,
+    /// why the model stopped, e.g. `stop` or `length`
+    pub finish_reason: Option<String>,
+    /// time spent on the request, including retries
+    pub latency: Duration,
 }
 
 #[allow(async_fn_in_trait)]
 pub trait AiService {
-    async fn complete(&self, messages: &[Message]) -> anyhow::Result<String>;
+    async fn complete(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError>;
+
+    /// Stream the completion as token deltas, as soon as the model generates them.
+    async fn complete_stream(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionStream, AiError>;
+
+    /// Complete with the given tools available. The result either has `tool_calls` to run, or
+    /// the final answer in `content`.
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError>;
+}
+
+#[allow(async_fn_in_trait)]
+pub trait EmbeddingService {
+    /// Embed every input, the result has the same order as the input. Large inputs are sent
+    /// in batches.
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, AiError>;
+
+    /// The dimension of the vectors produced by the embedding model
+    async fn dimensions(&self) -> Result<usize, AiError> {
+        let mut ret = self.embed(&["dimensions".to_string()]).await?;
+        let embedding = ret.pop().ok_or(AiError::MalformedResponse(
+            "no embedding returned".to_string(),
+        ))?;
+        Ok(embedding.len())
+    }
+}
+
+#[allow(async_fn_in_trait)]
+pub trait ModelService {
+    /// Names of the models the provider can serve, sorted
+    async fn list_models(&self) -> Result<Vec<String>, AiError>;
+}
+
+impl AiAdapter {
+    /// The model requests are sent to
+    pub fn model(&self) -> &str {
+        match self {
+            Self::OpenAI(adapter) => adapter.model(),
+            Self::Ollama(adapter) => adapter.model(),
+            Self::Mock(adapter) => adapter.model(),
+            Self::Replay(adapter) => adapter.model(),
+            Self::Fallback(adapter) => adapter.model(),
+        }
+    }
+
+    /// The provider requests are sent to, e.g. `ollama`
+    pub fn provider(&self) -> &'static str {
+        match self {
+            Self::OpenAI(_) => "openai",
+            Self::Ollama(_) => "ollama",
+            Self::Mock(_) => "mock",
+            Self::Replay(adapter) => adapter.inner().provider(),
+            Self::Fallback(adapter) => adapter.primary().provider(),
+        }
+    }
+
+    /// The server requests are sent to, the mock adapter has none
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            Self::OpenAI(adapter) => Some(adapter.host()),
+            Self::Ollama(adapter) => Some(adapter.host()),
+            Self::Mock(_) => None,
+            Self::Replay(adapter) => adapter.inner().host(),
+            Self::Fallback(adapter) => adapter.primary().host(),
+        }
+    }
 }
 
 // TODO: in future, use enum_dispatch crate to dispatch the methods for different adapters.
 impl AiService for AiAdapter {
-    async fn complete(&self, messages: &[Message]) -> anyhow::Result<String> {
+    async fn complete(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
+        match self {
+            Self::OpenAI(adapter) => adapter.complete(messages, options).await,
+            Self::Ollama(adapter) => adapter.complete(messages, options).await,
+            Self::Mock(adapter) => adapter.complete(messages, options).await,
+            Self::Replay(adapter) => adapter.complete(messages, options).await,
+            Self::Fallback(adapter) => adapter.complete(messages, options).await,
+        }
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: &[Message],
+        options: &CompletionOptions,
+    ) -> Result<CompletionStream, AiError> {
+        match self {
+            Self::OpenAI(adapter) => adapter.complete_stream(messages, options).await,
+            Self::Ollama(adapter) => adapter.complete_stream(messages, options).await,
+            Self::Mock(adapter) => adapter.complete_stream(messages, options).await,
+            Self::Replay(adapter) => adapter.complete_stream(messages, options).await,
+            Self::Fallback(adapter) => adapter.complete_stream(messages, options).await,
+        }
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &CompletionOptions,
+    ) -> Result<CompletionResult, AiError> {
         match self {
-            Self::OpenAI(adapter) => adapter.complete(messages).await,
-            Self::Ollama(adapter) => adapter.complete(messages).await,
+            Self::OpenAI(adapter) => adapter.complete_with_tools(messages, tools, options).await,
+            Self::Ollama(adapter) => adapter.complete_with_tools(messages, tools, options).await,
+            Self::Mock(adapter) => adapter.complete_with_tools(messages, tools, options).await,
+            Self::Replay(adapter) => adapter.complete_with_tools(messages, tools, options).await,
+            Self::Fallback(adapter) => adapter.complete_with_tools(messages, tools, options).await,
+        }
+    }
+}
+
+impl EmbeddingService for AiAdapter {
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, AiError> {
+        match self {
+            Self::OpenAI(adapter) => adapter.embed(input).await,
+            Self::Ollama(adapter) => adapter.embed(input).await,
+            Self::Mock(adapter) => adapter.embed(input).await,
+            Self::Replay(adapter) => adapter.embed(input).await,
+            Self::Fallback(adapter) => adapter.embed(input).await,
+        }
+    }
+}
+
+impl ModelService for AiAdapter {
+    async fn list_models(&self) -> Result<Vec<String>, AiError> {
+        match self {
+            Self::OpenAI(adapter) => adapter.list_models().await,
+            Self::Ollama(adapter) => adapter.list_models().await,
+            Self::Mock(adapter) => adapter.list_models().await,
+            Self::Replay(adapter) => adapter.list_models().await,
+            Self::Fallback(adapter) => adapter.list_models().await,
         }
     }
 }
@@ -43,15 +238,37 @@ impl fmt::Display for Role {
             Role::User => write!(f, "user"),
             Role::Assistant => write!(f, "assistant"),
             Role::System => write!(f, "system"),
+            Role::Tool => write!(f, "tool"),
         }
     }
 }
 
+impl Usage {
+    pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
 impl Message {
     pub fn new(role: Role, content: impl Into<String>) -> Self {
         Self {
             role,
             content: content.into(),
+            images: vec![],
+            tool_calls: vec![],
+            tool_call_id: None,
         }
     }
 
@@ -66,4 +283,26 @@ impl Message {
     pub fn system(content: impl Into<String>) -> Self {
         Self::new(Role::System, content)
     }
+
+    /// Attach images to the message, e.g. to ask the model to describe them
+    pub fn with_images(mut self, images: impl IntoIterator<Item = Image>) -> Self {
+        self.images.extend(images);
+        self
+    }
+
+    /// An assistant message asking to call tools
+    pub fn tool_calls(calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls: calls,
+            ..Self::new(Role::Assistant, "")
+        }
+    }
+
+    /// The result of a tool call, sent back to the model
+    pub fn tool(call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(call_id.into()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
 }
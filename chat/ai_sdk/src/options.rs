@@ -0,0 +1,151 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Generation parameters of a completion request. Unset fields use the model defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompletionOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "ResponseFormat::is_text")]
+    pub response_format: ResponseFormat,
+    /// only used by [`crate::FallbackAdapter`], skip the backends which cost more
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_cost_tier: Option<CostTier>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseFormat {
+    #[default]
+    Text,
+    /// Force the model to reply with a valid json object
+    Json,
+}
+
+/// Rough price class of a backend, used to route requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostTier {
+    Low,
+    Medium,
+    High,
+}
+
+impl CompletionOptions {
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn stop(mut self, stop: impl Into<String>) -> Self {
+        self.stop.push(stop.into());
+        self
+    }
+
+    pub fn seed(mut self, seed: i64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn json(mut self) -> Self {
+        self.response_format = ResponseFormat::Json;
+        self
+    }
+
+    pub fn max_cost_tier(mut self, tier: CostTier) -> Self {
+        self.max_cost_tier = Some(tier);
+        self
+    }
+
+    /// Check the values are in the ranges accepted by both OpenAI and Ollama
+    pub fn validate(&self) -> Result<()> {
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                bail!("temperature must be between 0 and 2, got {}", temperature);
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !(0.0..=1.0).contains(&top_p) {
+                bail!("top_p must be between 0 and 1, got {}", top_p);
+            }
+        }
+        if self.max_tokens == Some(0) {
+            bail!("max_tokens must be greater than 0");
+        }
+        // openai accepts at most 4 stop sequences
+        if self.stop.len() > 4 {
+            bail!(
+                "at most 4 stop sequences are allowed, got {}",
+                self.stop.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ResponseFormat {
+    pub fn is_text(&self) -> bool {
+        *self == Self::Text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn completion_options_should_deserialize() {
+        let options: CompletionOptions = serde_json::from_value(json!({
+            "temperature": 0.2,
+            "max_tokens": 256,
+            "stop": ["\n\n"],
+            "response_format": "json",
+            "language": "en"
+        }))
+        .unwrap();
+        assert_eq!(
+            options,
+            CompletionOptions::default()
+                .temperature(0.2)
+                .max_tokens(256)
+                .stop("\n\n")
+                .json()
+        );
+        assert!(options.validate().is_ok());
+    }
+
+    #[test]
+    fn completion_options_should_validate() {
+        assert!(CompletionOptions::default().validate().is_ok());
+        assert!(CompletionOptions::default()
+            .temperature(3.0)
+            .validate()
+            .is_err());
+        assert!(CompletionOptions::default().top_p(1.5).validate().is_err());
+        assert!(CompletionOptions::default()
+            .max_tokens(0)
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use std::{future::Future, time::Duration};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::AiError;
+
+/// Exponential backoff with jitter for retryable errors, see [`AiError::is_retryable`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 10_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Run `f` until it succeeds, fails with a non retryable error or retries are exhausted
+    pub async fn run<T, F, Fut>(&self, mut f: F) -> Result<T, AiError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, AiError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let err = match f().await {
+                Ok(v) => return Ok(v),
+                Err(e) => e,
+            };
+            if !err.is_retryable() || attempt >= self.max_retries {
+                return Err(err);
+            }
+            let Some(delay) = self.delay(attempt, &err) else {
+                return Err(err);
+            };
+            attempt += 1;
+            warn!(
+                "ai request failed: {}, retry {}/{} in {:?}",
+                err, attempt, self.max_retries, delay
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    // None if the server asks us to wait longer than we are willing to
+    fn delay(&self, attempt: u32, err: &AiError) -> Option<Duration> {
+        let max = Duration::from_millis(self.max_delay_ms);
+        if let AiError::RateLimited {
+            retry_after: Some(retry_after),
+        } = err
+        {
+            return (*retry_after <= max).then_some(*retry_after);
+        }
+        let exp = self
+            .base_delay_ms
+            .saturating_mul(1u64 << attempt.min(32))
+            .min(self.max_delay_ms);
+        // "equal jitter": half of the delay is fixed, the other half random
+        let jitter = rand::thread_rng().gen_range(0..=exp / 2);
+        Some(Duration::from_millis(exp - exp / 2 + jitter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay_ms: 1,
+            max_delay_ms: 10,
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_should_retry_retryable_errors() {
+        let count = AtomicU32::new(0);
+        let ret = policy()
+            .run(|| async {
+                match count.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(AiError::Timeout),
+                    1 => Err(AiError::Upstream {
+                        status: 502,
+                        message: "bad gateway".to_string(),
+                    }),
+                    _ => Ok("ok"),
+                }
+            })
+            .await;
+        assert_eq!(ret.unwrap(), "ok");
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_should_not_retry_other_errors() {
+        let count = AtomicU32::new(0);
+        let ret: Result<(), _> = policy()
+            .run(|| async {
+                count.fetch_add(1, Ordering::SeqCst);
+                Err(AiError::AuthFailed("invalid api key".to_string()))
+            })
+            .await;
+        assert!(matches!(ret, Err(AiError::AuthFailed(_))));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_should_give_up_after_max_retries() {
+        let count = AtomicU32::new(0);
+        let ret: Result<(), _> = policy()
+            .run(|| async {
+                count.fetch_add(1, Ordering::SeqCst);
+                Err(AiError::RateLimited {
+                    retry_after: Some(Duration::from_millis(1)),
+                })
+            })
+            .await;
+        assert!(matches!(ret, Err(AiError::RateLimited { .. })));
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+
+        // retry after longer than max delay, give up immediately
+        let count = AtomicU32::new(0);
+        let ret: Result<(), _> = policy()
+            .run(|| async {
+                count.fetch_add(1, Ordering::SeqCst);
+                Err(AiError::RateLimited {
+                    retry_after: Some(Duration::from_secs(60)),
+                })
+            })
+            .await;
+        assert!(ret.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn delay_should_be_bounded() {
+        let policy = RetryPolicy::default();
+        for attempt in 0..40 {
+            let delay = policy.delay(attempt, &AiError::Timeout).unwrap();
+            assert!(delay <= Duration::from_millis(policy.max_delay_ms));
+        }
+    }
+}
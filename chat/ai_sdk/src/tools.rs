@@ -0,0 +1,178 @@
+use std::{mem, time::Duration};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{AiError, AiService, CompletionOptions, CompletionResult, Message, Usage};
+
+/// A function the model can ask to call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments
+    pub parameters: serde_json::Value,
+}
+
+/// A call of a tool requested by the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+/// Runs the tool calls requested by the model
+#[allow(async_fn_in_trait)]
+pub trait ToolHandler {
+    async fn call(&self, call: &ToolCall) -> anyhow::Result<String>;
+}
+
+impl Tool {
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: serde_json::Value,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameters,
+        }
+    }
+}
+
+/// Complete `messages` with `tools`, running every requested tool call with `handler` and
+/// sending the results back, until the model produces a final answer or `max_steps` rounds
+/// have been used. Returns the final answer, with the usage and latency of every round.
+pub async fn run_tools(
+    service: &impl AiService,
+    mut messages: Vec<Message>,
+    tools: &[Tool],
+    handler: &impl ToolHandler,
+    options: &CompletionOptions,
+    max_steps: usize,
+) -> Result<CompletionResult, AiError> {
+    let mut usage = Usage::default();
+    let mut latency = Duration::ZERO;
+    for _ in 0..max_steps {
+        let mut ret = service
+            .complete_with_tools(&messages, tools, options)
+            .await?;
+        usage += ret.usage;
+        latency += ret.latency;
+        if ret.tool_calls.is_empty() {
+            ret.usage = usage;
+            ret.latency = latency;
+            return Ok(ret);
+        }
+
+        let calls = mem::take(&mut ret.tool_calls);
+        messages.push(Message {
+            content: ret.content,
+            ..Message::tool_calls(calls.clone())
+        });
+        for call in calls {
+            // let the model know the tool failed instead of aborting, it may recover
+            let content = match handler.call(&call).await {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("tool {} failed: {}", call.name, e);
+                    format!("error: {}", e)
+                }
+            };
+            messages.push(Message::tool(call.id, content));
+        }
+    }
+    Err(anyhow!("no final answer after {} tool call rounds", max_steps).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{MockAdapter, Role};
+
+    struct Weather;
+
+    impl ToolHandler for Weather {
+        async fn call(&self, call: &ToolCall) -> anyhow::Result<String> {
+            match call.arguments["city"].as_str() {
+                Some("Paris") => Ok("sunny, 20°C".to_string()),
+                _ => bail!("unknown city"),
+            }
+        }
+    }
+
+    fn weather_tool() -> Tool {
+        Tool::new(
+            "get_weather",
+            "Get the current weather of a city",
+            json!({
+                "type": "object",
+                "properties": { "city": { "type": "string" } },
+                "required": ["city"]
+            }),
+        )
+    }
+
+    fn call(id: &str, city: &str) -> ToolCall {
+        ToolCall {
+            id: id.to_string(),
+            name: "get_weather".to_string(),
+            arguments: json!({ "city": city }),
+        }
+    }
+
+    #[tokio::test]
+    async fn run_tools_should_work() -> anyhow::Result<()> {
+        let adapter = MockAdapter::new("gpt-4o-mini")
+            .reply_tool_calls(vec![call("call_1", "Paris"), call("call_2", "Atlantis")])
+            .reply("It is sunny in Paris.");
+        let messages = vec![Message::user("What's the weather in Paris?")];
+        let ret = run_tools(
+            &adapter,
+            messages,
+            &[weather_tool()],
+            &Weather,
+            &CompletionOptions::default(),
+            5,
+        )
+        .await?;
+        assert_eq!(ret.content, "It is sunny in Paris.");
+        // 5 prompt words and 2 tool calls, then 10 prompt words and 5 words
+        assert_eq!(ret.usage, Usage::new(15, 7));
+
+        let requests = adapter.requests();
+        assert_eq!(requests.len(), 2);
+        let second = &requests[1];
+        assert_eq!(second.len(), 4);
+        assert_eq!(second[1].tool_calls.len(), 2);
+        assert_eq!(second[2].role, Role::Tool);
+        assert_eq!(second[2].tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(second[2].content, "sunny, 20°C");
+        assert_eq!(second[3].content, "error: unknown city");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_tools_should_stop_after_max_steps() {
+        let adapter = MockAdapter::new("gpt-4o-mini")
+            .reply_tool_calls(vec![call("call_1", "Paris")])
+            .reply_tool_calls(vec![call("call_2", "Paris")]);
+        let messages = vec![Message::user("What's the weather in Paris?")];
+        let ret = run_tools(
+            &adapter,
+            messages,
+            &[weather_tool()],
+            &Weather,
+            &CompletionOptions::default(),
+            2,
+        )
+        .await;
+        assert!(ret.is_err());
+    }
+}
@@ -1,10 +1,15 @@
+use ai_sdk::{AiAdapter, EmbeddingService, OllamaConfig, OpenAIConfig, RetryPolicy};
 use anyhow::{bail, Result};
+use chat_core::AdapterType;
 use serde::{Deserialize, Serialize};
 use std::{env, fs::File};
 
+use crate::{AiEmbedder, VECTOR_SIZE};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
+    pub ai: AiConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -13,6 +18,49 @@ pub struct ServerConfig {
     pub db_url: String,
 }
 
+/// The ai providers of the bot, configured like the ones of chat_server
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AiConfig {
+    /// the model embedding the indexed code and the questions to the bot, the vectors it makes
+    /// should have `VECTOR_SIZE` dimensions
+    pub embed: ModelConfig,
+    #[serde(default)]
+    pub openai: OpenAIConfig,
+    #[serde(default)]
+    pub ollama: OllamaConfig,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelConfig {
+    pub adapter: AdapterType,
+    pub model: String,
+}
+
+impl AiConfig {
+    /// The embedder of the configured model, the indexer and the bot must use the same one.
+    /// It fails when the model doesn't make vectors of `VECTOR_SIZE` dimensions, as the vector
+    /// store couldn't hold them.
+    pub async fn embedder(&self) -> Result<AiEmbedder> {
+        let model = self.embed.model.as_str();
+        let adapter: AiAdapter = match self.embed.adapter {
+            AdapterType::Ollama => self.ollama.adapter(model, &self.retry).into(),
+            AdapterType::OpenAI => self.openai.adapter(model, &self.retry)?.into(),
+        };
+        let dimensions = adapter.dimensions().await?;
+        if dimensions != VECTOR_SIZE {
+            bail!(
+                "embed model {} makes vectors of {} dimensions, the vector store expects {}",
+                model,
+                dimensions,
+                VECTOR_SIZE
+            );
+        }
+        Ok(AiEmbedder::new(adapter))
+    }
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         // 思考: 这里同时打开了三个文件去判断，会影响到效率(优化做法，按优先级打开，然后再判断是否需要打开下一个)，但这里是在程序初始化的时候去做，所以问题不大，可以接受
@@ -0,0 +1,34 @@
+use std::{fmt, sync::Arc};
+
+use ai_sdk::{AiAdapter, EmbeddingService};
+use anyhow::Result;
+use async_trait::async_trait;
+use swiftide::{traits::EmbeddingModel, Embeddings};
+
+/// Use an ai_sdk adapter as the swiftide embedding model, so that the bot embeds text with the
+/// same ai client as chat_server
+#[derive(Clone)]
+pub struct AiEmbedder {
+    adapter: Arc<AiAdapter>,
+}
+
+impl AiEmbedder {
+    pub fn new(adapter: impl Into<AiAdapter>) -> Self {
+        Self {
+            adapter: Arc::new(adapter.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl EmbeddingModel for AiEmbedder {
+    async fn embed(&self, input: Vec<String>) -> Result<Embeddings> {
+        Ok(self.adapter.embed(&input).await?)
+    }
+}
+
+impl fmt::Debug for AiEmbedder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AiEmbedder").finish()
+    }
+}
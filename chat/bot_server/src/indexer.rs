@@ -19,21 +19,20 @@ async fn main() -> Result<()> {
     tracing_subscriber::registry().with(layer).init();
 
     let config = AppConfig::load()?;
-    let db_url = config.server.db_url;
+    let db_url = &config.server.db_url;
     let client = Ollama::default()
-        // ollama embed model: https://ollama.com/blog/embedding-models
-        .with_default_embed_model("mxbai-embed-large")
         .with_default_prompt_model("llama3.2")
         .to_owned();
-    let pool = PgPoolOptions::new().connect(&db_url).await?;
+    let embedder = config.ai.embedder().await?;
+    let pool = PgPoolOptions::new().connect(db_url).await?;
     let store = PgVector::try_new(pool, VECTOR_SIZE).await?;
     Pipeline::from_loader(FileLoader::new(".").with_extensions(&["rs"]))
-        .then(MetadataQACode::new(client.clone()))
+        .then(MetadataQACode::new(client))
         .then_chunk(ChunkCode::try_for_language_and_chunk_size(
             "rust",
             10..2048,
         )?)
-        .then_in_batch(Embed::new(client).with_batch_size(10))
+        .then_in_batch(Embed::new(embedder).with_batch_size(10))
         .then_store_with(store)
         .run()
         .await?;
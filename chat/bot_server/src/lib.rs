@@ -1,7 +1,9 @@
 mod config;
+mod embedder;
 mod notif;
 
 pub use config::*;
+pub use embedder::*;
 pub use notif::*;
 
 pub const VECTOR_SIZE: usize = 1024;
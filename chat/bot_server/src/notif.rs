@@ -41,15 +41,11 @@ pub async fn setup_pg_listener(config: &AppConfig) -> Result<()> {
     let mut listener = PgListener::connect(db_url).await?;
     listener.listen("chat_message_created").await?;
     info!("Listening to chat_message_created");
-    // let client = Ollama::default()
-    //     // ollama embed model: https://ollama.com/blog/embedding-models
-    //     .with_default_embed_model("mxbai-embed-large")
-    //     .with_default_prompt_model("llama3.2")
-    //     .to_owned();
     let client = OpenAI::builder()
-        .default_embed_model("text-embedding-3-small")
         .default_prompt_model("gpt-4o-mini")
         .build()?;
+    // queries must be embedded with the same model as the indexer
+    let embedder = config.ai.embedder().await?;
     let pool = PgPoolOptions::new().connect(db_url).await?;
     let bots = get_bots(&pool).await?;
     let mut stream = listener.into_stream();
@@ -58,10 +54,11 @@ pub async fn setup_pg_listener(config: &AppConfig) -> Result<()> {
             info!("Received notification: {:?}", notification);
             let pool = pool.clone();
             let client = client.clone();
+            let embedder = embedder.clone();
             tokio::spawn(async move {
                 let bot_id = notification.bot_id;
                 let user_id = notification.event.sender_id;
-                if let Err(e) = notification.process(&pool, client.clone(), client).await {
+                if let Err(e) = notification.process(&pool, client, embedder).await {
                     warn!(
                         "Bot {} failed to send notification to user {}: {}",
                         bot_id, user_id, e
@@ -137,9 +134,12 @@ impl Notification {
 }
 
 async fn get_bots(pool: &PgPool) -> Result<HashSet<i64>> {
+    // the bots of chat agents only post the replies of their agent
     let bots: Vec<(i64,)> = sqlx::query_as(
         r#"
-        SELECT id FROM users WHERE is_bot = TRUE
+        SELECT id FROM users
+        WHERE is_bot = TRUE
+        AND id NOT IN (SELECT bot_id FROM chat_agents WHERE bot_id IS NOT NULL)
     "#,
     )
     .fetch_all(pool)
@@ -1,10 +1,14 @@
+mod mention;
 mod utils;
 
 pub mod middlewares;
 
+pub use mention::*;
 use thiserror::Error;
 pub use utils::*;
 
+use std::time::Duration;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
@@ -44,6 +48,9 @@ pub struct ChatUser {
     pub id: i64,
     pub fullname: String,
     pub email: String,
+    #[sqlx(default)]
+    #[serde(default, alias = "isBot")]
+    pub is_bot: bool,
 }
 
 #[derive(Debug, ToSchema, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize)]
@@ -85,6 +92,9 @@ pub enum ChatType {
 #[sqlx(type_name = "agent_type", rename_all = "snake_case")]
 #[serde(rename_all(serialize = "camelCase"))]
 pub enum AgentType {
+    /// checks messages before any other agent, it can block, redact or flag them
+    #[serde(alias = "moderation", alias = "Moderation")]
+    Moderation,
     #[default]
     #[serde(alias = "proxy", alias = "Proxy")]
     Proxy,
@@ -95,7 +105,17 @@ pub enum AgentType {
 }
 
 #[derive(
-    Debug, Default, ToSchema, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, sqlx::Type,
+    Debug,
+    Default,
+    ToSchema,
+    Clone,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Hash,
+    sqlx::Type,
 )]
 #[sqlx(type_name = "adapter_type", rename_all = "snake_case")]
 #[serde(rename_all(serialize = "camelCase"))]
@@ -120,6 +140,11 @@ pub struct ChatAgent {
     pub model: String,
     pub prompt: String,
     pub args: sqlx::types::Json<serde_json::Value>, // TODO: change to custom type
+    /// the bot user the agent posts its replies as
+    #[serde(alias = "botId")]
+    pub bot_id: Option<i64>,
+    /// disabled agents don't run on new messages
+    pub enabled: bool,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
     #[serde(alias = "updatedAt")]
@@ -139,6 +164,35 @@ pub struct Message {
     pub files: Vec<String>,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
+    /// when the sender last edited the message
+    #[serde(default, alias = "editedAt")]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// a deleted message is a tombstone without content or files
+    #[serde(default, alias = "deletedAt")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// the message this one answers
+    #[serde(default, alias = "replyTo")]
+    pub reply_to: Option<i64>,
+    /// the root message of the thread this one is in
+    #[serde(default, alias = "threadId")]
+    pub thread_id: Option<i64>,
+    /// replies in the thread of this message
+    #[serde(default, alias = "replyCount")]
+    pub reply_count: i32,
+    /// reactions to the message, in the order they were first used
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub reactions: Vec<ReactionSummary>,
+}
+
+/// The users who reacted to a message with an emoji
+#[derive(Debug, ToSchema, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ReactionSummary {
+    pub emoji: String,
+    pub count: i64,
+    #[serde(alias = "userIds")]
+    pub user_ids: Vec<i64>,
 }
 
 #[allow(async_fn_in_trait)]
@@ -150,22 +204,71 @@ pub trait Agent {
 pub enum AgentDecision {
     Modify(String),
     Reply(String),
-    Delete,
+    /// the message must not be sent, with the reason given to the sender
+    Delete(String),
+    /// the message is sent with the offending parts removed
+    Redact(String),
+    /// the message is sent but kept for review, with the reason
+    Flag(String),
     None,
 }
 
 #[derive(Debug, Default, Clone)]
-pub struct AgentContext {}
+pub struct AgentContext {
+    /// images attached to the message, sent to the model along with the prompt
+    pub images: Vec<AgentImage>,
+    /// the workspace of the chat
+    pub workspace: Option<Workspace>,
+    /// the chat the message is sent to
+    pub chat: Option<Chat>,
+    /// the user who sent the message
+    pub sender: Option<User>,
+    /// members of the chat, to know who sent the messages in history
+    pub members: Vec<ChatUser>,
+    /// the last messages of the chat before this one, oldest first
+    pub history: Vec<Message>,
+}
+
+/// An image attached to a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentImage {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
 
 #[derive(Error, Debug)]
 pub enum AgentError {
-    #[error("Network error: {0}")]
-    Network(String),
+    /// the model provider failed, the service calling it tells why
+    #[error("{message}")]
+    Ai { kind: AiErrorKind, message: String },
 
     #[error("{0}")]
     AnyError(#[from] anyhow::Error),
 }
 
+/// Why a model provider failed, whatever the provider is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiErrorKind {
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    ContextTooLong,
+    Timeout,
+    /// the provider rejected or failed the request, or could not be reached
+    Upstream,
+    Other,
+}
+
+impl AgentContext {
+    /// The name of a chat member
+    pub fn sender_name(&self, user_id: i64) -> Option<&str> {
+        self.members
+            .iter()
+            .find(|u| u.id == user_id)
+            .map(|u| u.fullname.as_str())
+    }
+}
+
 impl User {
     pub fn new(id: i64, fullname: &str, email: &str) -> Self {
         Self {
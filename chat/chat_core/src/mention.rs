@@ -0,0 +1,117 @@
+use std::ops::Range;
+
+/// A mention in a message, `@` followed by a name made of letters, digits, `_` and `-`. It
+/// starts the message or follows a whitespace, so that emails are not mentions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention<'a> {
+    pub name: &'a str,
+    /// the bytes of the mention in the message, `@` included
+    pub range: Range<usize>,
+}
+
+/// The agents a message invokes by mentioning them, and what is left of the message for them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Invocation {
+    /// names of the mentioned agents as they are known, in the order they are mentioned
+    pub names: Vec<String>,
+    /// the message without the mentions of agents
+    pub content: String,
+}
+
+/// All the mentions in a message
+pub fn parse_mentions(content: &str) -> Vec<Mention<'_>> {
+    let mut mentions = vec![];
+    let mut prev: Option<char> = None;
+    for (i, c) in content.char_indices() {
+        if c == '@' && prev.is_none_or(char::is_whitespace) {
+            let start = i + c.len_utf8();
+            let len = content[start..]
+                .find(|c: char| !is_name_char(c))
+                .unwrap_or(content.len() - start);
+            if len > 0 {
+                mentions.push(Mention {
+                    name: &content[start..start + len],
+                    range: i..start + len,
+                });
+            }
+        }
+        prev = Some(c);
+    }
+    mentions
+}
+
+/// Find the agents mentioned in a message, names are matched case insensitively. Mentions of
+/// anything else, e.g. `@everyone`, are kept in the content.
+pub fn resolve_mentions<'a>(
+    content: &str,
+    agents: impl IntoIterator<Item = &'a str>,
+) -> Invocation {
+    let agents: Vec<_> = agents.into_iter().collect();
+    let mut names: Vec<String> = vec![];
+    let mut stripped = String::with_capacity(content.len());
+    let mut last = 0;
+    for mention in parse_mentions(content) {
+        let Some(agent) = agents
+            .iter()
+            .find(|name| name.eq_ignore_ascii_case(mention.name))
+        else {
+            continue;
+        };
+        if !names.iter().any(|name| name == agent) {
+            names.push(agent.to_string());
+        }
+        stripped.push_str(&content[last..mention.range.start]);
+        // drop the whitespace after the mention along with it
+        let rest = &content[mention.range.end..];
+        last = mention.range.end + (rest.len() - rest.trim_start().len()).min(1);
+    }
+    stripped.push_str(&content[last..]);
+    Invocation {
+        names,
+        content: stripped.trim().to_string(),
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mentions_should_work() {
+        let mentions = parse_mentions("@translator hi @bob, mail me at me@acme.org @ @");
+        let names: Vec<_> = mentions.iter().map(|m| m.name).collect();
+        assert_eq!(names, vec!["translator", "bob"]);
+        assert_eq!(mentions[0].range, 0..11);
+        assert_eq!(mentions[1].range, 15..19);
+        assert!(parse_mentions("no mention").is_empty());
+    }
+
+    #[test]
+    fn resolve_mentions_should_strip_agents() {
+        let agents = ["translator", "summarize"];
+        let invocation = resolve_mentions("@Translator bonjour", agents);
+        assert_eq!(invocation.names, vec!["translator"]);
+        assert_eq!(invocation.content, "bonjour");
+
+        let invocation = resolve_mentions("@summarize last 50", agents);
+        assert_eq!(invocation.names, vec!["summarize"]);
+        assert_eq!(invocation.content, "last 50");
+
+        let invocation = resolve_mentions("hey @everyone, @summarize this @summarize", agents);
+        assert_eq!(invocation.names, vec!["summarize"]);
+        assert_eq!(invocation.content, "hey @everyone, this");
+
+        let invocation = resolve_mentions("hello", agents);
+        assert_eq!(
+            invocation,
+            Invocation {
+                names: vec![],
+                content: "hello".to_string(),
+            }
+        );
+    }
+}
@@ -1,107 +1,585 @@
-use std::env;
-
-use ai_sdk::{AiAdapter, AiService, OllamaAdapter, OpenAIAdapter};
+use ai_sdk::{
+    run_tools, AiAdapter, AiError, AiService, Backend, CompletionOptions, CompletionResult,
+    ContextWindow, CostTier, FallbackAdapter, Overflow, TokenCounter, TruncationStrategy,
+};
 use chat_core::{
-    AdapterType, Agent, AgentContext, AgentDecision, AgentError, AgentType, ChatAgent,
+    AdapterType, Agent, AgentContext, AgentDecision, AgentError, AgentType, AiErrorKind, ChatAgent,
+};
+use serde::Deserialize;
+
+use crate::{
+    lookup::{lookup_tools, ChatLookup},
+    pipeline::{OnFailure, Trigger},
+    prompt::PromptTemplate,
+    AppState, TapTask,
 };
 
 pub enum AgentVariant {
+    Moderation(ModerationAgent),
     Proxy(ProxyAgent),
     Reply(ReplyAgent),
     Tap(TapAgent),
 }
 
+#[allow(unused)]
+pub struct ModerationAgent {
+    pub name: String,
+    pub adapter: AiAdapter,
+    /// the policy messages are checked against
+    pub prompt: PromptTemplate,
+    pub args: serde_json::Value,
+    pub options: CompletionOptions,
+    pub context: ContextWindow,
+    /// number of previous messages of the chat sent to the model
+    pub history: usize,
+}
+
 #[allow(unused)]
 pub struct ProxyAgent {
     pub name: String,
     pub adapter: AiAdapter,
-    pub prompt: String,
+    pub prompt: PromptTemplate,
     pub args: serde_json::Value,
+    pub options: CompletionOptions,
+    pub context: ContextWindow,
+    /// number of previous messages of the chat sent to the model
+    pub history: usize,
 }
 
 #[allow(unused)]
 pub struct ReplyAgent {
     pub name: String,
     pub adapter: AiAdapter,
-    pub prompt: String,
+    pub prompt: PromptTemplate,
     pub args: serde_json::Value,
+    pub options: CompletionOptions,
+    pub context: ContextWindow,
+    /// number of previous messages of the chat sent to the model
+    pub history: usize,
+    /// the bot user the replies are posted as
+    pub bot_id: Option<i64>,
+    /// looks up the chat with tools before replying when set, from `args.tools`
+    pub lookup: Option<AppState>,
 }
 
 #[allow(unused)]
 pub struct TapAgent {
     pub name: String,
     pub adapter: AiAdapter,
-    pub prompt: String,
+    pub prompt: PromptTemplate,
     pub args: serde_json::Value,
+    pub options: CompletionOptions,
+    pub context: ContextWindow,
+    /// number of previous messages of the chat sent to the model
+    pub history: usize,
+    pub task: TapTask,
+}
+
+/// A backend tried when the adapter of the agent fails, from `args.fallback`
+#[derive(Debug, Clone, Deserialize)]
+pub struct FallbackConfig {
+    pub adapter: AdapterType,
+    pub model: String,
+    pub max_prompt_len: Option<usize>,
+    pub cost_tier: Option<CostTier>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AgentArgs {
+    #[serde(default)]
+    fallback: Vec<FallbackConfig>,
+    max_prompt_len: Option<usize>,
+    cost_tier: Option<CostTier>,
+    max_input_tokens: Option<usize>,
+    #[serde(default)]
+    truncation: TruncationStrategy,
+    #[serde(default)]
+    overflow: Overflow,
+    history: Option<usize>,
+    on_failure: Option<OnFailure>,
+    #[serde(default)]
+    task: TapTask,
+    #[serde(default)]
+    trigger: Trigger,
+    #[serde(default)]
+    tools: bool,
+}
+
+/// Number of previous messages agents send to the model, unless `args.history` is set
+const DEFAULT_HISTORY: usize = 10;
+
+/// Rounds of tool calls a reply agent may make before it has to answer
+const MAX_TOOL_STEPS: usize = 5;
+
+/// The decision of an agent, with the completion it was made from so that the usage can be
+/// recorded
+#[derive(Debug)]
+pub struct AgentOutput {
+    pub decision: AgentDecision,
+    pub completion: Option<CompletionResult>,
+}
+
+impl ProxyAgent {
+    pub async fn run(&self, msg: &str, ctx: &AgentContext) -> Result<AgentOutput, AgentError> {
+        // previous messages and their modifications show the model what is expected
+        let history = recent(ctx, self.history).flat_map(|m| {
+            let mut turns = vec![ai_sdk::Message::user(&m.content)];
+            if let Some(modified) = &m.modified_content {
+                turns.push(ai_sdk::Message::assiatant(modified));
+            }
+            turns
+        });
+        let messages = conversation(&self.prompt, &self.args, msg, ctx, history)?;
+        let fitted = self
+            .context
+            .fit(&self.adapter, &messages, &self.options)
+            .await
+            .map_err(ai_error)?;
+        let mut res = self
+            .adapter
+            .complete(&fitted.messages, &self.options)
+            .await
+            .map_err(ai_error)?;
+        res.usage += fitted.usage;
+        Ok(AgentOutput {
+            decision: AgentDecision::Modify(res.content.clone()),
+            completion: Some(res),
+        })
+    }
+}
+
+impl ReplyAgent {
+    pub async fn run(&self, msg: &str, ctx: &AgentContext) -> Result<AgentOutput, AgentError> {
+        // the previous replies of the agent are the assistant's turns, the messages of the
+        // members are user turns, prefixed with their sender as there may be many of them
+        let history = recent(ctx, self.history).map(|m| {
+            if Some(m.sender_id) == self.bot_id {
+                return ai_sdk::Message::assiatant(&m.content);
+            }
+            match ctx.sender_name(m.sender_id) {
+                Some(sender) => ai_sdk::Message::user(format!("{}: {}", sender, m.content)),
+                None => ai_sdk::Message::user(&m.content),
+            }
+        });
+        let messages = conversation(&self.prompt, &self.args, msg, ctx, history)?;
+        let fitted = self
+            .context
+            .fit(&self.adapter, &messages, &self.options)
+            .await
+            .map_err(ai_error)?;
+        let mut res = match &self.lookup {
+            Some(state) => {
+                let handler = ChatLookup { state, ctx };
+                run_tools(
+                    &self.adapter,
+                    fitted.messages,
+                    &lookup_tools(),
+                    &handler,
+                    &self.options,
+                    MAX_TOOL_STEPS,
+                )
+                .await
+            }
+            None => self.adapter.complete(&fitted.messages, &self.options).await,
+        }
+        .map_err(ai_error)?;
+        res.usage += fitted.usage;
+        Ok(AgentOutput {
+            decision: AgentDecision::Reply(res.content.clone()),
+            completion: Some(res),
+        })
+    }
+}
+
+/// The error of a model provider as an agent error, chat_core doesn't know about the providers
+pub(crate) fn ai_error(e: AiError) -> AgentError {
+    AgentError::Ai {
+        kind: ai_error_kind(&e),
+        message: e.to_string(),
+    }
+}
+
+pub(crate) fn ai_error_kind(e: &AiError) -> AiErrorKind {
+    match e {
+        AiError::RateLimited { retry_after } => AiErrorKind::RateLimited {
+            retry_after: *retry_after,
+        },
+        AiError::ContextTooLong(_) => AiErrorKind::ContextTooLong,
+        AiError::Timeout => AiErrorKind::Timeout,
+        AiError::AuthFailed(_)
+        | AiError::BadRequest { .. }
+        | AiError::Upstream { .. }
+        | AiError::Network(_)
+        | AiError::MalformedResponse(_) => AiErrorKind::Upstream,
+        AiError::Other(_) => AiErrorKind::Other,
+    }
+}
+
+/// The conversation sent to the model. A prompt using `{{ message }}` is the last user turn,
+/// otherwise it is the system prompt and the message is the last user turn.
+fn conversation(
+    prompt: &PromptTemplate,
+    args: &serde_json::Value,
+    msg: &str,
+    ctx: &AgentContext,
+    history: impl Iterator<Item = ai_sdk::Message>,
+) -> anyhow::Result<Vec<ai_sdk::Message>> {
+    let rendered = prompt.render(args, msg, ctx)?;
+    let (system, last) = if prompt.has_message() {
+        (None, rendered)
+    } else {
+        (Some(ai_sdk::Message::system(rendered)), msg.to_string())
+    };
+    let images = ctx
+        .images
+        .iter()
+        .map(|image| ai_sdk::Image::data(&image.mime_type, image.data.clone()));
+    let last = ai_sdk::Message::user(last).with_images(images);
+    Ok(system.into_iter().chain(history).chain([last]).collect())
+}
+
+/// The last `n` messages of the chat, oldest first
+fn recent(ctx: &AgentContext, n: usize) -> impl Iterator<Item = &chat_core::Message> {
+    ctx.history[ctx.history.len().saturating_sub(n)..].iter()
+}
+
+/// The last `n` messages of the chat as user turns prefixed with their sender, for agents which
+/// observe the conversation instead of taking part in it
+fn transcript(ctx: &AgentContext, n: usize) -> impl Iterator<Item = ai_sdk::Message> + '_ {
+    recent(ctx, n).map(|m| {
+        let sender = ctx.sender_name(m.sender_id).unwrap_or_default();
+        ai_sdk::Message::user(format!("{}: {}", sender, m.content))
+    })
+}
+
+const MODERATION_INSTRUCTION: &str = "You moderate the messages of a chat. Check the last \
+message against the policy and reply with a JSON object only, e.g. \
+{\"action\": \"flag\", \"reason\": \"...\"}. The action is one of: allow, block when the \
+message must not be sent, redact when some parts of it must not be shown, with the message \
+where those parts are replaced by *** as \"content\", or flag when an admin should review it. \
+The reason is shown to the sender.";
+
+/// The verdict of a moderation agent, parsed from its completion
+#[derive(Debug, Deserialize)]
+struct Verdict {
+    action: ModerationAction,
+    #[serde(default)]
+    reason: String,
+    content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ModerationAction {
+    Allow,
+    Block,
+    Redact,
+    Flag,
+}
+
+impl ModerationAgent {
+    pub async fn run(&self, msg: &str, ctx: &AgentContext) -> Result<AgentOutput, AgentError> {
+        let history = transcript(ctx, self.history);
+        let mut messages = conversation(&self.prompt, &self.args, msg, ctx, history)?;
+        messages.retain(|m| !m.content.is_empty() || !m.images.is_empty());
+        messages.insert(0, ai_sdk::Message::system(MODERATION_INSTRUCTION));
+        let fitted = self
+            .context
+            .fit(&self.adapter, &messages, &self.options)
+            .await
+            .map_err(ai_error)?;
+        let mut res = self
+            .adapter
+            .complete(&fitted.messages, &self.options)
+            .await
+            .map_err(ai_error)?;
+        res.usage += fitted.usage;
+        Ok(AgentOutput {
+            decision: Verdict::parse(&res.content)?.into(),
+            completion: Some(res),
+        })
+    }
+}
+
+impl Verdict {
+    /// Models often wrap the json in a code block or add some words around it
+    fn parse(content: &str) -> anyhow::Result<Self> {
+        let json = match (content.find('{'), content.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &content[start..=end],
+            _ => content,
+        };
+        let verdict: Verdict = serde_json::from_str(json)
+            .map_err(|e| anyhow::anyhow!("invalid moderation verdict {:?}: {}", content, e))?;
+        if matches!(verdict.action, ModerationAction::Redact) && verdict.content.is_none() {
+            anyhow::bail!("moderation verdict {:?} redacts without content", content);
+        }
+        Ok(verdict)
+    }
+}
+
+impl From<Verdict> for AgentDecision {
+    fn from(verdict: Verdict) -> Self {
+        let reason = if verdict.reason.is_empty() {
+            "the message violates the content policy".to_string()
+        } else {
+            verdict.reason
+        };
+        match verdict.action {
+            ModerationAction::Allow => AgentDecision::None,
+            ModerationAction::Block => AgentDecision::Delete(reason),
+            ModerationAction::Redact => AgentDecision::Redact(verdict.content.unwrap_or_default()),
+            ModerationAction::Flag => AgentDecision::Flag(reason),
+        }
+    }
+}
+
+// Tap agents don't change the conversation, they are run by the job workers after the message
+// is sent and their completion is stored as the result of their task
+impl TapAgent {
+    pub async fn run(&self, msg: &str, ctx: &AgentContext) -> Result<AgentOutput, AgentError> {
+        let history = transcript(ctx, self.history);
+        let mut messages = conversation(&self.prompt, &self.args, msg, ctx, history)?;
+        // an agent with a builtin task may have no prompt of its own
+        messages.retain(|m| !m.content.is_empty() || !m.images.is_empty());
+        if let Some(instruction) = self.task.instruction() {
+            messages.insert(0, ai_sdk::Message::system(instruction));
+        }
+        let fitted = self
+            .context
+            .fit(&self.adapter, &messages, &self.options)
+            .await
+            .map_err(ai_error)?;
+        let mut res = self
+            .adapter
+            .complete(&fitted.messages, &self.options)
+            .await
+            .map_err(ai_error)?;
+        res.usage += fitted.usage;
+        Ok(AgentOutput {
+            decision: AgentDecision::None,
+            completion: Some(res),
+        })
+    }
+}
+
+impl TapTask {
+    /// The system prompt of a builtin task
+    fn instruction(&self) -> Option<&'static str> {
+        match self {
+            TapTask::Summarize => Some(
+                "Summarize the last message of the conversation in one or two sentences. \
+                Reply with the summary only.",
+            ),
+            TapTask::Tag => Some(
+                "Tag the last message of the conversation with a few short topics. \
+                Reply with the tags only, separated by commas.",
+            ),
+            TapTask::ActionItems => Some(
+                "List the action items in the last message of the conversation, one per line, \
+                with the person responsible when it is mentioned. Reply with the list only, or \
+                with nothing if there is no action item.",
+            ),
+            TapTask::Custom => None,
+        }
+    }
+}
+
+impl AgentVariant {
+    /// Same as [`Agent::process`], but also returns the completion made by the agent
+    pub async fn run(&self, msg: &str, ctx: &AgentContext) -> Result<AgentOutput, AgentError> {
+        match self {
+            AgentVariant::Moderation(agent) => agent.run(msg, ctx).await,
+            AgentVariant::Proxy(agent) => agent.run(msg, ctx).await,
+            AgentVariant::Reply(agent) => agent.run(msg, ctx).await,
+            AgentVariant::Tap(agent) => agent.run(msg, ctx).await,
+        }
+    }
+}
+
+impl Agent for ModerationAgent {
+    async fn process(&self, msg: &str, ctx: &AgentContext) -> Result<AgentDecision, AgentError> {
+        Ok(self.run(msg, ctx).await?.decision)
+    }
 }
 
 impl Agent for ProxyAgent {
-    async fn process(&self, msg: &str, _ctx: &AgentContext) -> Result<AgentDecision, AgentError> {
-        // If we need it to be flexible: prompt is a jinja2 template, and args is a json
-        let prompt = format!("{} {}", self.prompt, msg);
-        let messages = vec![ai_sdk::Message::user(prompt)];
-        let res = self.adapter.complete(&messages).await?;
-        Ok(AgentDecision::Modify(res))
+    async fn process(&self, msg: &str, ctx: &AgentContext) -> Result<AgentDecision, AgentError> {
+        Ok(self.run(msg, ctx).await?.decision)
     }
 }
 
 impl Agent for ReplyAgent {
-    async fn process(&self, msg: &str, _ctx: &AgentContext) -> Result<AgentDecision, AgentError> {
-        // If we need it to be flexible: prompt is a jinja2 template, and args is a json
-        let prompt = format!("{} {}", self.prompt, msg);
-        let messages = vec![ai_sdk::Message::user(prompt)];
-        let res = self.adapter.complete(&messages).await?;
-        Ok(AgentDecision::Reply(res))
+    async fn process(&self, msg: &str, ctx: &AgentContext) -> Result<AgentDecision, AgentError> {
+        Ok(self.run(msg, ctx).await?.decision)
     }
 }
 
-// In future, we should push message into a queue and process in a delayed manner
 impl Agent for TapAgent {
-    async fn process(&self, _msg: &str, _ctx: &AgentContext) -> Result<AgentDecision, AgentError> {
-        Ok(AgentDecision::None)
+    async fn process(&self, msg: &str, ctx: &AgentContext) -> Result<AgentDecision, AgentError> {
+        Ok(self.run(msg, ctx).await?.decision)
     }
 }
 
 impl Agent for AgentVariant {
     async fn process(&self, msg: &str, ctx: &AgentContext) -> Result<AgentDecision, AgentError> {
-        match self {
-            AgentVariant::Proxy(agent) => agent.process(msg, ctx).await,
-            AgentVariant::Reply(agent) => agent.process(msg, ctx).await,
-            AgentVariant::Tap(agent) => agent.process(msg, ctx).await,
-        }
+        Ok(self.run(msg, ctx).await?.decision)
     }
 }
 
-impl From<ChatAgent> for AgentVariant {
-    fn from(mut agent: ChatAgent) -> Self {
-        let adapter: AiAdapter = match agent.adapter {
-            AdapterType::Ollama => OllamaAdapter::new_local(agent.model).into(),
-            AdapterType::OpenAI => {
-                let api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
-                OpenAIAdapter::new(api_key, agent.model).into()
-            }
-        };
+impl AgentVariant {
+    /// Create the agent with the adapter resolved from config
+    pub fn try_new(mut agent: ChatAgent, state: &AppState) -> Result<Self, AgentError> {
+        let adapter = agent_adapter(&agent, state)?;
+        let options = completion_options(&agent.args)?;
+        let context = context_window(&agent.args, adapter.model(), &options)?;
+        let prompt = PromptTemplate::new(agent.prompt)?;
+        let agent_args = agent_args(&agent.args)?;
+        let history = agent_args.history.unwrap_or(DEFAULT_HISTORY);
 
-        match agent.r#type {
+        let agent = match agent.r#type {
+            AgentType::Moderation => AgentVariant::Moderation(ModerationAgent {
+                name: agent.name,
+                adapter,
+                prompt,
+                args: agent.args.take(),
+                options,
+                context,
+                history,
+            }),
             AgentType::Proxy => AgentVariant::Proxy(ProxyAgent {
                 name: agent.name,
                 adapter,
-                prompt: agent.prompt,
+                prompt,
                 args: agent.args.take(),
+                options,
+                context,
+                history,
             }),
             AgentType::Reply => AgentVariant::Reply(ReplyAgent {
                 name: agent.name,
                 adapter,
-                prompt: agent.prompt,
+                prompt,
                 args: agent.args.take(),
+                options,
+                context,
+                history,
+                bot_id: agent.bot_id,
+                lookup: agent_args.tools.then(|| state.clone()),
             }),
             AgentType::Tap => AgentVariant::Tap(TapAgent {
                 name: agent.name,
                 adapter,
-                prompt: agent.prompt,
+                prompt,
                 args: agent.args.take(),
+                options,
+                context,
+                history,
+                task: agent_args.task,
             }),
-        }
+        };
+        Ok(agent)
+    }
+}
+
+/// Check the args of an agent can be used to create it
+pub fn validate_args(args: &serde_json::Value) -> anyhow::Result<()> {
+    completion_options(args)?;
+    agent_args(args)?;
+    Ok(())
+}
+
+/// The adapter of the agent. If `args.fallback` is set, the agent fails over to those backends
+/// in order, e.g. `{"fallback": [{"adapter": "openai", "model": "gpt-4o-mini"}]}`. The agent's
+/// own adapter can be limited with `max_prompt_len` and `cost_tier` in args as well. Failovers
+/// are counted in the metrics of the state, adapters only live as long as a message.
+fn agent_adapter(agent: &ChatAgent, state: &AppState) -> anyhow::Result<AiAdapter> {
+    let config = &state.config.ai;
+    let adapter = config.adapter(&agent.adapter, &agent.model)?;
+    let args = agent_args(&agent.args)?;
+    if args.fallback.is_empty() {
+        return Ok(adapter);
+    }
+
+    let primary = backend(adapter, args.max_prompt_len, args.cost_tier);
+    let mut adapter = FallbackAdapter::new(primary).metrics(state.fallbacks.clone());
+    for fallback in args.fallback {
+        let backend = backend(
+            config.adapter(&fallback.adapter, fallback.model)?,
+            fallback.max_prompt_len,
+            fallback.cost_tier,
+        );
+        adapter = adapter.fallback(backend);
+    }
+    Ok(adapter.into())
+}
+
+/// What the pipeline does when the agent fails, `args.on_failure` or the default of its type
+/// when it isn't set or the args are invalid
+pub fn failure_policy(agent_type: &AgentType, args: &serde_json::Value) -> OnFailure {
+    agent_args(args)
+        .ok()
+        .and_then(|args| args.on_failure)
+        .unwrap_or_else(|| OnFailure::default_for(agent_type))
+}
+
+/// Whether the agent runs on every message or only when it is mentioned, `args.trigger`
+pub fn trigger(args: &serde_json::Value) -> anyhow::Result<Trigger> {
+    Ok(agent_args(args)?.trigger)
+}
+
+fn agent_args(args: &serde_json::Value) -> anyhow::Result<AgentArgs> {
+    if !args.is_object() {
+        return Ok(AgentArgs::default());
+    }
+    Ok(AgentArgs::deserialize(args)?)
+}
+
+fn backend(
+    adapter: AiAdapter,
+    max_prompt_len: Option<usize>,
+    cost_tier: Option<CostTier>,
+) -> Backend {
+    let mut backend = Backend::new(adapter);
+    if let Some(len) = max_prompt_len {
+        backend = backend.max_prompt_len(len);
+    }
+    if let Some(tier) = cost_tier {
+        backend = backend.cost_tier(tier);
+    }
+    backend
+}
+
+/// The prompt of an agent has to fit in the context window of its model, minus the tokens
+/// reserved for the completion. It can be made smaller with `max_input_tokens` in args. Longer
+/// prompts are rejected unless `overflow` is `trim`, and the history of a conversation is
+/// truncated with `truncation`, e.g. `{"truncation": {"keep_last": 10}}`.
+fn context_window(
+    args: &serde_json::Value,
+    model: &str,
+    options: &CompletionOptions,
+) -> anyhow::Result<ContextWindow> {
+    let args = agent_args(args)?;
+    let window = match args.max_input_tokens {
+        Some(n) => ContextWindow::new(TokenCounter::for_model(model), n),
+        None => ContextWindow::for_model(model, options),
+    };
+    Ok(window.strategy(args.truncation).overflow(args.overflow))
+}
+
+/// Generation options of an agent are the top level fields of its args, e.g.
+/// `{"temperature": 0.2, "max_tokens": 256}`. Other fields are ignored.
+pub fn completion_options(args: &serde_json::Value) -> anyhow::Result<CompletionOptions> {
+    if !args.is_object() {
+        return Ok(CompletionOptions::default());
+    }
+    let options: CompletionOptions = serde_json::from_value(args.clone())?;
+    options.validate()?;
+    Ok(options)
+}
+
+impl From<ModerationAgent> for AgentVariant {
+    fn from(agent: ModerationAgent) -> Self {
+        AgentVariant::Moderation(agent)
     }
 }
 
@@ -127,16 +605,16 @@ impl From<TapAgent> for AgentVariant {
 mod tests {
     use crate::AppState;
     use anyhow::Result;
+    use chat_core::AgentImage;
 
     use super::*;
 
-    #[ignore]
     #[tokio::test]
     async fn agent_variant_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let agents = state.list_agents(1).await?;
         let agent = agents[0].clone();
-        let agent: AgentVariant = agent.into();
+        let agent = AgentVariant::try_new(agent, &state)?;
         let msg = "Hello";
         let decision = agent.process(msg, &AgentContext::default()).await?;
         // test if it is modify
@@ -146,4 +624,267 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn completion_options_should_read_agent_args() -> Result<()> {
+        let options = completion_options(&serde_json::json!({
+            "temperature": 0.5,
+            "max_tokens": 64,
+            "language": "en"
+        }))?;
+        assert_eq!(
+            options,
+            CompletionOptions::default().temperature(0.5).max_tokens(64)
+        );
+        assert_eq!(
+            completion_options(&serde_json::json!("{}"))?,
+            CompletionOptions::default()
+        );
+        assert!(completion_options(&serde_json::json!({ "temperature": "hot" })).is_err());
+        assert!(completion_options(&serde_json::json!({ "top_p": 2 })).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn agent_with_fallback_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut agent = state.list_agents(1).await?.remove(0);
+        agent.args = sqlx::types::Json(serde_json::json!({
+            "max_prompt_len": 1000,
+            "fallback": [{ "adapter": "openai", "model": "gpt-4o-mini", "cost_tier": "medium" }]
+        }));
+        let adapter = agent_adapter(&agent, &state)?;
+        assert!(matches!(adapter, AiAdapter::Fallback(_)));
+        assert_eq!(adapter.model(), "llama3.2");
+
+        let agent = AgentVariant::try_new(agent, &state)?;
+        let decision = agent.process("Hello", &AgentContext::default()).await?;
+        assert!(matches!(decision, AgentDecision::Modify(_)));
+        // counted by the state, not by the adapter of the agent
+        let stats = state.fallback_stats();
+        assert_eq!(stats.failovers, 0);
+        assert_eq!(stats.backends[0].provider, "mock");
+        assert_eq!(stats.backends[0].model, "llama3.2");
+        assert_eq!(stats.backends[0].requests, 1);
+
+        assert!(
+            validate_args(&serde_json::json!({ "fallback": [{ "adapter": "gemini" }] })).is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_agent_should_send_images() -> Result<()> {
+        let agent = ProxyAgent {
+            name: "caption".to_string(),
+            adapter: ai_sdk::MockAdapter::new("llava").reply("a cat").into(),
+            prompt: PromptTemplate::new("Caption the image:")?,
+            args: serde_json::json!({}),
+            options: CompletionOptions::default(),
+            context: ContextWindow::new(TokenCounter::for_model("llava"), 1000),
+            history: 0,
+        };
+        let ctx = AgentContext {
+            images: vec![AgentImage {
+                mime_type: "image/png".to_string(),
+                data: b"png".to_vec(),
+            }],
+            ..Default::default()
+        };
+        let output = agent.run("", &ctx).await?;
+        assert!(matches!(output.decision, AgentDecision::Modify(ref s) if s == "a cat"));
+
+        let AiAdapter::Mock(adapter) = &agent.adapter else {
+            panic!("adapter should be the mock");
+        };
+        let messages = &adapter.requests()[0];
+        assert_eq!(messages[0].content, "Caption the image:");
+        assert_eq!(
+            messages[1].images,
+            vec![ai_sdk::Image::data("image/png", b"png".to_vec())]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn agent_should_reject_or_trim_long_input() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut agent = state.list_agents(1).await?.remove(0);
+        let msg = "word ".repeat(100);
+
+        agent.args = sqlx::types::Json(serde_json::json!({ "max_input_tokens": 50 }));
+        let variant = AgentVariant::try_new(agent.clone(), &state)?;
+        let ret = variant.process(&msg, &AgentContext::default()).await;
+        assert!(matches!(
+            ret,
+            Err(AgentError::Ai {
+                kind: AiErrorKind::ContextTooLong,
+                ..
+            })
+        ));
+
+        agent.args = sqlx::types::Json(serde_json::json!({
+            "max_input_tokens": 50,
+            "overflow": "trim"
+        }));
+        let variant = AgentVariant::try_new(agent, &state)?;
+        let ret = variant.process(&msg, &AgentContext::default()).await?;
+        let AgentDecision::Modify(content) = ret else {
+            panic!("proxy agent should modify the message");
+        };
+        assert!(content.len() < msg.len());
+
+        assert!(validate_args(&serde_json::json!({ "truncation": "keep_first" })).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reply_agent_should_send_conversation() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let agent = ReplyAgent {
+            name: "assistant".to_string(),
+            adapter: ai_sdk::MockAdapter::new("llama3.2").reply("Paris").into(),
+            prompt: PromptTemplate::new("You are {{ sender }}'s assistant in {{ workspace }}")?,
+            args: serde_json::json!({}),
+            options: CompletionOptions::default(),
+            context: ContextWindow::new(TokenCounter::for_model("llama3.2"), 1000),
+            history: 3,
+            bot_id: Some(3),
+            lookup: None,
+        };
+        let ctx = AgentContext {
+            sender: state.find_user_by_id(1).await?,
+            workspace: state.find_workspace_by_id(1).await?,
+            history: state
+                .list_message(
+                    crate::ListMessage {
+                        last_id: None,
+                        limit: 10,
+                    },
+                    1,
+                )
+                .await?
+                .into_iter()
+                .rev()
+                .collect(),
+            ..Default::default()
+        };
+        let output = agent.run("Where do I live?", &ctx).await?;
+        assert!(matches!(output.decision, AgentDecision::Reply(ref s) if s == "Paris"));
+
+        let AiAdapter::Mock(adapter) = &agent.adapter else {
+            panic!("adapter should be the mock");
+        };
+        let messages = &adapter.requests()[0];
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[0].role, ai_sdk::Role::System);
+        assert_eq!(
+            messages[0].content,
+            "You are Startdusk Shelby's assistant in acme"
+        );
+        // members are unknown in the context, so user turns are not prefixed with a sender
+        for (m, h) in messages[1..4].iter().zip(&ctx.history[7..]) {
+            assert_eq!(m.content, h.content);
+            let role = if h.sender_id == 3 {
+                ai_sdk::Role::Assistant
+            } else {
+                ai_sdk::Role::User
+            };
+            assert_eq!(m.role, role);
+        }
+        assert_eq!(messages[4].content, "Where do I live?");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reply_agent_should_look_up_the_chat() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let call = |id: &str, name: &str, arguments: serde_json::Value| ai_sdk::ToolCall {
+            id: id.to_string(),
+            name: name.to_string(),
+            arguments,
+        };
+        let adapter = ai_sdk::MockAdapter::new("llama3.2")
+            .reply_tool_calls(vec![
+                call(
+                    "call_1",
+                    "search_messages",
+                    serde_json::json!({ "query": "doing" }),
+                ),
+                call("call_2", "find_user", serde_json::json!({ "name": "ben" })),
+            ])
+            .reply("Ben asked how you are doing");
+        let agent = ReplyAgent {
+            name: "assistant".to_string(),
+            adapter: adapter.into(),
+            prompt: PromptTemplate::new("You are a helpful assistant")?,
+            args: serde_json::json!({ "tools": true }),
+            options: CompletionOptions::default(),
+            context: ContextWindow::new(TokenCounter::for_model("llama3.2"), 1000),
+            history: 0,
+            bot_id: None,
+            lookup: Some(state.clone()),
+        };
+        let ctx = state.agent_context(1, 1, &[], None, None).await?;
+        let output = agent.run("What did Ben ask?", &ctx).await?;
+        assert!(
+            matches!(output.decision, AgentDecision::Reply(ref s) if s == "Ben asked how you are doing")
+        );
+        // the usage of both rounds is recorded, 2 tool calls and a 6 words answer
+        let completion = output
+            .completion
+            .expect("tool calls should have a completion");
+        assert_eq!(completion.usage.completion_tokens, 8);
+
+        let AiAdapter::Mock(adapter) = &agent.adapter else {
+            panic!("adapter should be the mock");
+        };
+        let requests = adapter.requests();
+        assert_eq!(requests.len(), 2);
+        let results: Vec<_> = requests[1]
+            .iter()
+            .filter(|m| m.role == ai_sdk::Role::Tool)
+            .collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0]
+            .content
+            .ends_with("Ben Chole: How are you doing?"));
+        assert_eq!(results[1].content, "#3 Ben Chole");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn moderation_agent_should_parse_verdicts() -> Result<()> {
+        let agent = |reply: &str| ModerationAgent {
+            name: "moderator".to_string(),
+            adapter: ai_sdk::MockAdapter::new("llama3.2").reply(reply).into(),
+            prompt: PromptTemplate::new("No spam").unwrap(),
+            args: serde_json::json!({}),
+            options: CompletionOptions::default(),
+            context: ContextWindow::new(TokenCounter::for_model("llama3.2"), 1000),
+            history: 0,
+        };
+        let ctx = AgentContext::default();
+
+        let decision = agent(r#"{"action": "allow"}"#).process("hi", &ctx).await?;
+        assert!(matches!(decision, AgentDecision::None));
+
+        let reply = "```json\n{\"action\": \"block\", \"reason\": \"spam\"}\n```";
+        let decision = agent(reply).process("buy now", &ctx).await?;
+        assert!(matches!(decision, AgentDecision::Delete(ref r) if r == "spam"));
+
+        let reply = r#"{"action": "redact", "reason": "phone", "content": "call ***"}"#;
+        let decision = agent(reply).process("call 555", &ctx).await?;
+        assert!(matches!(decision, AgentDecision::Redact(ref s) if s == "call ***"));
+
+        let decision = agent(r#"{"action": "flag"}"#).process("hmm", &ctx).await?;
+        assert!(matches!(decision, AgentDecision::Flag(_)));
+
+        assert!(agent("looks fine").process("hi", &ctx).await.is_err());
+        assert!(agent(r#"{"action": "redact"}"#)
+            .process("hi", &ctx)
+            .await
+            .is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use ai_sdk::ModelService;
+use chat_core::{AdapterType, AgentError, AiErrorKind};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{agent::ai_error_kind, AppError, AppState};
+
+const DEFAULT_MODELS_TTL_SECS: u64 = 300;
+const FAILED_LISTING_TTL_SECS: u64 = 5;
+
+/// Models listed by the providers, cached so that creating agents and filling the model picker
+/// don't ask the provider every time. A failure to list them is cached for a few seconds only,
+/// so that a provider being down isn't asked on every request but a blip doesn't outlast it.
+pub struct ModelCatalog {
+    ttl: Duration,
+    failed_ttl: Duration,
+    entries: RwLock<HashMap<AdapterType, (Instant, Listing)>>,
+}
+
+/// The models of a provider, or why they couldn't be listed
+type Listing = Result<Vec<String>, (AiErrorKind, String)>;
+
+#[derive(Debug, IntoParams, ToSchema, Clone, Serialize, Deserialize)]
+pub struct ListModels {
+    pub adapter: AdapterType,
+}
+
+/// Requests and failures of the fallback backends of the agents since the server started
+#[derive(Debug, ToSchema, Clone, Serialize, Deserialize)]
+pub struct FallbackStats {
+    /// requests which failed on a backend and were sent to the next one
+    pub failovers: u64,
+    pub backends: Vec<FallbackBackend>,
+}
+
+#[derive(Debug, ToSchema, Clone, Serialize, Deserialize)]
+pub struct FallbackBackend {
+    pub provider: String,
+    pub host: Option<String>,
+    pub model: String,
+    pub requests: u64,
+    pub failures: u64,
+}
+
+impl ModelCatalog {
+    pub fn new(ttl_secs: Option<u64>) -> Self {
+        let ttl = Duration::from_secs(ttl_secs.unwrap_or(DEFAULT_MODELS_TTL_SECS));
+        Self {
+            ttl,
+            failed_ttl: ttl.min(Duration::from_secs(FAILED_LISTING_TTL_SECS)),
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, adapter: &AdapterType) -> Option<Listing> {
+        let entries = self.entries.read().unwrap();
+        let (at, listing) = entries.get(adapter)?;
+        let ttl = if listing.is_ok() {
+            self.ttl
+        } else {
+            self.failed_ttl
+        };
+        (at.elapsed() < ttl).then(|| listing.clone())
+    }
+
+    fn insert(&self, adapter: AdapterType, listing: Listing) {
+        let mut entries = self.entries.write().unwrap();
+        entries.insert(adapter, (Instant::now(), listing));
+    }
+}
+
+impl AppState {
+    /// Models available on an adapter, sorted
+    pub async fn list_models(&self, adapter: &AdapterType) -> Result<Vec<String>, AppError> {
+        let listing = match self.models.get(adapter) {
+            Some(listing) => listing,
+            None => {
+                // the model of the adapter doesn't matter to list them
+                let listing = self
+                    .config
+                    .ai
+                    .adapter(adapter, "")?
+                    .list_models()
+                    .await
+                    .map_err(|e| {
+                        warn!("failed to list the models of {:?}: {}", adapter, e);
+                        (ai_error_kind(&e), e.to_string())
+                    });
+                self.models.insert(adapter.clone(), listing.clone());
+                listing
+            }
+        };
+        listing.map_err(|(kind, message)| AgentError::Ai { kind, message }.into())
+    }
+
+    /// Requests and failures of the backends the agents failed over between
+    pub fn fallback_stats(&self) -> FallbackStats {
+        let backends = self
+            .fallbacks
+            .stats()
+            .into_iter()
+            .map(|s| FallbackBackend {
+                provider: s.provider,
+                host: s.host,
+                model: s.model,
+                requests: s.requests,
+                failures: s.failures,
+            })
+            .collect();
+        FallbackStats {
+            failovers: self.fallbacks.failovers(),
+            backends,
+        }
+    }
+
+    /// Whether a model is available on an adapter. Ollama models may be named without their
+    /// `:latest` tag. It fails when the models of the adapter can't be listed, as the model
+    /// can't be verified then.
+    pub async fn is_model_available(
+        &self,
+        adapter: &AdapterType,
+        model: &str,
+    ) -> Result<bool, AppError> {
+        let models = self.list_models(adapter).await.map_err(|e| match e {
+            AppError::AiAgentError(AgentError::Ai { kind, message }) => AgentError::Ai {
+                kind,
+                message: format!(
+                    "cannot verify that model {} is available: {}",
+                    model, message
+                ),
+            }
+            .into(),
+            e => e,
+        })?;
+        Ok(models
+            .iter()
+            .any(|name| name == model || name.strip_suffix(":latest") == Some(model)))
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    pub(crate) fn set_models(&self, adapter: AdapterType, models: &[&str]) {
+        let models = models.iter().map(|m| m.to_string()).collect();
+        self.models.insert(adapter, Ok(models));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn catalog_entries_should_expire() {
+        let catalog = ModelCatalog::new(Some(0));
+        catalog.insert(AdapterType::Ollama, Ok(vec!["llama3.2:latest".to_string()]));
+        assert_eq!(catalog.get(&AdapterType::Ollama), None);
+
+        let catalog = ModelCatalog::new(None);
+        catalog.insert(AdapterType::Ollama, Ok(vec!["llama3.2:latest".to_string()]));
+        assert_eq!(
+            catalog.get(&AdapterType::Ollama),
+            Some(Ok(vec!["llama3.2:latest".to_string()]))
+        );
+        assert_eq!(catalog.get(&AdapterType::OpenAI), None);
+    }
+
+    #[test]
+    fn failed_listings_should_expire_sooner() {
+        let catalog = ModelCatalog::new(None);
+        let at = Instant::now() - Duration::from_secs(FAILED_LISTING_TTL_SECS + 1);
+        {
+            let mut entries = catalog.entries.write().unwrap();
+            entries.insert(
+                AdapterType::Ollama,
+                (at, Ok(vec!["llama3.2:latest".to_string()])),
+            );
+            entries.insert(
+                AdapterType::OpenAI,
+                (
+                    at,
+                    Err((AiErrorKind::Upstream, "connection refused".to_string())),
+                ),
+            );
+        }
+        assert!(catalog.get(&AdapterType::Ollama).is_some());
+        assert_eq!(catalog.get(&AdapterType::OpenAI), None);
+
+        catalog.insert(
+            AdapterType::OpenAI,
+            Err((AiErrorKind::Upstream, "connection refused".to_string())),
+        );
+        assert!(catalog.get(&AdapterType::OpenAI).is_some());
+    }
+
+    #[tokio::test]
+    async fn is_model_available_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // listed by the mock adapter of the tests
+        assert!(
+            state
+                .is_model_available(&AdapterType::OpenAI, "gpt-4o-mini")
+                .await?
+        );
+        assert!(
+            !state
+                .is_model_available(&AdapterType::OpenAI, "gpt-9")
+                .await?
+        );
+
+        state.set_models(AdapterType::Ollama, &["llama3.2:latest", "qwen2.5:7b"]);
+        assert!(
+            state
+                .is_model_available(&AdapterType::Ollama, "llama3.2")
+                .await?
+        );
+        assert!(
+            state
+                .is_model_available(&AdapterType::Ollama, "qwen2.5:7b")
+                .await?
+        );
+        assert!(
+            !state
+                .is_model_available(&AdapterType::Ollama, "qwen2.5")
+                .await?
+        );
+        assert!(
+            !state
+                .is_model_available(&AdapterType::Ollama, "mistral")
+                .await?
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unlisted_models_should_not_be_available() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.models.insert(
+            AdapterType::OpenAI,
+            Err((AiErrorKind::Upstream, "connection refused".to_string())),
+        );
+        let err = state
+            .is_model_available(&AdapterType::OpenAI, "gpt-4o")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("cannot verify that model gpt-4o"));
+        let response = axum::response::IntoResponse::into_response(err);
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_GATEWAY);
+        Ok(())
+    }
+}
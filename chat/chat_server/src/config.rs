@@ -1,11 +1,19 @@
+use ai_sdk::{AiAdapter, MockAdapter, ReplayAdapter, ReplayMode, RetryPolicy, Usage};
 use anyhow::{bail, Result};
+use chat_core::AdapterType;
 use serde::{Deserialize, Serialize};
-use std::{env, fs::File, path::PathBuf};
+use std::{collections::HashMap, env, fs::File, path::PathBuf};
+
+pub use ai_sdk::{OllamaConfig, OpenAIConfig};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub ai: AiConfig,
+    #[serde(default)]
+    pub jobs: JobConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,6 +29,63 @@ pub struct ServerConfig {
     pub base_dir: PathBuf,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AiConfig {
+    #[serde(default)]
+    pub openai: OpenAIConfig,
+    #[serde(default)]
+    pub ollama: OllamaConfig,
+    /// how failed requests to the ai providers are retried
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// price of the models, keyed by model name
+    #[serde(default)]
+    pub pricing: HashMap<String, ModelPrice>,
+    /// answer with the mock adapter instead of the providers, it echoes the last message
+    #[serde(default)]
+    pub mock: bool,
+    /// models listed by the mock adapter
+    #[serde(default)]
+    pub mock_models: Vec<String>,
+    /// record the completions of the providers to fixture files, or replay them
+    pub replay: Option<ReplayConfig>,
+    /// how long the models listed by a provider are cached, 300 seconds when not set
+    pub models_ttl_secs: Option<u64>,
+}
+
+/// Workers running the jobs of tap agents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobConfig {
+    /// number of workers polling the queue, 0 to not run any in this process
+    #[serde(default = "default_job_workers")]
+    pub workers: usize,
+    /// how long an idle worker waits before polling again
+    #[serde(default = "default_job_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// attempts of a job before it is marked as failed
+    #[serde(default = "default_job_max_attempts")]
+    pub max_attempts: u32,
+    /// delay before the first retry of a failed job, doubled on every attempt
+    #[serde(default = "default_job_backoff_secs")]
+    pub backoff_secs: u64,
+    /// a job running for longer than this is considered abandoned and picked again
+    #[serde(default = "default_job_lock_timeout_secs")]
+    pub lock_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayConfig {
+    pub dir: PathBuf,
+    pub mode: ReplayMode,
+}
+
+/// Price in USD per 1M tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         // 思考: 这里同时打开了三个文件去判断，会影响到效率(优化做法，按优先级打开，然后再判断是否需要打开下一个)，但这里是在程序初始化的时候去做，所以问题不大，可以接受
@@ -39,3 +104,61 @@ impl AppConfig {
         Ok(ret)
     }
 }
+
+impl AiConfig {
+    /// Create the ai adapter for an agent
+    pub fn adapter(&self, adapter: &AdapterType, model: impl Into<String>) -> Result<AiAdapter> {
+        if self.mock {
+            let adapter = MockAdapter::new(model).with_models(self.mock_models.iter().cloned());
+            return Ok(adapter.into());
+        }
+        let adapter = match adapter {
+            AdapterType::Ollama => self.ollama.adapter(model, &self.retry).into(),
+            AdapterType::OpenAI => self.openai.adapter(model, &self.retry)?.into(),
+        };
+        match &self.replay {
+            Some(replay) => Ok(ReplayAdapter::new(adapter, &replay.dir, replay.mode).into()),
+            None => Ok(adapter),
+        }
+    }
+}
+
+impl ModelPrice {
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt
+            + usage.completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            workers: default_job_workers(),
+            poll_interval_ms: default_job_poll_interval_ms(),
+            max_attempts: default_job_max_attempts(),
+            backoff_secs: default_job_backoff_secs(),
+            lock_timeout_secs: default_job_lock_timeout_secs(),
+        }
+    }
+}
+
+fn default_job_workers() -> usize {
+    2
+}
+
+fn default_job_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_job_max_attempts() -> u32 {
+    3
+}
+
+fn default_job_backoff_secs() -> u64 {
+    10
+}
+
+fn default_job_lock_timeout_secs() -> u64 {
+    300
+}
@@ -1,5 +1,10 @@
-use axum::{extract::multipart, http::StatusCode, response::IntoResponse, Json};
-use chat_core::AgentError;
+use axum::{
+    extract::multipart,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chat_core::{AgentError, AiErrorKind};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use utoipa::ToSchema;
@@ -26,6 +31,9 @@ pub enum AppError {
     #[error("email already exists: {0}")]
     EmailAlreadyExists(String),
 
+    #[error("email is reserved: {0}")]
+    EmailReserved(String),
+
     #[error("create chat error: {0}")]
     CreateChatError(String),
 
@@ -41,6 +49,9 @@ pub enum AppError {
     #[error("create content error: {0}")]
     CreateMessageError(String),
 
+    #[error("update message error: {0}")]
+    UpdateMessageError(String),
+
     #[error("{0}")]
     ChatFileError(String),
 
@@ -58,6 +69,21 @@ pub enum AppError {
 
     #[error("ai agent error: {0}")]
     AiAgentError(#[from] AgentError),
+
+    #[error("message rejected: {0}")]
+    MessageRejected(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("review moderation error: {0}")]
+    ReviewModerationError(String),
+
+    #[error("reaction error: {0}")]
+    ReactionError(String),
+
+    #[error("search error: {0}")]
+    SearchError(String),
 }
 
 impl ErrorOutput {
@@ -71,23 +97,84 @@ impl ErrorOutput {
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
         let status = match &self {
-            Self::PasswordHashError(_) | Self::HttpHeaderError(_) | Self::MultipartError(_) => {
-                StatusCode::UNPROCESSABLE_ENTITY
-            }
-            Self::SqlxError(_) | Self::AnyError(_) | Self::IoError(_) | Self::AiAgentError(_) => {
+            Self::PasswordHashError(_)
+            | Self::HttpHeaderError(_)
+            | Self::MultipartError(_)
+            | Self::MessageRejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::SqlxError(_) | Self::AnyError(_) | Self::IoError(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
+            Self::AiAgentError(AgentError::Ai { kind, .. }) => match kind {
+                AiErrorKind::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+                AiErrorKind::ContextTooLong => StatusCode::PAYLOAD_TOO_LARGE,
+                AiErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+                AiErrorKind::Upstream => StatusCode::BAD_GATEWAY,
+                AiErrorKind::Other => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Self::AiAgentError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::EmailAlreadyExists(_) => StatusCode::CONFLICT,
             Self::CreateChatError(_)
             | Self::CreateMessageError(_)
+            | Self::UpdateMessageError(_)
             | Self::ChatFileError(_)
             | Self::CreateAgentError(_)
-            | Self::UpdateAgentError(_) => StatusCode::BAD_REQUEST,
+            | Self::UpdateAgentError(_)
+            | Self::ReviewModerationError(_)
+            | Self::ReactionError(_)
+            | Self::SearchError(_)
+            | Self::EmailReserved(_) => StatusCode::BAD_REQUEST,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
             Self::UnAuthorization(_) => StatusCode::UNAUTHORIZED,
-            Self::NotChatMemberError { .. } => StatusCode::FORBIDDEN,
+            Self::NotChatMemberError { .. } | Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
         };
 
-        (status, Json(ErrorOutput::new(self.to_string()))).into_response()
+        let mut response = (status, Json(ErrorOutput::new(self.to_string()))).into_response();
+        if let Self::AiAgentError(AgentError::Ai {
+            kind:
+                AiErrorKind::RateLimited {
+                    retry_after: Some(retry_after),
+                },
+            ..
+        }) = &self
+        {
+            // round up, so that clients never retry too early
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, secs.into());
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ai_sdk::AiError;
+
+    use super::*;
+    use crate::agent::ai_error;
+
+    #[test]
+    fn ai_agent_error_should_map_to_status() {
+        let response = AppError::from(ai_error(AiError::RateLimited {
+            retry_after: Some(Duration::from_millis(1500)),
+        }))
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+
+        let response = AppError::from(ai_error(AiError::ContextTooLong("too long".to_string())))
+            .into_response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = AppError::from(ai_error(AiError::Timeout)).into_response();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let err = ai_error(AiError::AuthFailed("bad key".to_string()));
+        assert_eq!(err.to_string(), "authentication failed: bad key");
+        let response = AppError::from(err).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
     }
 }
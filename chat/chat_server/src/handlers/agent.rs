@@ -1,11 +1,13 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
 
-use crate::{AppError, AppState, CreateAgent, UpdateAgent};
+use super::verify_admin;
+use crate::{AppError, AppState, CreateAgent, ListModels, PatchAgent, UpdateAgent};
+use chat_core::User;
 
 /// List all agent in a chat
 #[utoipa::path(
@@ -74,3 +76,171 @@ pub(crate) async fn update_agent_handler(
     let agent = state.update_agent(input, chat_id).await?;
     Ok((StatusCode::OK, Json(agent)))
 }
+
+/// Get an agent of a chat
+#[utoipa::path(
+    get,
+    path = "/api/chats/{chat_id}/agents/{agent_id}",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("agent_id" = u64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "The agent", body = ChatAgent),
+        (status = 404, description = "Agent not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_agent_handler(
+    Path((chat_id, agent_id)): Path<(u64, u64)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let agent = state.get_agent(chat_id, agent_id).await?;
+    Ok(Json(agent))
+}
+
+/// Change the type, adapter, model, prompt or args of an agent, or enable or disable it
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{chat_id}/agents/{agent_id}",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("agent_id" = u64, Path, description = "Agent id")
+    ),
+    request_body = PatchAgent,
+    responses(
+        (status = 200, description = "Agent updated", body = ChatAgent),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 404, description = "Agent not found", body = ErrorOutput),
+        (status = 502, description = "The models of the adapter cannot be listed", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn patch_agent_handler(
+    Path((chat_id, agent_id)): Path<(u64, u64)>,
+    State(state): State<AppState>,
+    Json(input): Json<PatchAgent>,
+) -> Result<impl IntoResponse, AppError> {
+    let agent = state.patch_agent(chat_id, agent_id, input).await?;
+    Ok(Json(agent))
+}
+
+/// Delete an agent of a chat
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{chat_id}/agents/{agent_id}",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("agent_id" = u64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 204, description = "Agent deleted"),
+        (status = 404, description = "Agent not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_agent_handler(
+    Path((chat_id, agent_id)): Path<(u64, u64)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_agent(chat_id, agent_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List the versions of an agent, newest first
+#[utoipa::path(
+    get,
+    path = "/api/chats/{chat_id}/agents/{agent_id}/versions",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("agent_id" = u64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Versions of the agent", body = Vec<AgentVersion>)
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_agent_versions_handler(
+    Path((chat_id, agent_id)): Path<(u64, u64)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let versions = state.list_agent_versions(chat_id, agent_id).await?;
+    Ok(Json(versions))
+}
+
+/// Restore an agent to one of its versions
+#[utoipa::path(
+    post,
+    path = "/api/chats/{chat_id}/agents/{agent_id}/versions/{version}/rollback",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("agent_id" = u64, Path, description = "Agent id"),
+        ("version" = u32, Path, description = "Version to restore")
+    ),
+    responses(
+        (status = 200, description = "Agent restored", body = ChatAgent),
+        (status = 404, description = "Version not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn rollback_agent_handler(
+    Path((chat_id, agent_id, version)): Path<(u64, u64, u32)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let agent = state.rollback_agent(chat_id, agent_id, version).await?;
+    Ok(Json(agent))
+}
+
+/// List the models an adapter can serve, to pick the model of an agent
+#[utoipa::path(
+    get,
+    path = "/api/models",
+    params(
+        ListModels
+    ),
+    responses(
+        (status = 200, description = "Names of the models", body = Vec<String>),
+        (status = 502, description = "The provider failed to list its models", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_models_handler(
+    State(state): State<AppState>,
+    Query(input): Query<ListModels>,
+) -> Result<impl IntoResponse, AppError> {
+    let models = state.list_models(&input.adapter).await?;
+    Ok(Json(models))
+}
+
+/// Requests and failures of the backends agents with fallbacks failed over between, by
+/// provider, host and model. They are counted for the whole server since it started.
+#[utoipa::path(
+    get,
+    path = "/api/models/fallbacks",
+    responses(
+        (status = 200, description = "Fallback metrics", body = FallbackStats),
+        (status = 403, description = "User is not a workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_fallbacks_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    verify_admin(&state, &user).await?;
+    Ok(Json(state.fallback_stats()))
+}
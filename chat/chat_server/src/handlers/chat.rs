@@ -5,14 +5,14 @@ use axum::{
     Extension, Json,
 };
 
-use crate::{AppError, AppState, CreateChat, UpdateChat};
+use crate::{AppError, AppState, CreateChat, MarkRead, UpdateChat};
 use chat_core::User;
 
 #[utoipa::path(
     get,
     path = "/api/chats",
     responses(
-        (status = 200, description = "List of chats", body = [Chat])
+        (status = 200, description = "List of chats", body = [ChatSummary])
     ),
     security(
         ("token" = [])
@@ -79,6 +79,32 @@ pub(crate) async fn get_chat_handler(
     }
 }
 
+/// Mark the messages of the chat as read, up to the latest one when no message is given.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/read",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body = MarkRead,
+    responses(
+        (status = 200, description = "Read receipt of the user", body = ReadReceipt),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn mark_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+    let receipt = state.mark_read(input, id, user.id as _).await?;
+    Ok(Json(receipt))
+}
+
 // TODO: chats表需要添加一个owner(外键 user id)字段
 // 检查是否能改名（owner可以改？）
 // 可以删除members?
@@ -7,7 +7,9 @@ use axum::{
 use tokio::fs;
 use tracing::{info, warn};
 
-use crate::{AppError, AppState, ChatFile, CreateMessage, ListMessage};
+use crate::{
+    AppError, AppState, ChatFile, CreateMessage, CreateReaction, ListMessage, UpdateMessage,
+};
 use chat_core::User;
 
 /// Send a new message in the chat.
@@ -20,6 +22,7 @@ use chat_core::User;
     responses(
         (status = 200, description = "List of messages", body = Message),
         (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 422, description = "Message rejected by a moderation agent", body = ErrorOutput),
     ),
     security(
         ("token" = [])
@@ -58,6 +61,166 @@ pub(crate) async fn list_message_handler(
     Ok(Json(msgs))
 }
 
+/// List the replies in the thread of a message, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{msg_id}/thread",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Root message id"),
+        ListMessage
+    ),
+    responses(
+        (status = 200, description = "Replies in the thread", body = [Message]),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_thread_handler(
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+    Query(input): Query<ListMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let msgs = state.list_thread(input, id, msg_id).await?;
+    Ok(Json(msgs))
+}
+
+/// Edit a message, only its sender can.
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/messages/{msg_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 200, description = "Message edited", body = Message),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "User is not the sender", body = ErrorOutput),
+        (status = 404, description = "Message not found or deleted", body = ErrorOutput),
+        (status = 422, description = "Message rejected by a moderation agent", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+    Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let msg = state
+        .update_message(input, id, msg_id, user.id as _)
+        .await?;
+    Ok(Json(msg))
+}
+
+/// Delete a message, only its sender can. It is kept as a tombstone without content.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{msg_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 204, description = "Message deleted"),
+        (status = 403, description = "User is not the sender", body = ErrorOutput),
+        (status = 404, description = "Message not found or deleted", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_message(id, msg_id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List the previous contents of an edited message, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{msg_id}/edits",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 200, description = "Edit history of the message", body = Vec<MessageEdit>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_message_edits_handler(
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let edits = state.list_message_edits(id, msg_id).await?;
+    Ok(Json(edits))
+}
+
+/// React to a message with an emoji.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/messages/{msg_id}/reactions",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 200, description = "Reactions to the message", body = Vec<ReactionSummary>),
+        (status = 400, description = "Invalid emoji", body = ErrorOutput),
+        (status = 404, description = "Message not found or deleted", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id)): Path<(u64, u64)>,
+    Json(input): Json<CreateReaction>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state.add_reaction(input, id, msg_id, user.id as _).await?;
+    Ok(Json(reactions))
+}
+
+/// Remove the reaction of the user to a message.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{msg_id}/reactions/{emoji}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("msg_id" = u64, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "Emoji, url encoded")
+    ),
+    responses(
+        (status = 200, description = "Reactions to the message", body = Vec<ReactionSummary>),
+        (status = 404, description = "User has no such reaction", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, msg_id, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state
+        .remove_reaction(&emoji, id, msg_id, user.id as _)
+        .await?;
+    Ok(Json(reactions))
+}
+
 pub(crate) async fn upload_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
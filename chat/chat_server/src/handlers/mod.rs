@@ -2,12 +2,16 @@ mod agent;
 mod auth;
 mod chat;
 mod messages;
+mod moderation;
+mod search;
 mod workspace;
 
 pub(crate) use agent::*;
 pub(crate) use auth::*;
 pub(crate) use chat::*;
 pub(crate) use messages::*;
+pub(crate) use moderation::*;
+pub(crate) use search::*;
 pub(crate) use workspace::*;
 
 use axum::response::IntoResponse;
@@ -0,0 +1,75 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, ListModeration, ReviewModeration};
+use chat_core::User;
+
+/// List the messages flagged by moderation agents in the workspace of the user.
+#[utoipa::path(
+    get,
+    path = "/api/moderation",
+    params(
+        ListModeration
+    ),
+    responses(
+        (status = 200, description = "List of flagged messages", body = Vec<ModerationItem>),
+        (status = 403, description = "User is not a workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_moderation_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListModeration>,
+) -> Result<impl IntoResponse, AppError> {
+    verify_admin(&state, &user).await?;
+    let items = state.list_moderation(user.ws_id as _, input).await?;
+    Ok(Json(items))
+}
+
+/// Approve or remove a flagged message.
+#[utoipa::path(
+    patch,
+    path = "/api/moderation/{id}",
+    params(
+        ("id" = u64, Path, description = "Moderation item id")
+    ),
+    responses(
+        (status = 200, description = "Moderation item reviewed", body = ModerationItem),
+        (status = 403, description = "User is not a workspace admin", body = ErrorOutput),
+        (status = 404, description = "No pending item with the id", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn review_moderation_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<ReviewModeration>,
+) -> Result<impl IntoResponse, AppError> {
+    verify_admin(&state, &user).await?;
+    let item = state
+        .review_moderation(id, user.ws_id as _, user.id as _, input)
+        .await?;
+    Ok(Json(item))
+}
+
+pub(crate) async fn verify_admin(state: &AppState, user: &User) -> Result<(), AppError> {
+    if !state
+        .is_workspace_admin(user.ws_id as _, user.id as _)
+        .await?
+    {
+        return Err(AppError::PermissionDenied(format!(
+            "user {} is not an admin of workspace {}",
+            user.id, user.ws_id
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, SearchMessages};
+use chat_core::User;
+
+/// Search the messages of the chats of the user, newest first. Pass the id of the last hit as
+/// `last_id` to get the next page.
+#[utoipa::path(
+    get,
+    path = "/api/search/messages",
+    params(
+        SearchMessages
+    ),
+    responses(
+        (status = 200, description = "Matching messages", body = Vec<MessageHit>),
+        (status = 400, description = "Invalid search", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn search_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<SearchMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let hits = state
+        .search_messages(input, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(hits))
+}
@@ -1,15 +1,25 @@
 mod agent;
+mod catalog;
 mod config;
 mod error;
 mod handlers;
+mod lookup;
 mod middlewares;
 mod models;
 mod openapi;
+mod pipeline;
+mod prompt;
+mod worker;
 
-pub use config::AppConfig;
+pub use catalog::{FallbackBackend, FallbackStats, ListModels, ModelCatalog};
+pub use config::{
+    AiConfig, AppConfig, JobConfig, ModelPrice, OllamaConfig, OpenAIConfig, ReplayConfig,
+};
 pub use error::AppError;
 pub use models::*;
+pub use worker::spawn_workers;
 
+use ai_sdk::FallbackMetrics;
 use anyhow::Context;
 use axum::http::Method;
 use chat_core::middlewares::{set_layer, verify_token, TokenVerify};
@@ -25,7 +35,7 @@ use std::{fmt, ops::Deref, sync::Arc};
 
 use axum::{
     middleware::from_fn_with_state,
-    routing::{get, post},
+    routing::{delete, get, patch, post},
     Router,
 };
 
@@ -39,6 +49,9 @@ pub struct AppStateInner {
     pub(crate) ek: EncodingKey,
     pub(crate) dk: DecodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) models: ModelCatalog,
+    /// failovers of the agents with fallback backends
+    pub(crate) fallbacks: Arc<FallbackMetrics>,
 }
 
 pub async fn get_router(state: AppState) -> Result<Router, AppError> {
@@ -54,9 +67,41 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
             "/:id/agents",
             get(list_agent_handler)
                 .post(create_agent_handler)
-                .patch(update_agent_handler), // 理论上是应该 /:id/agents/:agent_id 更新 agent 的，但中间 chat 根据路径的chat_id判断是不是群成员，所以不能使用这种方式(否则会判断路径id错误)
+                .patch(update_agent_handler),
+        )
+        .route(
+            "/:id/agents/:agent_id",
+            get(get_agent_handler)
+                .patch(patch_agent_handler)
+                .delete(delete_agent_handler),
+        )
+        .route(
+            "/:id/agents/:agent_id/versions",
+            get(list_agent_versions_handler),
+        )
+        .route(
+            "/:id/agents/:agent_id/versions/:version/rollback",
+            post(rollback_agent_handler),
         )
+        .route("/:id/read", post(mark_read_handler))
         .route("/:id/messages", get(list_message_handler))
+        .route(
+            "/:id/messages/:msg_id",
+            patch(update_message_handler).delete(delete_message_handler),
+        )
+        .route(
+            "/:id/messages/:msg_id/edits",
+            get(list_message_edits_handler),
+        )
+        .route("/:id/messages/:msg_id/thread", get(list_thread_handler))
+        .route(
+            "/:id/messages/:msg_id/reactions",
+            post(add_reaction_handler),
+        )
+        .route(
+            "/:id/messages/:msg_id/reactions/:emoji",
+            delete(remove_reaction_handler),
+        )
         .layer(from_fn_with_state(state.clone(), verify_chat))
         .route("/", get(list_chat_handler).post(create_chat_handler));
 
@@ -74,6 +119,11 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
     let api = Router::new()
         .route("/users", get(list_chat_users_handler))
         .nest("/chats", chats)
+        .route("/models", get(list_models_handler))
+        .route("/models/fallbacks", get(list_fallbacks_handler))
+        .route("/moderation", get(list_moderation_handler))
+        .route("/moderation/:id", patch(review_moderation_handler))
+        .route("/search/messages", get(search_messages_handler))
         .route("/upload", post(upload_handler))
         .route("/files/:ws_id/*path", get(file_handler))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
@@ -108,12 +158,15 @@ impl AppState {
         let pool = PgPool::connect(&config.server.db_url)
             .await
             .context("connect db failed")?;
+        let models = ModelCatalog::new(config.ai.models_ttl_secs);
         Ok(AppState {
             inner: Arc::new(AppStateInner {
                 config,
                 dk,
                 ek,
                 pool,
+                models,
+                fallbacks: Arc::default(),
             }),
         })
     }
@@ -143,18 +196,24 @@ mod test_util {
 
     impl AppState {
         pub async fn new_for_test() -> Result<(TestPg, Self), AppError> {
-            let config = AppConfig::load()?;
+            let mut config = AppConfig::load()?;
+            // agents must not depend on a running ollama or an openai key in tests
+            config.ai.mock = true;
+            config.ai.mock_models = vec!["llama3.2:latest".to_string(), "gpt-4o-mini".to_string()];
             let dk = DecodingKey::load(&config.auth.pk).context("load pk failed")?;
             let ek = EncodingKey::load(&config.auth.sk).context("load sk failed")?;
             let post = config.server.db_url.rfind('/').expect("invalid db_url");
             let server_url = &config.server.db_url[..post];
             let (tdb, pool) = get_test_pool(Some(server_url)).await;
+            let models = ModelCatalog::new(config.ai.models_ttl_secs);
             let state = AppState {
                 inner: Arc::new(AppStateInner {
                     config,
                     dk,
                     ek,
                     pool,
+                    models,
+                    fallbacks: Arc::default(),
                 }),
             };
             Ok((tdb, state))
@@ -0,0 +1,136 @@
+use ai_sdk::{Tool, ToolCall, ToolHandler};
+use anyhow::{anyhow, bail};
+use chat_core::{AgentContext, Message};
+use serde_json::json;
+
+use crate::{AppState, SearchMessages};
+
+/// Number of messages a search returns to the model
+const SEARCH_LIMIT: u64 = 10;
+
+/// Tools letting reply agents look up the messages, files and members of their chat instead of
+/// guessing
+pub fn lookup_tools() -> Vec<Tool> {
+    vec![
+        Tool::new(
+            "search_messages",
+            "Search the messages of the chat, newest first",
+            json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "search terms, supports \"quoted phrases\", or and -excluded words"
+                    }
+                },
+                "required": ["query"]
+            }),
+        ),
+        Tool::new(
+            "get_message",
+            "Get a message of the chat with its files by id",
+            json!({
+                "type": "object",
+                "properties": { "id": { "type": "integer" } },
+                "required": ["id"]
+            }),
+        ),
+        Tool::new(
+            "find_user",
+            "Find the members of the chat whose name contains the given name",
+            json!({
+                "type": "object",
+                "properties": { "name": { "type": "string" } },
+                "required": ["name"]
+            }),
+        ),
+    ]
+}
+
+/// Runs the lookup tools in the chat of the message, as its sender, so that an agent can't see
+/// more than the sender could
+pub struct ChatLookup<'a> {
+    pub state: &'a AppState,
+    pub ctx: &'a AgentContext,
+}
+
+impl ToolHandler for ChatLookup<'_> {
+    async fn call(&self, call: &ToolCall) -> anyhow::Result<String> {
+        let chat = self
+            .ctx
+            .chat
+            .as_ref()
+            .ok_or(anyhow!("no chat to look up"))?;
+        let sender = self
+            .ctx
+            .sender
+            .as_ref()
+            .ok_or(anyhow!("no sender to look up as"))?;
+        match call.name.as_str() {
+            "search_messages" => {
+                let query = call.arguments["query"]
+                    .as_str()
+                    .ok_or(anyhow!("query must be a string"))?;
+                let input = SearchMessages {
+                    q: query.to_string(),
+                    chat_id: Some(chat.id as _),
+                    limit: SEARCH_LIMIT,
+                    ..Default::default()
+                };
+                let hits = self
+                    .state
+                    .search_messages(input, sender.id as _, chat.ws_id as _)
+                    .await?;
+                if hits.is_empty() {
+                    return Ok("no message found".to_string());
+                }
+                let lines: Vec<String> = hits.iter().map(|h| self.describe(&h.message)).collect();
+                Ok(lines.join("\n"))
+            }
+            "get_message" => {
+                let id = call.arguments["id"]
+                    .as_u64()
+                    .ok_or(anyhow!("id must be an integer"))?;
+                match self.state.find_message(chat.id as _, id).await? {
+                    Some(message) if message.deleted_at.is_none() => Ok(self.describe(&message)),
+                    Some(_) => Ok(format!("message {} was deleted", id)),
+                    None => bail!("message {} not found", id),
+                }
+            }
+            "find_user" => {
+                let name = call.arguments["name"]
+                    .as_str()
+                    .ok_or(anyhow!("name must be a string"))?
+                    .to_lowercase();
+                let users: Vec<String> = self
+                    .ctx
+                    .members
+                    .iter()
+                    .filter(|u| u.fullname.to_lowercase().contains(&name))
+                    .map(|u| format!("#{} {}", u.id, u.fullname))
+                    .collect();
+                if users.is_empty() {
+                    return Ok("no user found".to_string());
+                }
+                Ok(users.join("\n"))
+            }
+            name => bail!("unknown tool {}", name),
+        }
+    }
+}
+
+impl ChatLookup<'_> {
+    /// A message as a line, `#id sender: content [files: ...]`
+    fn describe(&self, message: &Message) -> String {
+        let sender = self.ctx.sender_name(message.sender_id).unwrap_or("unknown");
+        let content = message
+            .modified_content
+            .as_deref()
+            .unwrap_or(&message.content);
+        let mut line = format!("#{} {}: {}", message.id, sender, content);
+        if !message.files.is_empty() {
+            line.push_str(&format!(" [files: {}]", message.files.join(", ")));
+        }
+        line
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use chat_server::{get_router, AppConfig, AppState};
+use chat_server::{get_router, spawn_workers, AppConfig, AppState};
 use tokio::net::TcpListener;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
@@ -12,6 +12,7 @@ async fn main() -> Result<()> {
     let config = AppConfig::load()?;
     let addr = format!("0.0.0.0:{}", config.server.port);
     let state = AppState::try_new(config).await?;
+    spawn_workers(&state);
     let app = get_router(state).await?;
     let listener = TcpListener::bind(&addr).await?;
     info!("Chat Server listening on: {}", addr);
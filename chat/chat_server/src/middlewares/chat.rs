@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use axum::{
     extract::{FromRequestParts, Path, Request, State},
     middleware::Next,
@@ -7,11 +9,18 @@ use axum::{
 use crate::{AppError, AppState};
 use chat_core::User;
 
+/// Check the user is a member of the chat, the chat id is the `id` path parameter so that
+/// nested routes like `/chats/:id/agents/:agent_id` are verified as well
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
-    let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
-        .await
-        .unwrap();
+    let chat_id =
+        match Path::<HashMap<String, String>>::from_request_parts(&mut parts, &state).await {
+            Ok(Path(params)) => params.get("id").and_then(|id| id.parse::<u64>().ok()),
+            Err(e) => return e.into_response(),
+        };
+    let Some(chat_id) = chat_id else {
+        return AppError::NotFound("chat id in path".to_string()).into_response();
+    };
     let user = parts.extensions.get::<User>().unwrap();
     if !state
         .is_chat_member(chat_id, user.id as _)
@@ -52,6 +61,7 @@ mod tests {
         let token = state.ek.sign(user)?;
         let app = Router::new()
             .route("/chats/:id/messages", get(handler))
+            .route("/chats/:id/agents/:agent_id", get(handler))
             .layer(from_fn_with_state(state.clone(), verify_chat))
             .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
             .with_state(state);
@@ -69,6 +79,21 @@ mod tests {
             .uri("/chats/5/messages")
             .header("Authorization", format!("Bearer {}", token))
             .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // nested path parameters
+        let req = Request::builder()
+            .uri("/chats/1/agents/1")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = Request::builder()
+            .uri("/chats/5/agents/1")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
         let res = app.oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::FORBIDDEN);
 
@@ -1,9 +1,14 @@
 use chat_core::{AdapterType, AgentType, ChatAgent};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Postgres, Transaction};
 use tracing::info;
 use utoipa::ToSchema;
 
-use crate::{AppError, AppState};
+use crate::{agent::validate_args, prompt::PromptTemplate, AppError, AppState};
+
+/// Domain of the emails of agent bots, users can't sign up with it
+pub(crate) const AGENT_BOT_DOMAIN: &str = "agents.bot.org";
 
 #[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
 #[serde(rename_all(serialize = "camelCase"))]
@@ -27,6 +32,33 @@ pub struct UpdateAgent {
     pub args: serde_json::Value,
 }
 
+/// Partial update of an agent, the fields which are not set are kept
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct PatchAgent {
+    pub r#type: Option<AgentType>,
+    pub adapter: Option<AdapterType>,
+    pub model: Option<String>,
+    pub prompt: Option<String>,
+    pub args: Option<serde_json::Value>,
+    pub enabled: Option<bool>,
+}
+
+/// A configuration an agent had
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct AgentVersion {
+    pub id: i64,
+    pub agent_id: i64,
+    pub version: i32,
+    pub r#type: AgentType,
+    pub adapter: AdapterType,
+    pub model: String,
+    pub prompt: String,
+    pub args: sqlx::types::Json<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
 impl AppState {
     /// Create a new agent in a chat
     pub async fn create_agent(
@@ -43,8 +75,25 @@ impl AppState {
             )));
         }
 
-        // TODO: check if model is supported by adapter
-        let agent = sqlx::query_as(
+        validate_args(&input.args).map_err(|e| {
+            AppError::CreateAgentError(format!("invalid args for agent {}: {}", input.name, e))
+        })?;
+        PromptTemplate::new(input.prompt.as_str()).map_err(|e| {
+            AppError::CreateAgentError(format!("invalid prompt for agent {}: {}", input.name, e))
+        })?;
+
+        if !self
+            .is_model_available(&input.adapter, &input.model)
+            .await?
+        {
+            return Err(AppError::CreateAgentError(format!(
+                "model {} is not available on {:?}",
+                input.model, input.adapter
+            )));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let mut agent: ChatAgent = sqlx::query_as(
             r#"
             INSERT INTO chat_agents (chat_id, name, type, adapter, model, prompt, args)
             VALUES ($1, $2, $3, $4, $5, $6, $7)
@@ -58,11 +107,62 @@ impl AppState {
         .bind(input.model)
         .bind(input.prompt)
         .bind(input.args)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
+        record_version(&mut tx, &agent).await?;
+        tx.commit().await?;
+        agent.bot_id = Some(self.agent_bot(&agent).await?);
         Ok(agent)
     }
 
+    /// The bot user an agent posts its replies as, it is created on first use for agents created
+    /// before they had one
+    pub async fn agent_bot(&self, agent: &ChatAgent) -> Result<i64, AppError> {
+        if let Some(bot_id) = agent.bot_id {
+            return Ok(bot_id);
+        }
+        let mut tx = self.pool.begin().await?;
+        let (bot_id,): (Option<i64>,) =
+            sqlx::query_as("SELECT bot_id FROM chat_agents WHERE id = $1 FOR UPDATE")
+                .bind(agent.id)
+                .fetch_one(&mut *tx)
+                .await?;
+        if let Some(bot_id) = bot_id {
+            return Ok(bot_id);
+        }
+        // bots can't sign in, they have no password. A user who already has the email is only
+        // reused if it is a bot of the same workspace, never a person
+        let fullname: String = agent.name.chars().take(64).collect();
+        let email = format!("agent-{}@{}", agent.id, AGENT_BOT_DOMAIN);
+        let bot: Option<(i64,)> = sqlx::query_as(
+            r#"
+            INSERT INTO users (ws_id, email, fullname, password_hash, is_bot)
+            SELECT ws_id, $2, $3, '', TRUE FROM chats WHERE id = $1
+            ON CONFLICT (email) DO UPDATE SET fullname = EXCLUDED.fullname
+                WHERE users.is_bot AND users.ws_id = EXCLUDED.ws_id
+            RETURNING id
+        "#,
+        )
+        .bind(agent.chat_id)
+        .bind(&email)
+        .bind(fullname)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((bot_id,)) = bot else {
+            return Err(AppError::CreateAgentError(format!(
+                "bot user of agent {} cannot be created, {} belongs to another user",
+                agent.id, email
+            )));
+        };
+        sqlx::query("UPDATE chat_agents SET bot_id = $1 WHERE id = $2")
+            .bind(bot_id)
+            .bind(agent.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(bot_id)
+    }
+
     /// List all agent in a chat
     pub async fn list_agents(&self, chat_id: u64) -> Result<Vec<ChatAgent>, AppError> {
         let agents = sqlx::query_as(
@@ -92,50 +192,175 @@ impl AppState {
             )));
         }
 
-        let prompt = input.prompt;
-        let args = input.args;
-        let agent = match (prompt.as_str(), &args) {
-            ("", _) => {
-                // no prompt
-                sqlx::query_as(
-                    r#"
-                    UPDATE chat_agents
-                        SET
-                            args = $1,
-                            updated_at = NOW()
-                    WHERE chat_id = $2 AND id = $3
-                    RETURNING *
-                "#,
-                )
-                .bind(args)
-                .bind(chat_id as i64)
-                .bind(agent_id as i64)
-                .fetch_one(&self.pool)
-                .await?
-            }
-            (_, _) => {
-                sqlx::query_as(
-                    r#"
-                    UPDATE chat_agents
-                        SET
-                            prompt = $1,
-                            args = $2,
-                            updated_at = NOW()
-                    WHERE chat_id = $3 and id = $4
-                    RETURNING *
-                "#,
-                )
-                .bind(prompt)
-                .bind(args)
-                .bind(chat_id as i64)
-                .bind(agent_id as i64)
-                .fetch_one(&self.pool)
-                .await?
-            }
+        // an empty prompt keeps the current one
+        let input = PatchAgent {
+            prompt: (!input.prompt.is_empty()).then_some(input.prompt),
+            args: Some(input.args),
+            ..Default::default()
         };
+        self.patch_agent(chat_id, agent_id, input).await
+    }
+
+    /// Get an agent of a chat
+    pub async fn get_agent(&self, chat_id: u64, agent_id: u64) -> Result<ChatAgent, AppError> {
+        let agent = sqlx::query_as(
+            r#"
+            SELECT * FROM chat_agents WHERE chat_id = $1 AND id = $2
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(agent_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        agent
+            .ok_or_else(|| AppError::NotFound(format!("agent id {} in chat {}", agent_id, chat_id)))
+    }
+
+    /// Change the fields of an agent which are set. A new version of the agent is recorded when
+    /// its configuration changes.
+    pub async fn patch_agent(
+        &self,
+        chat_id: u64,
+        agent_id: u64,
+        input: PatchAgent,
+    ) -> Result<ChatAgent, AppError> {
+        let current = self.get_agent(chat_id, agent_id).await?;
+        if let Some(args) = &input.args {
+            validate_args(args).map_err(|e| {
+                AppError::UpdateAgentError(format!("invalid args for agent {}: {}", agent_id, e))
+            })?;
+        }
+        if let Some(prompt) = &input.prompt {
+            PromptTemplate::new(prompt.as_str()).map_err(|e| {
+                AppError::UpdateAgentError(format!("invalid prompt for agent {}: {}", agent_id, e))
+            })?;
+        }
+        if input.model.as_ref().is_some_and(|model| model.is_empty()) {
+            return Err(AppError::UpdateAgentError(format!(
+                "model of agent {} cannot be empty",
+                agent_id
+            )));
+        }
+
+        if input.adapter.is_some() || input.model.is_some() {
+            let adapter = input.adapter.as_ref().unwrap_or(&current.adapter);
+            let model = input.model.as_deref().unwrap_or(&current.model);
+            if !self.is_model_available(adapter, model).await? {
+                return Err(AppError::UpdateAgentError(format!(
+                    "model {} is not available on {:?}",
+                    model, adapter
+                )));
+            }
+        }
+
+        let changed = input.changes_config();
+        let mut tx = self.pool.begin().await?;
+        let agent: ChatAgent = sqlx::query_as(
+            r#"
+            UPDATE chat_agents
+                SET
+                    type = $1,
+                    adapter = $2,
+                    model = $3,
+                    prompt = $4,
+                    args = $5,
+                    enabled = $6,
+                    updated_at = NOW()
+            WHERE chat_id = $7 AND id = $8
+            RETURNING *
+        "#,
+        )
+        .bind(input.r#type.unwrap_or(current.r#type))
+        .bind(input.adapter.unwrap_or(current.adapter))
+        .bind(input.model.unwrap_or(current.model))
+        .bind(input.prompt.unwrap_or(current.prompt))
+        .bind(input.args.unwrap_or(current.args.0))
+        .bind(input.enabled.unwrap_or(current.enabled))
+        .bind(chat_id as i64)
+        .bind(agent_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        if changed {
+            record_version(&mut tx, &agent).await?;
+        }
+        tx.commit().await?;
         Ok(agent)
     }
 
+    /// Delete an agent of a chat, its decisions and usage are kept
+    pub async fn delete_agent(&self, chat_id: u64, agent_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query("DELETE FROM chat_agents WHERE chat_id = $1 AND id = $2")
+            .bind(chat_id as i64)
+            .bind(agent_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "agent id {} in chat {}",
+                agent_id, chat_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// All the versions of an agent, newest first
+    pub async fn list_agent_versions(
+        &self,
+        chat_id: u64,
+        agent_id: u64,
+    ) -> Result<Vec<AgentVersion>, AppError> {
+        let versions = sqlx::query_as(
+            r#"
+            SELECT v.* FROM chat_agent_versions v
+            JOIN chat_agents a ON a.id = v.agent_id
+            WHERE a.chat_id = $1 AND v.agent_id = $2
+            ORDER BY v.version DESC
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(agent_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(versions)
+    }
+
+    /// Restore the configuration of an agent from one of its versions, it is recorded as a new
+    /// version so that the rollback can be rolled back as well
+    pub async fn rollback_agent(
+        &self,
+        chat_id: u64,
+        agent_id: u64,
+        version: u32,
+    ) -> Result<ChatAgent, AppError> {
+        let target: Option<AgentVersion> = sqlx::query_as(
+            r#"
+            SELECT v.* FROM chat_agent_versions v
+            JOIN chat_agents a ON a.id = v.agent_id
+            WHERE a.chat_id = $1 AND v.agent_id = $2 AND v.version = $3
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(agent_id as i64)
+        .bind(version as i32)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(target) = target else {
+            return Err(AppError::NotFound(format!(
+                "version {} of agent id {}",
+                version, agent_id
+            )));
+        };
+        let input = PatchAgent {
+            r#type: Some(target.r#type),
+            adapter: Some(target.adapter),
+            model: Some(target.model),
+            prompt: Some(target.prompt),
+            args: Some(target.args.0),
+            enabled: None,
+        };
+        self.patch_agent(chat_id, agent_id, input).await
+    }
+
     /// Check if an agent name exists in a chat
     pub async fn agent_name_exists(&self, chat_id: u64, name: &str) -> Result<bool, AppError> {
         let exists = sqlx::query_scalar(
@@ -163,6 +388,40 @@ impl AppState {
     }
 }
 
+/// Record the current configuration of an agent as its next version
+async fn record_version(
+    tx: &mut Transaction<'_, Postgres>,
+    agent: &ChatAgent,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO chat_agent_versions (agent_id, version, type, adapter, model, prompt, args)
+        SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3, $4, $5, $6
+        FROM chat_agent_versions WHERE agent_id = $1
+    "#,
+    )
+    .bind(agent.id)
+    .bind(&agent.r#type)
+    .bind(&agent.adapter)
+    .bind(&agent.model)
+    .bind(&agent.prompt)
+    .bind(&agent.args)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+impl PatchAgent {
+    /// Whether the patch changes what the agent does, rather than only enabling or disabling it
+    fn changes_config(&self) -> bool {
+        self.r#type.is_some()
+            || self.adapter.is_some()
+            || self.model.is_some()
+            || self.prompt.is_some()
+            || self.args.is_some()
+    }
+}
+
 impl CreateAgent {
     pub fn new(
         name: impl Into<String>,
@@ -222,6 +481,87 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn agent_bot_should_not_take_over_other_users() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateAgent::new(
+            "helper",
+            AgentType::Reply,
+            AdapterType::Ollama,
+            "llama3.2",
+            "You are a helpful assistant",
+            serde_json::json!({}),
+        );
+        let mut agent = state.create_agent(input, 1).await?;
+        let bot_id = agent.bot_id.expect("agent should have a bot");
+        // the email of the bot is now owned by a person
+        sqlx::query("UPDATE users SET is_bot = FALSE, fullname = 'Mallory' WHERE id = $1")
+            .bind(bot_id)
+            .execute(&state.pool)
+            .await?;
+        sqlx::query("UPDATE chat_agents SET bot_id = NULL WHERE id = $1")
+            .bind(agent.id)
+            .execute(&state.pool)
+            .await?;
+        agent.bot_id = None;
+
+        let ret = state.agent_bot(&agent).await;
+        assert!(matches!(ret, Err(AppError::CreateAgentError(_))));
+        let user = state.find_user_by_id(bot_id as _).await?.unwrap();
+        assert_eq!(user.fullname, "Mallory");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_agent_with_invalid_prompt_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateAgent::new(
+            "agent X2",
+            AgentType::Proxy,
+            AdapterType::Ollama,
+            "llama3.2",
+            "Translate {{ message } to {{ args.language }}",
+            serde_json::json!({ "language": "French" }),
+        );
+        let ret = state.create_agent(input, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateAgentError(_))));
+
+        let input = UpdateAgent::new(1, "Translate {{ content }}", serde_json::json!({}));
+        let ret = state.update_agent(input, 1).await;
+        let Err(AppError::UpdateAgentError(e)) = ret else {
+            panic!("update should fail");
+        };
+        assert!(e.contains("unknown variables in prompt: content"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn agent_with_unknown_model_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.set_models(AdapterType::Ollama, &["llama3.2:latest"]);
+        let input = CreateAgent::new(
+            "agent X3",
+            AgentType::Proxy,
+            AdapterType::Ollama,
+            "mistral",
+            "You are a helpful assistant",
+            serde_json::json!({}),
+        );
+        let ret = state.create_agent(input, 1).await;
+        let Err(AppError::CreateAgentError(e)) = ret else {
+            panic!("create should fail");
+        };
+        assert!(e.contains("model mistral is not available"));
+
+        let input = PatchAgent {
+            model: Some("mistral".to_string()),
+            ..Default::default()
+        };
+        let ret = state.patch_agent(1, 1, input).await;
+        assert!(matches!(ret, Err(AppError::UpdateAgentError(_))));
+        Ok(())
+    }
+
     /*
 
     -- insert agent to chat
@@ -274,4 +614,91 @@ mod tests {
         );
         Ok(())
     }
+
+    #[tokio::test]
+    async fn agent_lifecycle_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateAgent::new(
+            "lifecycle",
+            AgentType::Proxy,
+            AdapterType::Ollama,
+            "llama3.2",
+            "v1",
+            serde_json::json!({}),
+        );
+        let agent = state.create_agent(input, 1).await?;
+        let agent_id = agent.id as u64;
+        assert!(agent.enabled);
+        assert_eq!(state.get_agent(1, agent_id).await?.prompt, "v1");
+        // agents are scoped to their chat
+        assert!(matches!(
+            state.get_agent(2, agent_id).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        let input = PatchAgent {
+            r#type: Some(AgentType::Reply),
+            adapter: Some(AdapterType::OpenAI),
+            model: Some("gpt-4o-mini".to_string()),
+            prompt: Some("v2".to_string()),
+            ..Default::default()
+        };
+        let agent = state.patch_agent(1, agent_id, input).await?;
+        assert_eq!(agent.r#type, AgentType::Reply);
+        assert_eq!(agent.adapter, AdapterType::OpenAI);
+        assert_eq!(agent.model, "gpt-4o-mini");
+        assert_eq!(agent.prompt, "v2");
+
+        // enabling or disabling is not a new version
+        let input = PatchAgent {
+            enabled: Some(false),
+            ..Default::default()
+        };
+        let agent = state.patch_agent(1, agent_id, input).await?;
+        assert!(!agent.enabled);
+        let versions = state.list_agent_versions(1, agent_id).await?;
+        let numbers: Vec<_> = versions.iter().map(|v| v.version).collect();
+        assert_eq!(numbers, vec![2, 1]);
+
+        let agent = state.rollback_agent(1, agent_id, 1).await?;
+        assert_eq!(agent.r#type, AgentType::Proxy);
+        assert_eq!(agent.adapter, AdapterType::Ollama);
+        assert_eq!(agent.prompt, "v1");
+        assert!(!agent.enabled);
+        let versions = state.list_agent_versions(1, agent_id).await?;
+        assert_eq!(versions[0].version, 3);
+        assert_eq!(versions[0].prompt, "v1");
+        assert!(matches!(
+            state.rollback_agent(1, agent_id, 9).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        state.delete_agent(1, agent_id).await?;
+        assert!(matches!(
+            state.get_agent(1, agent_id).await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(state.list_agent_versions(1, agent_id).await?.is_empty());
+        assert!(matches!(
+            state.delete_agent(1, agent_id).await,
+            Err(AppError::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_agent_with_invalid_options_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateAgent::new(
+            "hot agent",
+            AgentType::Reply,
+            AdapterType::Ollama,
+            "llama3.2",
+            "You are a helpful assistant",
+            serde_json::json!({ "temperature": 5.0 }),
+        );
+        let ret = state.create_agent(input, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateAgentError(_))));
+        Ok(())
+    }
 }
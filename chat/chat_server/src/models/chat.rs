@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 use utoipa::ToSchema;
 
 use crate::{AppError, AppState};
-use chat_core::{Chat, ChatType};
+use chat_core::{Chat, ChatType, Message};
 
 #[derive(Debug, ToSchema, Clone, Serialize, Deserialize, Default)]
 pub struct CreateChat {
@@ -11,6 +14,21 @@ pub struct CreateChat {
     pub public: bool,
 }
 
+/// A chat of the user along with the activity the user hasn't seen yet
+#[derive(Debug, ToSchema, Clone, FromRow, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChatSummary {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub chat: Chat,
+    /// the last message the user has read, 0 when none
+    pub last_read_id: i64,
+    /// messages of the other members after the last read one, thread replies excluded
+    pub unread_count: i64,
+    #[sqlx(skip)]
+    pub last_message: Option<Message>,
+}
+
 #[derive(Debug, ToSchema, Clone, Serialize, Deserialize)]
 pub struct UpdateChat {
     pub name: Option<String>,
@@ -87,18 +105,52 @@ impl AppState {
         Ok(chat)
     }
 
-    pub async fn fetch_chats(&self, user_id: u64, ws_id: u64) -> Result<Vec<Chat>, AppError> {
-        let chats = sqlx::query_as(
+    /// The chats of the user with their unread count and last message
+    pub async fn fetch_chats(
+        &self,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<ChatSummary>, AppError> {
+        let mut chats: Vec<ChatSummary> = sqlx::query_as(
             r#"
-            SELECT id, ws_id, name, type, agents, members, created_at
-            FROM chats
-            WHERE ws_id = $1 AND $2 = ANY(members)
+            SELECT c.id, c.ws_id, c.name, c.type, c.agents, c.members, c.created_at,
+                COALESCE(r.last_read_id, 0) AS last_read_id,
+                (
+                    SELECT COUNT(*) FROM messages m
+                    WHERE m.chat_id = c.id
+                    AND m.thread_id IS NULL
+                    AND m.deleted_at IS NULL
+                    AND m.sender_id <> $2
+                    AND m.id > COALESCE(r.last_read_id, 0)
+                ) AS unread_count
+            FROM chats c
+            LEFT JOIN chat_reads r ON r.chat_id = c.id AND r.user_id = $2
+            WHERE c.ws_id = $1 AND $2 = ANY(c.members)
         "#,
         )
         .bind(ws_id as i64)
         .bind(user_id as i64)
         .fetch_all(&self.pool)
         .await?;
+
+        let ids: Vec<_> = chats.iter().map(|c| c.chat.id).collect();
+        let messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT ON (chat_id) id, chat_id, sender_id, content, modified_content, files,
+                created_at, edited_at, deleted_at, reply_to, thread_id, reply_count
+            FROM messages
+            WHERE chat_id = ANY($1) AND thread_id IS NULL AND deleted_at IS NULL
+            ORDER BY chat_id, id DESC
+        "#,
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut last_messages: HashMap<_, _> =
+            messages.into_iter().map(|m| (m.chat_id, m)).collect();
+        for chat in &mut chats {
+            chat.last_message = last_messages.remove(&chat.chat.id);
+        }
         Ok(chats)
     }
 
@@ -198,6 +250,14 @@ mod tests {
         let chats = state.fetch_chats(1, 1).await?;
         assert_eq!(chats.len(), 4);
 
+        let chat = chats
+            .iter()
+            .find(|c| c.chat.id == 1)
+            .expect("chat 1 missing");
+        assert_eq!(chat.last_read_id, 0);
+        // 10 messages in chat 1, 3 of them sent by user 1
+        assert_eq!(chat.unread_count, 7);
+        assert_eq!(chat.last_message.as_ref().map(|m| m.id), Some(10));
         Ok(())
     }
 
@@ -0,0 +1,96 @@
+use chat_core::AgentDecision;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::{pipeline::AgentRun, AppError, AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToSchema, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "agent_decision_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DecisionType {
+    Modify,
+    Reply,
+    Delete,
+    Redact,
+    Flag,
+    None,
+    Failed,
+}
+
+/// The decision an agent made on a message
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct AgentDecisionRecord {
+    pub id: i64,
+    pub chat_id: i64,
+    pub message_id: Option<i64>,
+    pub agent_id: Option<i64>,
+    pub decision: DecisionType,
+    /// the modified content, the reply, the reason of a block or a flag, or the content before a
+    /// redaction
+    pub content: Option<String>,
+    pub error: Option<String>,
+    pub latency_ms: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// Record the decisions of the agents which ran on a message, `message_id` is `None` when
+    /// the message was blocked
+    pub async fn record_decisions(
+        &self,
+        chat_id: u64,
+        message_id: Option<i64>,
+        runs: &[AgentRun],
+    ) -> Result<(), AppError> {
+        for run in runs {
+            let (decision, content) = match &run.decision {
+                Some(AgentDecision::Modify(s)) => (DecisionType::Modify, Some(s)),
+                Some(AgentDecision::Reply(s)) => (DecisionType::Reply, Some(s)),
+                Some(AgentDecision::Delete(reason)) => (DecisionType::Delete, Some(reason)),
+                // the message has the redacted content, the audit keeps what was redacted
+                Some(AgentDecision::Redact(_)) => {
+                    (DecisionType::Redact, run.redacted_from.as_ref())
+                }
+                Some(AgentDecision::Flag(reason)) => (DecisionType::Flag, Some(reason)),
+                Some(AgentDecision::None) => (DecisionType::None, None),
+                None => (DecisionType::Failed, None),
+            };
+            sqlx::query(
+                r#"
+                INSERT INTO agent_decisions (chat_id, message_id, agent_id, decision, content,
+                    error, latency_ms)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            )
+            .bind(chat_id as i64)
+            .bind(message_id)
+            .bind(run.agent.id)
+            .bind(decision)
+            .bind(content)
+            .bind(&run.error)
+            .bind(run.latency.as_millis() as i32)
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Decisions of the agents on a message, in the order they ran
+    pub async fn list_decisions(
+        &self,
+        message_id: u64,
+    ) -> Result<Vec<AgentDecisionRecord>, AppError> {
+        let decisions = sqlx::query_as(
+            r#"
+            SELECT * FROM agent_decisions WHERE message_id = $1 ORDER BY id ASC
+        "#,
+        )
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(decisions)
+    }
+}
@@ -11,7 +11,7 @@ impl ChatFile {
         let hash = Sha1::digest(data);
         Self {
             ws_id,
-            ext: filename.split('.').last().unwrap_or("txt").to_string(),
+            ext: filename.split('.').next_back().unwrap_or("txt").to_string(),
             hash: hex::encode(hash),
         }
     }
@@ -24,6 +24,17 @@ impl ChatFile {
         base_dir.join(self.hash_to_path())
     }
 
+    /// The mime type of the file, guessed from its extension
+    pub fn mime_type(&self) -> String {
+        mime_guess::from_ext(&self.ext)
+            .first_or_octet_stream()
+            .to_string()
+    }
+
+    pub fn is_image(&self) -> bool {
+        self.mime_type().starts_with("image/")
+    }
+
     // split hash into 3 parts, first 2 with 3 chars
     fn hash_to_path(&self) -> String {
         let (part1, part2) = self.hash.split_at(3);
@@ -80,6 +91,9 @@ mod tests {
         assert_eq!(file.ws_id, 1);
         assert_eq!(file.ext, "txt");
         assert_eq!(file.hash, "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d");
+        assert_eq!(file.mime_type(), "text/plain");
+        assert!(!file.is_image());
+        assert!(ChatFile::new(1, "cat.png", b"hello").is_image());
 
         let file: ChatFile = "/files/1/b47/459/db6c6288890f77fd20d105e2240fe0fd5a.toml".parse()?;
         assert_eq!(file.ws_id, 1);
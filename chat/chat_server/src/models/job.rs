@@ -0,0 +1,364 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToSchema, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "agent_job_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+/// What a tap agent does with a message, from `args.task`
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "agent_task", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum TapTask {
+    Summarize,
+    Tag,
+    ActionItems,
+    /// only what the prompt of the agent asks
+    #[default]
+    Custom,
+}
+
+/// A tap agent to run on a message, picked by a worker
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct AgentJob {
+    pub id: i64,
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub agent_id: i64,
+    pub status: JobStatus,
+    pub attempts: i32,
+    pub run_at: DateTime<Utc>,
+    pub locked_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// What a tap agent made of a message
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct AgentResult {
+    pub id: i64,
+    pub job_id: Option<i64>,
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub agent_id: Option<i64>,
+    pub task: TapTask,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// Enqueue a job for each of the agents on a new message
+    pub async fn enqueue_jobs(
+        &self,
+        executor: impl PgExecutor<'_>,
+        chat_id: u64,
+        message_id: u64,
+        agent_ids: &[i64],
+    ) -> Result<Vec<AgentJob>, AppError> {
+        let jobs = sqlx::query_as(
+            r#"
+            INSERT INTO agent_jobs (chat_id, message_id, agent_id)
+            SELECT $1, $2, agent_id FROM UNNEST($3::BIGINT[]) AS agent_id
+            RETURNING *
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .bind(agent_ids)
+        .fetch_all(executor)
+        .await?;
+        Ok(jobs)
+    }
+
+    /// Pick the next job which is due, or which has been running for longer than the lock
+    /// timeout because its worker died. Jobs locked by other workers are skipped, so any
+    /// number of workers can poll the queue. A timed out job which ran out of attempts is
+    /// failed instead of being run again.
+    pub async fn claim_job(&self) -> Result<Option<AgentJob>, AppError> {
+        let config = &self.config.jobs;
+        sqlx::query(
+            r#"
+            UPDATE agent_jobs
+                SET
+                    status = 'failed',
+                    error = 'job lock timed out',
+                    locked_at = NULL,
+                    updated_at = NOW()
+            WHERE status = 'running'
+                AND locked_at < NOW() - make_interval(secs => $1)
+                AND attempts >= $2
+        "#,
+        )
+        .bind(config.lock_timeout_secs as f64)
+        .bind(config.max_attempts as i32)
+        .execute(&self.pool)
+        .await?;
+
+        let job = sqlx::query_as(
+            r#"
+            UPDATE agent_jobs
+                SET
+                    status = 'running',
+                    attempts = attempts + 1,
+                    locked_at = NOW(),
+                    updated_at = NOW()
+            WHERE id = (
+                SELECT id FROM agent_jobs
+                WHERE (status = 'pending' AND run_at <= NOW())
+                    OR (status = 'running'
+                        AND locked_at < NOW() - make_interval(secs => $1)
+                        AND attempts < $2)
+                ORDER BY run_at ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+        "#,
+        )
+        .bind(config.lock_timeout_secs as f64)
+        .bind(config.max_attempts as i32)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(job)
+    }
+
+    /// Mark a job as done, along with the result of its agent if it made one. Nothing is
+    /// recorded when the job was handed to another worker after its lock timed out.
+    pub async fn complete_job(
+        &self,
+        job: &AgentJob,
+        result: Option<(TapTask, String)>,
+    ) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let updated = sqlx::query(
+            r#"
+            UPDATE agent_jobs
+                SET
+                    status = 'done',
+                    error = NULL,
+                    locked_at = NULL,
+                    updated_at = NOW()
+            WHERE id = $1 AND status = 'running' AND attempts = $2
+        "#,
+        )
+        .bind(job.id)
+        .bind(job.attempts)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            warn!("agent job {} was taken over, dropping its result", job.id);
+            return Ok(());
+        }
+        if let Some((task, content)) = result {
+            sqlx::query(
+                r#"
+                INSERT INTO agent_results (job_id, chat_id, message_id, agent_id, task, content)
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            )
+            .bind(job.id)
+            .bind(job.chat_id)
+            .bind(job.message_id)
+            .bind(job.agent_id)
+            .bind(task)
+            .bind(content)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Retry a failed job later with an exponential backoff, or give up on it once it ran out of
+    /// attempts. Returns None when the job was handed to another worker after its lock timed out.
+    pub async fn fail_job(
+        &self,
+        job: &AgentJob,
+        error: &str,
+    ) -> Result<Option<JobStatus>, AppError> {
+        let config = &self.config.jobs;
+        let (status, delay) = if job.attempts as u32 >= config.max_attempts {
+            (JobStatus::Failed, 0)
+        } else {
+            let exp = (job.attempts.max(1) - 1).min(16) as u32;
+            (
+                JobStatus::Pending,
+                config.backoff_secs.saturating_mul(1 << exp),
+            )
+        };
+        let updated = sqlx::query(
+            r#"
+            UPDATE agent_jobs
+                SET
+                    status = $1,
+                    error = $2,
+                    run_at = NOW() + make_interval(secs => $3),
+                    locked_at = NULL,
+                    updated_at = NOW()
+            WHERE id = $4 AND status = 'running' AND attempts = $5
+        "#,
+        )
+        .bind(status)
+        .bind(error)
+        .bind(delay as f64)
+        .bind(job.id)
+        .bind(job.attempts)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok((updated > 0).then_some(status))
+    }
+
+    /// Jobs enqueued on a message
+    pub async fn list_jobs(&self, message_id: u64) -> Result<Vec<AgentJob>, AppError> {
+        let jobs = sqlx::query_as(
+            r#"
+            SELECT * FROM agent_jobs WHERE message_id = $1 ORDER BY id ASC
+        "#,
+        )
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(jobs)
+    }
+
+    /// Results of the tap agents on a message
+    pub async fn list_agent_results(&self, message_id: u64) -> Result<Vec<AgentResult>, AppError> {
+        let results = sqlx::query_as(
+            r#"
+            SELECT * FROM agent_results WHERE message_id = $1 ORDER BY id ASC
+        "#,
+        )
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn job_queue_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let jobs = state.enqueue_jobs(&state.pool, 1, 1, &[1]).await?;
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].status, JobStatus::Pending);
+
+        let job = state.claim_job().await?.expect("job should be claimed");
+        assert_eq!(job.id, jobs[0].id);
+        assert_eq!(job.status, JobStatus::Running);
+        assert_eq!(job.attempts, 1);
+        // a running job is not picked again
+        assert!(state.claim_job().await?.is_none());
+
+        state
+            .complete_job(&job, Some((TapTask::Summarize, "a greeting".to_string())))
+            .await?;
+        let jobs = state.list_jobs(1).await?;
+        assert_eq!(jobs[0].status, JobStatus::Done);
+        let results = state.list_agent_results(1).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].task, TapTask::Summarize);
+        assert_eq!(results[0].content, "a greeting");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_job_should_be_retried() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.enqueue_jobs(&state.pool, 1, 1, &[1]).await?;
+        let max_attempts = state.config.jobs.max_attempts;
+        for attempt in 1..=max_attempts {
+            let job = state.claim_job().await?.expect("job should be claimed");
+            assert_eq!(job.attempts as u32, attempt);
+            let status = state
+                .fail_job(&job, "boom")
+                .await?
+                .expect("job should be owned");
+            if attempt < max_attempts {
+                assert_eq!(status, JobStatus::Pending);
+                // backed off, not due yet
+                assert!(state.claim_job().await?.is_none());
+                sqlx::query("UPDATE agent_jobs SET run_at = NOW()")
+                    .execute(&state.pool)
+                    .await?;
+            } else {
+                assert_eq!(status, JobStatus::Failed);
+            }
+        }
+        assert!(state.claim_job().await?.is_none());
+        let jobs = state.list_jobs(1).await?;
+        assert_eq!(jobs[0].status, JobStatus::Failed);
+        assert_eq!(jobs[0].error.as_deref(), Some("boom"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn timed_out_job_should_not_be_finished_by_its_first_worker() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.enqueue_jobs(&state.pool, 1, 1, &[1]).await?;
+        let first = state.claim_job().await?.expect("job should be claimed");
+        sqlx::query("UPDATE agent_jobs SET locked_at = NOW() - INTERVAL '1 day'")
+            .execute(&state.pool)
+            .await?;
+        let second = state
+            .claim_job()
+            .await?
+            .expect("job should be claimed again");
+        assert_eq!(second.attempts, 2);
+
+        state
+            .complete_job(&first, Some((TapTask::Summarize, "late".to_string())))
+            .await?;
+        assert!(state.fail_job(&first, "late").await?.is_none());
+        let jobs = state.list_jobs(1).await?;
+        assert_eq!(jobs[0].status, JobStatus::Running);
+        assert!(state.list_agent_results(1).await?.is_empty());
+
+        state
+            .complete_job(
+                &second,
+                Some((TapTask::Summarize, "a greeting".to_string())),
+            )
+            .await?;
+        assert_eq!(state.list_agent_results(1).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn timed_out_job_should_fail_once_out_of_attempts() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.enqueue_jobs(&state.pool, 1, 1, &[1]).await?;
+        for _ in 0..state.config.jobs.max_attempts {
+            state.claim_job().await?.expect("job should be claimed");
+            sqlx::query("UPDATE agent_jobs SET locked_at = NOW() - INTERVAL '1 day'")
+                .execute(&state.pool)
+                .await?;
+        }
+        assert!(state.claim_job().await?.is_none());
+        let jobs = state.list_jobs(1).await?;
+        assert_eq!(jobs[0].status, JobStatus::Failed);
+        Ok(())
+    }
+}
@@ -1,17 +1,44 @@
 use std::str::FromStr;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, Postgres, Transaction};
 use tracing::warn;
 use utoipa::{IntoParams, ToSchema};
 
-use crate::{agent::AgentVariant, AppError, AppState, ChatFile};
-use chat_core::{Agent, AgentContext, AgentDecision, ChatType, Message};
+use crate::{
+    pipeline::{select_agents, AgentPipeline, PipelineOutput},
+    AppError, AppState, ChatFile,
+};
+use chat_core::{resolve_mentions, AgentContext, AgentImage, AgentType, Message};
+
+/// Max number of previous messages agents get along with a new one
+const AGENT_HISTORY_LEN: u64 = 20;
 
 #[derive(Debug, ToSchema, Clone, Serialize, Deserialize)]
 pub struct CreateMessage {
     pub content: String,
     #[serde(default)]
     pub files: Vec<String>,
+    /// the message to reply to, the reply goes in its thread
+    #[serde(default)]
+    pub reply_to: Option<u64>,
+}
+
+#[derive(Debug, ToSchema, Clone, Serialize, Deserialize)]
+pub struct UpdateMessage {
+    pub content: String,
+}
+
+/// The content of a message before one of its edits
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MessageEdit {
+    pub id: i64,
+    pub message_id: i64,
+    pub content: String,
+    pub modified_content: Option<String>,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, IntoParams, ToSchema, Clone, Serialize, Deserialize)]
@@ -35,89 +62,399 @@ impl AppState {
             ));
         }
 
-        // verify files exists
+        // verify files exists, in the workspace of the chat
+        let ws_id = match self.get_chat_by_id(chat_id).await? {
+            Some(chat) => chat.ws_id,
+            None => return Err(AppError::NotFound(format!("chat id {}", chat_id))),
+        };
+        let mut files = Vec::with_capacity(input.files.len());
         for s in &input.files {
             let file = ChatFile::from_str(s)?;
-            if !file.path(base_dir).exists() {
+            if file.ws_id != ws_id as u64 || !file.path(base_dir).exists() {
                 return Err(AppError::CreateMessageError(format!(
                     "file {} doesn't exist",
                     s
                 )));
             }
+            files.push(file);
         }
 
-        // if we have agent, apply it and get the result
-        let mut agents = self.list_agents(chat_id).await?;
-        let decision = if let Some(agent) = agents.pop() {
-            let agent: AgentVariant = agent.into();
-            match agent {
-                AgentVariant::Proxy(agent) => {
-                    agent
-                        .process(&input.content, &AgentContext::default())
-                        .await?
+        // a reply to a message in a thread goes in the same thread
+        let (reply_to, thread_id) = match input.reply_to {
+            Some(id) => match self.find_message(chat_id, id).await? {
+                Some(parent) if parent.deleted_at.is_none() => {
+                    (Some(parent.id), Some(parent.thread_id.unwrap_or(parent.id)))
                 }
-                _ => AgentDecision::None,
-            }
-        } else {
-            AgentDecision::None
+                _ => {
+                    return Err(AppError::CreateMessageError(format!(
+                        "message {} to reply to doesn't exist",
+                        id
+                    )))
+                }
+            },
+            None => (None, None),
         };
 
-        let modify_content = match decision {
-            AgentDecision::Modify(ref s) => Some(s),
-            _ => None,
+        // run the agents the message invokes on it, without their mentions. Tap agents run
+        // later in jobs
+        let mut agents = self.list_agents(chat_id).await?;
+        agents.retain(|agent| agent.enabled);
+        let invocation = resolve_mentions(&input.content, agents.iter().map(|a| a.name.as_str()));
+        let (taps, agents): (Vec<_>, Vec<_>) = select_agents(agents, &invocation)
+            .into_iter()
+            .partition(|agent| agent.r#type == AgentType::Tap);
+        let pipeline = AgentPipeline::new(agents, self);
+        let mut output = if pipeline.is_empty() {
+            PipelineOutput::default()
+        } else {
+            let ctx = self
+                .agent_context(chat_id, user_id, &files, None, thread_id.map(|id| id as _))
+                .await?;
+            pipeline.run(&invocation.content, &ctx).await
         };
+        self.record_runs_usage(&output).await;
+        self.ensure_accepted(chat_id, None, &mut output).await?;
 
-        // create message
+        // the bots of the agents which reply, created before so that they don't hold the
+        // transaction
+        let mut replies = Vec::with_capacity(output.replies.len());
+        for (agent, reply) in output.replies.drain(..) {
+            replies.push((self.agent_bot(&agent).await?, reply));
+        }
+
+        // create the message along with its flags, replies and jobs, all or nothing, so that a
+        // failure doesn't leave a message the sender would send again
+        let mut tx = self.pool.begin().await?;
         let message: Message = sqlx::query_as(
             r#"
-            INSERT INTO messages (chat_id, sender_id, content, files, modified_content)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO messages (chat_id, sender_id, content, files, modified_content, reply_to,
+                thread_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
             RETURNING *
         "#,
         )
         .bind(chat_id as i64)
         .bind(user_id as i64)
-        .bind(input.content)
+        .bind(output.redacted.take().unwrap_or(input.content))
         .bind(input.files)
-        .bind(modify_content)
-        .fetch_one(&self.pool)
+        .bind(output.modified_content.take())
+        .bind(reply_to)
+        .bind(thread_id)
+        .fetch_one(&mut *tx)
         .await?;
 
-        // if decision is reply, create a new message
-        if let AgentDecision::Reply(reply) = decision {
-            let chat = self
-                .get_chat_by_id(chat_id)
-                .await?
-                .expect("chat should exist");
-            if chat.r#type != ChatType::Single {
-                // log error
-                warn!(
-                    "reply decision found in non single chat {}. reply: {}",
-                    chat_id, reply
-                );
-            }
-            let other_user_id = chat
-                .members
-                .into_iter()
-                .find(|id| id != &(user_id as i64))
-                .expect("other user should exist");
-            let _: (i64,) = sqlx::query_as(
-                r#"
-                        INSERT INTO messages (chat_id, sender_id, content)
-                        VALUES ($1, $2, $3)
-                        RETURNING id
-                    "#,
-            )
-            .bind(chat_id as i64)
-            .bind(other_user_id)
-            .bind(reply)
-            .fetch_one(&self.pool)
+        for (agent, reason) in &output.flags {
+            self.flag_message(&mut *tx, &message, agent, reason).await?;
+        }
+
+        for (bot_id, reply) in replies {
+            create_reply(&mut *tx, &message, bot_id, reply).await?;
+        }
+
+        if !taps.is_empty() {
+            let ids: Vec<_> = taps.iter().map(|agent| agent.id).collect();
+            self.enqueue_jobs(&mut *tx, chat_id, message.id as _, &ids)
+                .await?;
+        }
+        tx.commit().await?;
+
+        if let Err(e) = self
+            .record_decisions(chat_id, Some(message.id), &output.runs)
+            .await
+        {
+            warn!(
+                "failed to record agent decisions on message {}: {}",
+                message.id, e
+            );
+        }
+
+        Ok(message)
+    }
+
+    /// Edit a message, only its sender can. Moderation agents check the new content, and the
+    /// previous one is kept in the edit history.
+    pub async fn update_message(
+        &self,
+        input: UpdateMessage,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        if input.content.is_empty() {
+            return Err(AppError::UpdateMessageError(
+                "content cannot be empty".to_string(),
+            ));
+        }
+        let message = self
+            .get_sender_message(chat_id, message_id, user_id)
             .await?;
+
+        // the content other agents made of the previous content doesn't apply to the new one,
+        // only moderation agents run again so that edits can't get around them
+        let mut agents = self.list_agents(chat_id).await?;
+        agents.retain(|agent| agent.enabled && agent.r#type == AgentType::Moderation);
+        let pipeline = AgentPipeline::new(agents, self);
+        let mut output = if pipeline.is_empty() {
+            PipelineOutput::default()
+        } else {
+            let ctx = self
+                .agent_context(
+                    chat_id,
+                    user_id,
+                    &[],
+                    Some(message_id),
+                    message.thread_id.map(|id| id as _),
+                )
+                .await?;
+            pipeline.run(&input.content, &ctx).await
+        };
+        self.record_runs_usage(&output).await;
+        self.ensure_accepted(chat_id, Some(message.id), &mut output)
+            .await?;
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO message_edits (message_id, content, modified_content)
+            VALUES ($1, $2, $3)
+        "#,
+        )
+        .bind(message.id)
+        .bind(&message.content)
+        .bind(&message.modified_content)
+        .execute(&mut *tx)
+        .await?;
+        let message: Message = sqlx::query_as(
+            r#"
+            UPDATE messages
+                SET
+                    content = $1,
+                    modified_content = $2,
+                    edited_at = NOW()
+            WHERE id = $3
+            RETURNING id, chat_id, sender_id, content, modified_content, files, created_at,
+                edited_at, deleted_at, reply_to, thread_id, reply_count
+        "#,
+        )
+        .bind(output.redacted.take().unwrap_or(input.content))
+        .bind(output.modified_content.take())
+        .bind(message.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        for (agent, reason) in &output.flags {
+            self.flag_message(&mut *tx, &message, agent, reason).await?;
         }
+        tx.commit().await?;
 
+        if let Err(e) = self
+            .record_decisions(chat_id, Some(message.id), &output.runs)
+            .await
+        {
+            warn!(
+                "failed to record agent decisions on message {}: {}",
+                message.id, e
+            );
+        }
         Ok(message)
     }
 
+    /// Delete a message, only its sender can. The message stays as a tombstone without its
+    /// content, files and edit history.
+    pub async fn delete_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let message = self
+            .get_sender_message(chat_id, message_id, user_id)
+            .await?;
+        let mut tx = self.pool.begin().await?;
+        tombstone_message(&mut tx, message.id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// What a message said before each of its edits, oldest first
+    pub async fn list_message_edits(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Vec<MessageEdit>, AppError> {
+        let edits = sqlx::query_as(
+            r#"
+            SELECT e.* FROM message_edits e
+            JOIN messages m ON m.id = e.message_id
+            WHERE m.chat_id = $1 AND e.message_id = $2
+            ORDER BY e.id ASC
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(edits)
+    }
+
+    /// A message of a chat, deleted ones included
+    pub async fn find_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Option<Message>, AppError> {
+        let message = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, modified_content, files, created_at,
+                edited_at, deleted_at, reply_to, thread_id, reply_count
+            FROM messages
+            WHERE chat_id = $1 AND id = $2
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(message)
+    }
+
+    /// A message which is not deleted, the user should be its sender
+    async fn get_sender_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        let message = self
+            .find_message(chat_id, message_id)
+            .await?
+            .filter(|m| m.deleted_at.is_none());
+        match message {
+            Some(message) if message.sender_id == user_id as i64 => Ok(message),
+            Some(_) => Err(AppError::PermissionDenied(format!(
+                "only the sender can change message {}",
+                message_id
+            ))),
+            None => Err(AppError::NotFound(format!("message id {}", message_id))),
+        }
+    }
+
+    async fn record_runs_usage(&self, output: &PipelineOutput) {
+        for run in &output.runs {
+            if let Some(completion) = &run.completion {
+                // usage is for accounting only, it should not fail the message
+                if let Err(e) = self.record_usage(&run.agent, completion).await {
+                    warn!("failed to record usage of agent {}: {}", run.agent.id, e);
+                }
+            }
+        }
+    }
+
+    /// Fail when an agent blocked the message or a moderation agent rejected it, along with
+    /// the decisions which led to it
+    async fn ensure_accepted(
+        &self,
+        chat_id: u64,
+        message_id: Option<i64>,
+        output: &mut PipelineOutput,
+    ) -> Result<(), AppError> {
+        if output.blocked.is_none() && output.rejected.is_none() {
+            return Ok(());
+        }
+        if let Err(e) = self
+            .record_decisions(chat_id, message_id, &output.runs)
+            .await
+        {
+            warn!(
+                "failed to record agent decisions in chat {}: {}",
+                chat_id, e
+            );
+        }
+        match (output.blocked.take(), output.rejected.take()) {
+            (Some(e), _) => Err(e.into()),
+            (_, reason) => Err(AppError::MessageRejected(reason.unwrap_or_default())),
+        }
+    }
+
+    /// What agents know about a message besides its content: the chat, the sender, the
+    /// recent history before `last_id` (or the latest one) and the attached images. The
+    /// history of a message in a thread is the root of the thread and its replies.
+    pub(crate) async fn agent_context(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        files: &[ChatFile],
+        last_id: Option<u64>,
+        thread_id: Option<u64>,
+    ) -> Result<AgentContext, AppError> {
+        let chat = self.get_chat_by_id(chat_id).await?;
+        let input = ListMessage {
+            last_id,
+            limit: AGENT_HISTORY_LEN,
+        };
+        let mut history = match thread_id {
+            Some(thread_id) => {
+                let mut replies = self.list_thread(input, chat_id, thread_id).await?;
+                replies.extend(self.find_message(chat_id, thread_id).await?);
+                replies
+            }
+            None => self.list_message(input, chat_id).await?,
+        };
+        history.retain(|m| m.deleted_at.is_none());
+        history.reverse();
+        let (workspace, members) = match &chat {
+            Some(chat) => {
+                // agent bots and former members who wrote in the history are not members
+                let mut ids = chat.members.clone();
+                ids.extend(history.iter().map(|m| m.sender_id));
+                ids.sort_unstable();
+                ids.dedup();
+                (
+                    self.find_workspace_by_id(chat.ws_id as _).await?,
+                    self.fetch_chat_user_by_ids(&ids).await?,
+                )
+            }
+            None => (None, vec![]),
+        };
+        let images = match &chat {
+            Some(chat) => self.message_images(chat.ws_id as _, files).await?,
+            None => vec![],
+        };
+        Ok(AgentContext {
+            images,
+            workspace,
+            chat,
+            sender: self.find_user_by_id(user_id).await?,
+            members,
+            history,
+        })
+    }
+
+    /// The images attached to a message, so that agents can see them. Only the files of the
+    /// workspace of the chat can be read.
+    async fn message_images(
+        &self,
+        ws_id: u64,
+        files: &[ChatFile],
+    ) -> Result<Vec<AgentImage>, AppError> {
+        let base_dir = &self.config.server.base_dir;
+        let mut images = vec![];
+        for file in files.iter().filter(|f| f.is_image()) {
+            if file.ws_id != ws_id {
+                return Err(AppError::ChatFileError(format!(
+                    "file {} is not in workspace {}",
+                    file.url(),
+                    ws_id
+                )));
+            }
+            let data = tokio::fs::read(file.path(base_dir)).await?;
+            images.push(AgentImage {
+                mime_type: file.mime_type(),
+                data,
+            });
+        }
+        Ok(images)
+    }
+
     pub async fn list_message(
         &self,
         input: ListMessage,
@@ -129,11 +466,13 @@ impl AppState {
             1..=100 => input.limit as _,
             _ => 100,
         };
-        let messages: Vec<Message> = sqlx::query_as(
+        let mut messages: Vec<Message> = sqlx::query_as(
             r#"
-        SELECT id, chat_id, sender_id, content, modified_content, files, created_at
+        SELECT id, chat_id, sender_id, content, modified_content, files, created_at, edited_at,
+            deleted_at, reply_to, thread_id, reply_count
         FROM messages
         WHERE chat_id = $1
+        AND thread_id IS NULL
         AND id < $2
         ORDER BY id DESC
         LIMIT $3
@@ -144,13 +483,103 @@ impl AppState {
         .bind(limit)
         .fetch_all(&self.pool)
         .await?;
+        self.attach_reactions(&mut messages).await?;
+        Ok(messages)
+    }
+
+    /// Replies in the thread of a root message, newest first
+    pub async fn list_thread(
+        &self,
+        input: ListMessage,
+        chat_id: u64,
+        thread_id: u64,
+    ) -> Result<Vec<Message>, AppError> {
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = match input.limit {
+            0 => i64::MAX,
+            1..=100 => input.limit as _,
+            _ => 100,
+        };
+        let mut messages: Vec<Message> = sqlx::query_as(
+            r#"
+        SELECT id, chat_id, sender_id, content, modified_content, files, created_at, edited_at,
+            deleted_at, reply_to, thread_id, reply_count
+        FROM messages
+        WHERE chat_id = $1
+        AND thread_id = $2
+        AND id < $3
+        ORDER BY id DESC
+        LIMIT $4
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(thread_id as i64)
+        .bind(last_id as i64)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        self.attach_reactions(&mut messages).await?;
         Ok(messages)
     }
 }
 
+/// Post the reply of an agent to a message as the bot user of the agent. The reply goes in the
+/// thread of the message when it is in one.
+async fn create_reply(
+    executor: impl PgExecutor<'_>,
+    message: &Message,
+    bot_id: i64,
+    reply: String,
+) -> Result<(), AppError> {
+    let reply_to = message.thread_id.map(|_| message.id);
+    let _: (i64,) = sqlx::query_as(
+        r#"
+            INSERT INTO messages (chat_id, sender_id, content, reply_to, thread_id)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+        "#,
+    )
+    .bind(message.chat_id)
+    .bind(bot_id)
+    .bind(reply)
+    .bind(reply_to)
+    .bind(message.thread_id)
+    .fetch_one(executor)
+    .await?;
+    Ok(())
+}
+
+/// Delete a message, it stays as a tombstone without its content, files and edit history so
+/// that its thread and reactions stay in place
+pub(crate) async fn tombstone_message(
+    tx: &mut Transaction<'_, Postgres>,
+    message_id: i64,
+) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM message_edits WHERE message_id = $1")
+        .bind(message_id)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query(
+        r#"
+        UPDATE messages
+            SET
+                content = '',
+                modified_content = NULL,
+                files = '{}',
+                deleted_at = NOW()
+        WHERE id = $1
+    "#,
+    )
+    .bind(message_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::DecisionType;
     use anyhow::Result;
 
     #[tokio::test]
@@ -159,6 +588,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec![],
+            reply_to: None,
         };
         let message = state.create_message(input, 1, 1).await?;
         assert_eq!(message.content, "hello");
@@ -167,6 +597,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec!["1".to_string()],
+            reply_to: None,
         };
         let err = state.create_message(input, 1, 1).await.unwrap_err();
         assert_eq!(err.to_string(), "invalid chat file path: 1");
@@ -176,6 +607,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec![url],
+            reply_to: None,
         };
         let message = state.create_message(input, 1, 1).await?;
         assert_eq!(message.content, "hello");
@@ -183,6 +615,220 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn proxy_agent_should_run_on_new_message() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        // the translation agent of chat 1 uses the mock adapter, which echoes the message. Its
+        // prompt is sent as the system prompt, so the content is unchanged
+        assert_eq!(message.modified_content, None);
+        let decisions = state.list_decisions(message.id as _).await?;
+        assert_eq!(decisions.len(), 1);
+        assert_eq!(decisions[0].decision, DecisionType::Modify);
+        assert_eq!(decisions[0].content.as_deref(), Some("hello"));
+        let since = chrono::Utc::now() - chrono::Duration::minutes(1);
+        let usage = state.workspace_usage(1, since).await?;
+        assert!(usage.total_tokens > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reply_agent_should_reply_as_its_bot() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 4 is a group of users 1, 2 and 3
+        let input = crate::CreateAgent::new(
+            "assistant",
+            AgentType::Reply,
+            chat_core::AdapterType::Ollama,
+            "llama3.2",
+            "You are a helpful assistant",
+            serde_json::json!({}),
+        );
+        let agent = state.create_agent(input, 4).await?;
+        let bot_id = agent.bot_id.expect("agent should have a bot");
+        let bot = state.find_user_by_id(bot_id as _).await?;
+        assert_eq!(bot.expect("bot should exist").fullname, "assistant");
+
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        let message = state.create_message(input, 4, 1).await?;
+        let input = ListMessage {
+            last_id: None,
+            limit: 1,
+        };
+        let reply = state.list_message(input, 4).await?.remove(0);
+        assert!(reply.id > message.id);
+        assert_eq!(reply.sender_id, bot_id);
+        assert_eq!(reply.content, "hello");
+        let users = state.fetch_chat_user_by_ids(&[bot_id]).await?;
+        assert!(users[0].is_bot);
+
+        // the bot is in the context of the next message, its reply is the assistant's turn
+        let ctx = state.agent_context(4, 2, &[], None, None).await?;
+        assert_eq!(ctx.sender_name(bot_id), Some("assistant"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mentioned_agent_should_reply() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = crate::CreateAgent::new(
+            "helper",
+            AgentType::Reply,
+            chat_core::AdapterType::Ollama,
+            "llama3.2",
+            "You are a helpful assistant",
+            serde_json::json!({ "trigger": "mention" }),
+        );
+        state.create_agent(input, 4).await?;
+        let last_message = |state: AppState| async move {
+            let input = ListMessage {
+                last_id: None,
+                limit: 1,
+            };
+            state.list_message(input, 4).await.map(|mut m| m.remove(0))
+        };
+
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        let message = state.create_message(input, 4, 1).await?;
+        assert_eq!(last_message(state.clone()).await?.id, message.id);
+
+        let input = CreateMessage {
+            content: "@Helper how are you?".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        let message = state.create_message(input, 4, 1).await?;
+        assert_eq!(message.content, "@Helper how are you?");
+        // the mock adapter echoes the message without the mention
+        let reply = last_message(state.clone()).await?;
+        assert!(reply.id > message.id);
+        assert_eq!(reply.content, "how are you?");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replies_should_go_in_thread() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let reply = |content: &str, reply_to: u64| CreateMessage {
+            content: content.to_string(),
+            files: vec![],
+            reply_to: Some(reply_to),
+        };
+        let first = state.create_message(reply("hi", 1), 1, 2).await?;
+        assert_eq!(first.reply_to, Some(1));
+        assert_eq!(first.thread_id, Some(1));
+        // a reply to a reply stays in the thread of the root
+        let second = state
+            .create_message(reply("hi again", first.id as _), 1, 3)
+            .await?;
+        assert_eq!(second.reply_to, Some(first.id));
+        assert_eq!(second.thread_id, Some(1));
+
+        let input = ListMessage {
+            last_id: None,
+            limit: 1,
+        };
+        let thread = state.list_thread(input, 1, 1).await?;
+        assert_eq!(thread.len(), 1);
+        assert_eq!(thread[0].id, second.id);
+        let input = ListMessage {
+            last_id: Some(second.id as _),
+            limit: 10,
+        };
+        let thread = state.list_thread(input, 1, 1).await?;
+        assert_eq!(thread.len(), 1);
+        assert_eq!(thread[0].id, first.id);
+        // replies are not in the channel, the root counts them
+        let input = ListMessage {
+            last_id: None,
+            limit: 0,
+        };
+        let messages = state.list_message(input, 1).await?;
+        assert_eq!(messages.len(), 10);
+        let root = state.find_message(1, 1).await?.expect("root should exist");
+        assert_eq!(root.reply_count, 2);
+
+        state.delete_message(1, second.id as _, 3).await?;
+        let root = state.find_message(1, 1).await?.expect("root should exist");
+        assert_eq!(root.reply_count, 1);
+
+        let err = state.create_message(reply("hi", 999), 1, 2).await;
+        assert!(matches!(err, Err(AppError::CreateMessageError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn agent_should_reply_in_thread() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = crate::CreateAgent::new(
+            "helper",
+            AgentType::Reply,
+            chat_core::AdapterType::Ollama,
+            "llama3.2",
+            "You are a helpful assistant",
+            serde_json::json!({}),
+        );
+        state.create_agent(input, 4).await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        let root = state.create_message(input, 4, 1).await?;
+        let input = CreateMessage {
+            content: "how are you?".to_string(),
+            files: vec![],
+            reply_to: Some(root.id as _),
+        };
+        let message = state.create_message(input, 4, 2).await?;
+
+        let input = ListMessage {
+            last_id: None,
+            limit: 0,
+        };
+        let thread = state.list_thread(input, 4, root.id as _).await?;
+        assert_eq!(thread.len(), 2);
+        let reply = &thread[0];
+        assert_eq!(reply.reply_to, Some(message.id));
+        assert_eq!(reply.content, "how are you?");
+
+        // agents see the thread, root first
+        let ctx = state
+            .agent_context(4, 2, &[], None, Some(root.id as _))
+            .await?;
+        let ids: Vec<_> = ctx.history.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![root.id, message.id, reply.id]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn agent_context_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ctx = state.agent_context(1, 1, &[], None, None).await?;
+        assert_eq!(ctx.chat.expect("chat should exist").id, 1);
+        assert_eq!(ctx.workspace.expect("workspace should exist").id, 1);
+        assert_eq!(ctx.sender.expect("sender should exist").id, 1);
+        assert!(!ctx.members.is_empty());
+        // all the 10 messages of the chat in fixtures
+        assert_eq!(ctx.history.len(), 10);
+        assert!(ctx.history[0].id < ctx.history[1].id);
+        assert!(ctx.images.is_empty());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn list_message_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -211,6 +857,85 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn update_message_should_keep_history() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateMessage {
+            content: "Hello everyone!".to_string(),
+        };
+        let message = state.update_message(input, 1, 1, 1).await?;
+        assert_eq!(message.content, "Hello everyone!");
+        assert!(message.edited_at.is_some());
+        let edits = state.list_message_edits(1, 1).await?;
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].content, "Hello world!");
+
+        // only the sender can edit
+        let input = UpdateMessage {
+            content: "hacked".to_string(),
+        };
+        let err = state.update_message(input, 1, 1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        let input = UpdateMessage {
+            content: "".to_string(),
+        };
+        let err = state.update_message(input, 1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateMessageError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_should_leave_tombstone() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateMessage {
+            content: "Hello everyone!".to_string(),
+        };
+        state.update_message(input, 1, 1, 1).await?;
+        let err = state.delete_message(1, 1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        state.delete_message(1, 1, 1).await?;
+
+        let input = ListMessage {
+            last_id: Some(2),
+            limit: 1,
+        };
+        let messages = state.list_message(input, 1).await?;
+        assert_eq!(messages[0].id, 1);
+        assert_eq!(messages[0].content, "");
+        assert!(messages[0].deleted_at.is_some());
+        assert!(state.list_message_edits(1, 1).await?.is_empty());
+
+        let err = state.delete_message(1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        let input = UpdateMessage {
+            content: "back".to_string(),
+        };
+        let err = state.update_message(input, 1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn files_of_other_workspaces_should_be_rejected() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = ChatFile::new(2, "cat.png", b"png");
+        let path = file.path(&state.config.server.base_dir);
+        std::fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
+        std::fs::write(&path, b"png")?;
+
+        // chat 1 is in workspace 1
+        let input = CreateMessage {
+            content: "what is this?".to_string(),
+            files: vec![file.url()],
+            reply_to: None,
+        };
+        let err = state.create_message(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+        let err = state.message_images(1, &[file]).await.unwrap_err();
+        assert!(matches!(err, AppError::ChatFileError(_)));
+        Ok(())
+    }
+
     fn upload_dummy_file(state: &AppState) -> Result<String> {
         let file = ChatFile::new(1, "test.txt", b"hello world");
         let path = file.path(&state.config.server.base_dir);
@@ -1,15 +1,29 @@
 mod agent;
 mod chat;
+mod decision;
 mod file;
+mod job;
 mod message;
+mod moderation;
+mod reaction;
+mod read;
+mod search;
+mod usage;
 mod user;
 mod workspace;
 
 pub use agent::*;
 pub use chat::*;
+pub use decision::*;
 #[allow(unused)]
 pub use file::*;
+pub use job::*;
 pub use message::*;
+pub use moderation::*;
+pub use reaction::*;
+pub use read::*;
+pub use search::*;
+pub use usage::*;
 pub use user::*;
 
 use serde::{Deserialize, Serialize};
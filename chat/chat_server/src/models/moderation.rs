@@ -0,0 +1,312 @@
+use chat_core::{ChatAgent, Message};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{models::message::tombstone_message, AppError, AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToSchema, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "moderation_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationStatus {
+    Pending,
+    /// the message is fine and stays in the chat
+    Approved,
+    /// the message is deleted from the chat
+    Removed,
+}
+
+/// A message flagged by a moderation agent, waiting for a workspace admin
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ModerationItem {
+    pub id: i64,
+    pub ws_id: i64,
+    pub chat_id: i64,
+    pub message_id: Option<i64>,
+    pub agent_id: Option<i64>,
+    pub sender_id: i64,
+    pub content: String,
+    pub reason: String,
+    pub status: ModerationStatus,
+    pub reviewed_by: Option<i64>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, IntoParams, ToSchema, Clone, Serialize, Deserialize)]
+pub struct ListModeration {
+    /// all the items when not set
+    pub status: Option<ModerationStatus>,
+    pub last_id: Option<u64>,
+    pub limit: u64,
+}
+
+#[derive(Debug, ToSchema, Clone, Serialize, Deserialize)]
+pub struct ReviewModeration {
+    /// approved or removed
+    pub status: ModerationStatus,
+}
+
+impl AppState {
+    /// Keep a message flagged by a moderation agent for review
+    pub async fn flag_message(
+        &self,
+        executor: impl PgExecutor<'_>,
+        message: &Message,
+        agent: &ChatAgent,
+        reason: &str,
+    ) -> Result<ModerationItem, AppError> {
+        let item = sqlx::query_as(
+            r#"
+            INSERT INTO moderation_queue (ws_id, chat_id, message_id, agent_id, sender_id,
+                content, reason)
+            SELECT ws_id, id, $2, $3, $4, $5, $6 FROM chats WHERE id = $1
+            RETURNING *
+        "#,
+        )
+        .bind(message.chat_id)
+        .bind(message.id)
+        .bind(agent.id)
+        .bind(message.sender_id)
+        .bind(&message.content)
+        .bind(reason)
+        .fetch_one(executor)
+        .await?;
+        Ok(item)
+    }
+
+    /// The moderation queue of a workspace, newest first
+    pub async fn list_moderation(
+        &self,
+        ws_id: u64,
+        input: ListModeration,
+    ) -> Result<Vec<ModerationItem>, AppError> {
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = match input.limit {
+            0 => i64::MAX,
+            1..=100 => input.limit as _,
+            _ => 100,
+        };
+        let items = sqlx::query_as(
+            r#"
+            SELECT * FROM moderation_queue
+            WHERE ws_id = $1
+            AND ($2::moderation_status IS NULL OR status = $2)
+            AND id < $3
+            ORDER BY id DESC
+            LIMIT $4
+        "#,
+        )
+        .bind(ws_id as i64)
+        .bind(input.status)
+        .bind(last_id as i64)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(items)
+    }
+
+    /// Approve or remove a pending message of the workspace, removed messages are deleted like
+    /// their sender would
+    pub async fn review_moderation(
+        &self,
+        id: u64,
+        ws_id: u64,
+        reviewer_id: u64,
+        input: ReviewModeration,
+    ) -> Result<ModerationItem, AppError> {
+        if input.status == ModerationStatus::Pending {
+            return Err(AppError::ReviewModerationError(
+                "status should be approved or removed".to_string(),
+            ));
+        }
+        let mut tx = self.pool.begin().await?;
+        let item: Option<ModerationItem> = sqlx::query_as(
+            r#"
+            UPDATE moderation_queue
+                SET
+                    status = $1,
+                    reviewed_by = $2,
+                    reviewed_at = NOW()
+            WHERE id = $3 AND ws_id = $4 AND status = 'pending'
+            RETURNING *
+        "#,
+        )
+        .bind(input.status)
+        .bind(reviewer_id as i64)
+        .bind(id as i64)
+        .bind(ws_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(item) = item else {
+            return Err(AppError::NotFound(format!(
+                "pending moderation item id {}",
+                id
+            )));
+        };
+        if let (ModerationStatus::Removed, Some(message_id)) = (item.status, item.message_id) {
+            tombstone_message(&mut tx, message_id).await?;
+        }
+        tx.commit().await?;
+        Ok(item)
+    }
+
+    /// Only the owner of a workspace can review its moderation queue
+    pub async fn is_workspace_admin(&self, ws_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let ws = self.find_workspace_by_id(ws_id).await?;
+        Ok(ws.is_some_and(|ws| ws.owner_id == user_id as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateAgent, CreateMessage, DecisionType};
+    use anyhow::Result;
+    use chat_core::{AdapterType, AgentType};
+
+    async fn add_moderator(state: &AppState) -> Result<()> {
+        let input = CreateAgent::new(
+            "moderator",
+            AgentType::Moderation,
+            AdapterType::Ollama,
+            "llama3.2",
+            "No spam",
+            serde_json::json!({}),
+        );
+        state.create_agent(input, 1).await?;
+        Ok(())
+    }
+
+    fn message(content: &str) -> CreateMessage {
+        CreateMessage {
+            content: content.to_string(),
+            files: vec![],
+            reply_to: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn moderation_agent_should_reject_message() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        add_moderator(&state).await?;
+        // the mock adapter echoes the message, so the message is the verdict
+        let input = message(r#"{"action": "block", "reason": "spam"}"#);
+        let err = state.create_message(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::MessageRejected(ref r) if r == "spam"));
+        assert_eq!(state.list_message_count(1).await?, 10);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn moderation_agent_should_reject_edit() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        add_moderator(&state).await?;
+        let input = crate::UpdateMessage {
+            content: r#"{"action": "block", "reason": "spam"}"#.to_string(),
+        };
+        let err = state.update_message(input, 1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::MessageRejected(ref r) if r == "spam"));
+        assert!(state.list_message_edits(1, 1).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn flagged_message_should_be_reviewed() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        add_moderator(&state).await?;
+        let input = message(r#"{"action": "flag", "reason": "rude"}"#);
+        let message = state.create_message(input, 1, 1).await?;
+        let decisions = state.list_decisions(message.id as _).await?;
+        assert_eq!(decisions[0].decision, DecisionType::Flag);
+
+        let input = ListModeration {
+            status: Some(ModerationStatus::Pending),
+            last_id: None,
+            limit: 10,
+        };
+        let items = state.list_moderation(1, input.clone()).await?;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].message_id, Some(message.id));
+        assert_eq!(items[0].reason, "rude");
+
+        let reply = CreateMessage {
+            reply_to: Some(message.id as _),
+            ..self::message(r#"{"action": "allow"}"#)
+        };
+        state.create_message(reply, 1, 2).await?;
+
+        let review = ReviewModeration {
+            status: ModerationStatus::Removed,
+        };
+        let item = state
+            .review_moderation(items[0].id as _, 1, 1, review)
+            .await?;
+        assert_eq!(item.status, ModerationStatus::Removed);
+        assert_eq!(item.reviewed_by, Some(1));
+        // the message is a tombstone, its reply stays in its thread
+        assert_eq!(item.message_id, Some(message.id));
+        let removed = state.find_message(1, message.id as _).await?.unwrap();
+        assert!(removed.deleted_at.is_some());
+        assert!(removed.content.is_empty());
+        assert_eq!(state.list_message_count(1).await?, 11);
+        assert!(state.list_moderation(1, input).await?.is_empty());
+
+        // reviewed once only
+        let review = ReviewModeration {
+            status: ModerationStatus::Approved,
+        };
+        let err = state
+            .review_moderation(item.id as _, 1, 1, review)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redacted_content_should_never_be_shown() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        add_moderator(&state).await?;
+        let mut listener = sqlx::postgres::PgListener::connect_with(&state.pool).await?;
+        listener.listen("chat_message_created").await?;
+
+        // the mock adapter echoes the message, so the message is the verdict
+        let input = message(r#"{"action": "redact", "content": "call ***", "phone": "5550100"}"#);
+        let message = state.create_message(input, 1, 1).await?;
+        assert_eq!(message.content, "call ***");
+
+        let notification = listener.recv().await?;
+        assert!(!notification.payload().contains("5550100"));
+        let input = crate::ListMessage {
+            last_id: None,
+            limit: 1,
+        };
+        let messages = state.list_message(input, 1).await?;
+        assert_eq!(messages[0].id, message.id);
+        assert!(!serde_json::to_string(&messages)?.contains("5550100"));
+        let input = crate::SearchMessages {
+            q: "5550100".to_string(),
+            ..Default::default()
+        };
+        assert!(state.search_messages(input, 1, 1).await?.is_empty());
+
+        // only the audit knows what was redacted
+        let decisions = state.list_decisions(message.id as _).await?;
+        assert_eq!(decisions[0].decision, DecisionType::Redact);
+        assert!(decisions[0].content.as_ref().unwrap().contains("5550100"));
+        Ok(())
+    }
+
+    impl AppState {
+        async fn list_message_count(&self, chat_id: u64) -> Result<usize> {
+            let input = crate::ListMessage {
+                last_id: None,
+                limit: 0,
+            };
+            Ok(self.list_message(input, chat_id).await?.len())
+        }
+    }
+}
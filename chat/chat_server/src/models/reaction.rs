@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use chat_core::{Message, ReactionSummary};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+
+/// Max length of an emoji in bytes, enough for the longest ZWJ sequences or a `:shortcode:`
+const MAX_EMOJI_LEN: usize = 64;
+
+#[derive(Debug, ToSchema, Clone, Serialize, Deserialize)]
+pub struct CreateReaction {
+    pub emoji: String,
+}
+
+impl AppState {
+    /// React to a message with an emoji, reacting twice with the same emoji does nothing.
+    /// Returns the reactions to the message.
+    pub async fn add_reaction(
+        &self,
+        input: CreateReaction,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ReactionSummary>, AppError> {
+        let emoji = input.emoji.trim();
+        if emoji.is_empty() || emoji.len() > MAX_EMOJI_LEN || emoji.contains(char::is_whitespace) {
+            return Err(AppError::ReactionError(format!(
+                "invalid emoji {:?}",
+                input.emoji
+            )));
+        }
+        let message = self.find_message(chat_id, message_id).await?;
+        if message.is_none_or(|m| m.deleted_at.is_some()) {
+            return Err(AppError::NotFound(format!("message id {}", message_id)));
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, user_id, emoji, chat_id)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT DO NOTHING
+        "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .bind(chat_id as i64)
+        .execute(&self.pool)
+        .await?;
+        self.list_reactions(message_id).await
+    }
+
+    /// Remove the reaction of a user to a message. Returns the reactions to the message.
+    pub async fn remove_reaction(
+        &self,
+        emoji: &str,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ReactionSummary>, AppError> {
+        // the same emoji as the one added, which was trimmed
+        let emoji = emoji.trim();
+        let ret = sqlx::query(
+            r#"
+            DELETE FROM message_reactions
+            WHERE chat_id = $1 AND message_id = $2 AND user_id = $3 AND emoji = $4
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "reaction {} to message id {}",
+                emoji, message_id
+            )));
+        }
+        self.list_reactions(message_id).await
+    }
+
+    /// Reactions to a message
+    pub async fn list_reactions(&self, message_id: u64) -> Result<Vec<ReactionSummary>, AppError> {
+        let mut reactions = self.fetch_reactions(&[message_id as i64]).await?;
+        Ok(reactions.remove(&(message_id as i64)).unwrap_or_default())
+    }
+
+    /// Fill in the reactions of messages
+    pub(crate) async fn attach_reactions(&self, messages: &mut [Message]) -> Result<(), AppError> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<_> = messages.iter().map(|m| m.id).collect();
+        let mut reactions = self.fetch_reactions(&ids).await?;
+        for message in messages {
+            message.reactions = reactions.remove(&message.id).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    async fn fetch_reactions(
+        &self,
+        message_ids: &[i64],
+    ) -> Result<HashMap<i64, Vec<ReactionSummary>>, AppError> {
+        let rows: Vec<(i64, String, i64, Vec<i64>)> = sqlx::query_as(
+            r#"
+            SELECT message_id, emoji, COUNT(*), ARRAY_AGG(user_id ORDER BY created_at)
+            FROM message_reactions
+            WHERE message_id = ANY($1)
+            GROUP BY message_id, emoji
+            ORDER BY message_id, MIN(created_at)
+        "#,
+        )
+        .bind(message_ids)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut reactions: HashMap<i64, Vec<ReactionSummary>> = HashMap::new();
+        for (message_id, emoji, count, user_ids) in rows {
+            reactions
+                .entry(message_id)
+                .or_default()
+                .push(ReactionSummary {
+                    emoji,
+                    count,
+                    user_ids,
+                });
+        }
+        Ok(reactions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ListMessage;
+    use anyhow::Result;
+
+    fn reaction(emoji: &str) -> CreateReaction {
+        CreateReaction {
+            emoji: emoji.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reactions_should_be_summarized() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.add_reaction(reaction("👍"), 1, 1, 1).await?;
+        state.add_reaction(reaction("🎉"), 1, 1, 1).await?;
+        state.add_reaction(reaction("👍"), 1, 1, 2).await?;
+        // reacting twice changes nothing
+        let reactions = state.add_reaction(reaction("👍"), 1, 1, 2).await?;
+        assert_eq!(reactions.len(), 2);
+        assert_eq!(reactions[0].emoji, "👍");
+        assert_eq!(reactions[0].count, 2);
+        assert_eq!(reactions[0].user_ids, vec![1, 2]);
+        assert_eq!(reactions[1].emoji, "🎉");
+
+        let input = ListMessage {
+            last_id: Some(2),
+            limit: 1,
+        };
+        let messages = state.list_message(input, 1).await?;
+        assert_eq!(messages[0].reactions, reactions);
+
+        // 🎉 is now the first one used of the remaining reactions
+        let reactions = state.remove_reaction("👍", 1, 1, 1).await?;
+        assert_eq!(reactions[1].emoji, "👍");
+        assert_eq!(reactions[1].count, 1);
+        assert_eq!(reactions[1].user_ids, vec![2]);
+        let err = state.remove_reaction("👍", 1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // emojis are trimmed when added, so they are when removed
+        state.add_reaction(reaction(" 🚀 "), 1, 1, 1).await?;
+        let reactions = state.remove_reaction(" 🚀\n", 1, 1, 1).await?;
+        assert!(reactions.iter().all(|r| r.emoji != "🚀"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_reaction_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let err = state
+            .add_reaction(reaction(" "), 1, 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::ReactionError(_)));
+        let err = state
+            .add_reaction(reaction(&"a".repeat(65)), 1, 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::ReactionError(_)));
+        // message 1 is not in chat 2
+        let err = state
+            .add_reaction(reaction("👍"), 2, 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+}
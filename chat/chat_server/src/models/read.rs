@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+
+#[derive(Debug, Default, ToSchema, Clone, Serialize, Deserialize)]
+pub struct MarkRead {
+    /// the last message read, the latest one of the chat when not set
+    pub message_id: Option<u64>,
+}
+
+/// How far a member has read a chat
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ReadReceipt {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub last_read_id: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// Mark the messages of a chat as read up to a message. The receipt never goes back, marking
+    /// an older message as read keeps the current one.
+    pub async fn mark_read(
+        &self,
+        input: MarkRead,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ReadReceipt, AppError> {
+        let last_read_id: Option<i64> = match input.message_id {
+            Some(id) => self.find_message(chat_id, id).await?.map(|m| m.id),
+            None => {
+                sqlx::query_scalar("SELECT MAX(id) FROM messages WHERE chat_id = $1")
+                    .bind(chat_id as i64)
+                    .fetch_one(&self.pool)
+                    .await?
+            }
+        };
+        let Some(last_read_id) = last_read_id else {
+            return Err(AppError::NotFound(match input.message_id {
+                Some(id) => format!("message id {}", id),
+                None => format!("message in chat id {}", chat_id),
+            }));
+        };
+        let receipt = sqlx::query_as(
+            r#"
+            INSERT INTO chat_reads (chat_id, user_id, last_read_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (chat_id, user_id) DO UPDATE
+                SET
+                    last_read_id = GREATEST(chat_reads.last_read_id, EXCLUDED.last_read_id),
+                    updated_at = NOW()
+            RETURNING *
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(last_read_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(receipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn unread_count(chats: &[crate::ChatSummary], chat_id: i64) -> i64 {
+        chats
+            .iter()
+            .find(|c| c.chat.id == chat_id)
+            .map(|c| c.unread_count)
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn mark_read_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = MarkRead {
+            message_id: Some(5),
+        };
+        let receipt = state.mark_read(input, 1, 1).await?;
+        assert_eq!(receipt.last_read_id, 5);
+        // messages 7, 8 and 10 are left
+        let chats = state.fetch_chats(1, 1).await?;
+        assert_eq!(unread_count(&chats, 1), 3);
+
+        // reading an older message doesn't go back
+        let input = MarkRead {
+            message_id: Some(2),
+        };
+        let receipt = state.mark_read(input, 1, 1).await?;
+        assert_eq!(receipt.last_read_id, 5);
+
+        let receipt = state.mark_read(MarkRead::default(), 1, 1).await?;
+        assert_eq!(receipt.last_read_id, 10);
+        let chats = state.fetch_chats(1, 1).await?;
+        assert_eq!(unread_count(&chats, 1), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mark_read_unknown_message_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // message 1 is not in chat 2
+        let input = MarkRead {
+            message_id: Some(1),
+        };
+        let err = state.mark_read(input, 2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use chat_core::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{AppError, AppState};
+
+#[derive(Debug, Default, IntoParams, ToSchema, Clone, Serialize, Deserialize)]
+pub struct SearchMessages {
+    /// search terms, supports "quoted phrases", `or` and -excluded words
+    pub q: String,
+    pub chat_id: Option<u64>,
+    pub sender_id: Option<u64>,
+    /// messages sent at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// messages sent before this time
+    pub until: Option<DateTime<Utc>>,
+    /// only messages with files, or only messages without files
+    pub has_files: Option<bool>,
+    /// cursor, the id of the last message of the previous page
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+/// A message matching a search, newest first
+#[derive(Debug, ToSchema, Clone, FromRow, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MessageHit {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: Message,
+    /// the matching part of the message as html, escaped, with matched words wrapped in `<mark>`
+    /// tags
+    pub snippet: String,
+}
+
+impl AppState {
+    /// Search the messages of the chats the user is a member of in the workspace
+    pub async fn search_messages(
+        &self,
+        input: SearchMessages,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<MessageHit>, AppError> {
+        let q = input.q.trim();
+        if q.is_empty() {
+            return Err(AppError::SearchError(
+                "search terms must not be empty".to_string(),
+            ));
+        }
+        if let (Some(since), Some(until)) = (input.since, input.until) {
+            if since >= until {
+                return Err(AppError::SearchError(
+                    "since must be earlier than until".to_string(),
+                ));
+            }
+        }
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = match input.limit {
+            1..=100 => input.limit as i64,
+            _ => 20,
+        };
+        let hits = sqlx::query_as(
+            r#"
+            SELECT m.id, m.chat_id, m.sender_id, m.content, m.modified_content, m.files,
+                m.created_at, m.edited_at, m.deleted_at, m.reply_to, m.thread_id, m.reply_count,
+                ts_headline(
+                    'simple',
+                    html_escape(CASE
+                        WHEN to_tsvector('simple', m.content) @@ query THEN m.content
+                        ELSE COALESCE(m.modified_content, m.content)
+                    END),
+                    query,
+                    'StartSel=<mark>, StopSel=</mark>'
+                ) AS snippet
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id,
+                websearch_to_tsquery('simple', $3) query
+            WHERE c.ws_id = $1
+            AND $2 = ANY(c.members)
+            AND message_search_vector(m.content, m.modified_content) @@ query
+            AND m.deleted_at IS NULL
+            AND ($4::BIGINT IS NULL OR m.chat_id = $4)
+            AND ($5::BIGINT IS NULL OR m.sender_id = $5)
+            AND ($6::TIMESTAMPTZ IS NULL OR m.created_at >= $6)
+            AND ($7::TIMESTAMPTZ IS NULL OR m.created_at < $7)
+            AND ($8::BOOLEAN IS NULL OR (COALESCE(CARDINALITY(m.files), 0) > 0) = $8)
+            AND m.id < $9
+            ORDER BY m.id DESC
+            LIMIT $10
+        "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(q)
+        .bind(input.chat_id.map(|id| id as i64))
+        .bind(input.sender_id.map(|id| id as i64))
+        .bind(input.since)
+        .bind(input.until)
+        .bind(input.has_files)
+        .bind(last_id as i64)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn search(q: &str) -> SearchMessages {
+        SearchMessages {
+            q: q.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn ids(hits: &[MessageHit]) -> Vec<i64> {
+        hits.iter().map(|h| h.message.id).collect()
+    }
+
+    #[tokio::test]
+    async fn search_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let hits = state.search_messages(search("hello"), 1, 1).await?;
+        assert_eq!(ids(&hits), vec![6, 1]);
+        assert_eq!(hits[0].snippet, "<mark>Hello</mark> world!");
+
+        let input = SearchMessages {
+            sender_id: Some(2),
+            ..search("there")
+        };
+        let hits = state.search_messages(input, 1, 1).await?;
+        assert_eq!(ids(&hits), vec![7, 2]);
+
+        // page by page
+        let input = SearchMessages {
+            limit: 1,
+            ..search("hello world")
+        };
+        let hits = state.search_messages(input, 1, 1).await?;
+        assert_eq!(ids(&hits), vec![6]);
+        let input = SearchMessages {
+            limit: 1,
+            last_id: Some(6),
+            ..search("hello world")
+        };
+        let hits = state.search_messages(input, 1, 1).await?;
+        assert_eq!(ids(&hits), vec![1]);
+
+        let input = SearchMessages {
+            since: Some(Utc::now()),
+            ..search("hello")
+        };
+        assert!(state.search_messages(input, 1, 1).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_snippet_should_be_escaped() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query(
+            "INSERT INTO messages (chat_id, sender_id, content) VALUES (2, 2, '<b>bold</b> & <img src=x onerror=alert(1)>')",
+        )
+        .execute(&state.pool)
+        .await?;
+        let hits = state.search_messages(search("bold"), 1, 1).await?;
+        assert_eq!(
+            hits[0].snippet,
+            "&lt;b&gt;<mark>bold</mark>&lt;/b&gt; &amp; &lt;img src=x onerror=alert(1)&gt;"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_messages_should_filter_files_and_members() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 2 is a private channel of users 1, 2 and 3
+        let (id,): (i64,) = sqlx::query_as(
+            "INSERT INTO messages (chat_id, sender_id, content, files) VALUES (2, 2, 'hello with a file', '{/files/1/abc.txt}') RETURNING id",
+        )
+        .fetch_one(&state.pool)
+        .await?;
+        let input = SearchMessages {
+            has_files: Some(true),
+            ..search("hello")
+        };
+        let hits = state.search_messages(input, 1, 1).await?;
+        assert_eq!(ids(&hits), vec![id]);
+        let input = SearchMessages {
+            chat_id: Some(1),
+            ..search("hello")
+        };
+        let hits = state.search_messages(input, 1, 1).await?;
+        assert_eq!(ids(&hits), vec![6, 1]);
+
+        // user 4 is not a member of chat 2
+        let hits = state.search_messages(search("file"), 4, 1).await?;
+        assert!(hits.is_empty());
+
+        let err = state.search_messages(search(" "), 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::SearchError(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use ai_sdk::CompletionResult;
+use chat_core::{AdapterType, ChatAgent};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{AppError, AppState};
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct AgentUsage {
+    pub id: i64,
+    pub ws_id: i64,
+    pub chat_id: i64,
+    pub agent_id: Option<i64>,
+    pub adapter: AdapterType,
+    pub model: String,
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub total_tokens: i32,
+    pub cost: f64,
+    pub finish_reason: Option<String>,
+    pub latency_ms: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, FromRow, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct UsageSummary {
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub total_tokens: i64,
+    pub cost: f64,
+}
+
+impl AppState {
+    /// Record the tokens used by a completion of an agent
+    pub async fn record_usage(
+        &self,
+        agent: &ChatAgent,
+        result: &CompletionResult,
+    ) -> Result<AgentUsage, AppError> {
+        // providers may report a more specific model than the configured one, e.g. a dated version
+        let pricing = &self.config.ai.pricing;
+        let cost = pricing
+            .get(&result.model)
+            .or_else(|| pricing.get(&agent.model))
+            .map(|price| price.cost(&result.usage))
+            .unwrap_or_default();
+
+        let usage = sqlx::query_as(
+            r#"
+            INSERT INTO agent_usages (ws_id, chat_id, agent_id, adapter, model, prompt_tokens,
+                completion_tokens, total_tokens, cost, finish_reason, latency_ms)
+            SELECT ws_id, id, $2, $3, $4, $5, $6, $7, $8, $9, $10 FROM chats WHERE id = $1
+            RETURNING *
+        "#,
+        )
+        .bind(agent.chat_id)
+        .bind(agent.id)
+        .bind(&agent.adapter)
+        .bind(&result.model)
+        .bind(result.usage.prompt_tokens as i32)
+        .bind(result.usage.completion_tokens as i32)
+        .bind(result.usage.total_tokens as i32)
+        .bind(cost)
+        .bind(&result.finish_reason)
+        .bind(result.latency.as_millis() as i32)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(usage)
+    }
+
+    /// Total usage of all agents in a workspace since the given time
+    pub async fn workspace_usage(
+        &self,
+        ws_id: u64,
+        since: DateTime<Utc>,
+    ) -> Result<UsageSummary, AppError> {
+        let summary = sqlx::query_as(
+            r#"
+            SELECT
+                COALESCE(SUM(prompt_tokens), 0)::BIGINT AS prompt_tokens,
+                COALESCE(SUM(completion_tokens), 0)::BIGINT AS completion_tokens,
+                COALESCE(SUM(total_tokens), 0)::BIGINT AS total_tokens,
+                COALESCE(SUM(cost), 0)::DOUBLE PRECISION AS cost
+            FROM agent_usages
+            WHERE ws_id = $1 AND created_at >= $2
+        "#,
+        )
+        .bind(ws_id as i64)
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ai_sdk::Usage;
+    use anyhow::Result;
+
+    use super::*;
+
+    fn completion(model: &str, prompt_tokens: u32, completion_tokens: u32) -> CompletionResult {
+        CompletionResult {
+            content: "hello".to_string(),
+            tool_calls: vec![],
+            usage: Usage::new(prompt_tokens, completion_tokens),
+            model: model.to_string(),
+            finish_reason: Some("stop".to_string()),
+            latency: Duration::from_millis(120),
+        }
+    }
+
+    #[tokio::test]
+    async fn record_usage_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let agent = state
+            .list_agents(1)
+            .await?
+            .pop()
+            .expect("agent should exist");
+        let since = Utc::now() - chrono::Duration::minutes(1);
+
+        let usage = state
+            .record_usage(&agent, &completion("llama3.2", 100, 20))
+            .await?;
+        assert_eq!(usage.ws_id, 1);
+        assert_eq!(usage.chat_id, 1);
+        assert_eq!(usage.agent_id, Some(agent.id));
+        assert_eq!(usage.total_tokens, 120);
+        assert_eq!(usage.latency_ms, 120);
+        assert_eq!(usage.cost, 0.0);
+
+        // priced by the configured model when the reported one is unknown
+        let agent = ChatAgent {
+            model: "gpt-4o-mini".to_string(),
+            adapter: AdapterType::OpenAI,
+            ..agent
+        };
+        let usage = state
+            .record_usage(&agent, &completion("gpt-4o-mini-2024-07-18", 1_000_000, 0))
+            .await?;
+        assert_eq!(usage.cost, 0.15);
+
+        let summary = state.workspace_usage(1, since).await?;
+        assert_eq!(summary.prompt_tokens, 1_000_100);
+        assert_eq!(summary.completion_tokens, 20);
+        assert_eq!(summary.total_tokens, 1_000_120);
+        assert_eq!(summary.cost, 0.15);
+
+        let summary = state.workspace_usage(2, since).await?;
+        assert_eq!(summary, UsageSummary::default());
+        Ok(())
+    }
+}
@@ -7,6 +7,7 @@ use argon2::{
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use super::agent::AGENT_BOT_DOMAIN;
 use crate::{AppError, AppState};
 use chat_core::{ChatUser, User};
 
@@ -51,6 +52,12 @@ impl AppState {
     /// Create a new user
     // TODO: use transaction for workspace creation and user creation
     pub async fn create_user(&self, input: CreateUser) -> Result<User, AppError> {
+        // the emails of agent bots are reserved, a user with one would be taken for the bot
+        let domain = input.email.rsplit('@').next().unwrap_or_default();
+        if domain.eq_ignore_ascii_case(AGENT_BOT_DOMAIN) {
+            return Err(AppError::EmailReserved(input.email.clone()));
+        }
+
         // check if email exists
         let user = self.find_user_by_email(&input.email).await?;
         if user.is_some() {
@@ -120,7 +127,7 @@ impl AppState {
     pub async fn fetch_chat_user_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-            SELECT id, fullname, email
+            SELECT id, fullname, email, is_bot
             FROM users
             WHERE id = ANY($1)
         "#,
@@ -134,7 +141,7 @@ impl AppState {
     pub async fn fetch_chat_users(&self, ws_id: u64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-            SELECT id, fullname, email
+            SELECT id, fullname, email, is_bot
             FROM users
             WHERE ws_id = $1
         "#,
@@ -216,6 +223,15 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn create_user_with_agent_bot_email_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("acme", "Agent", "agent-9@Agents.Bot.org", "password");
+        let ret = state.create_user(input).await;
+        assert!(matches!(ret, Err(AppError::EmailReserved(_))));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn create_and_verify_user_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
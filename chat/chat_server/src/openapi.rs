@@ -1,6 +1,8 @@
 use axum::Router;
 
-use chat_core::{AgentType, Chat, ChatAgent, ChatType, Message, User, Workspace};
+use chat_core::{
+    AdapterType, AgentType, Chat, ChatAgent, ChatType, Message, ReactionSummary, User, Workspace,
+};
 use utoipa::{
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi,
@@ -10,8 +12,11 @@ use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
-    error::ErrorOutput, handlers::*, AppState, CreateAgent, CreateChat, CreateMessage, CreateUser,
-    ListMessage, SigninUser, UpdateAgent,
+    error::ErrorOutput, handlers::*, AgentVersion, AppState, ChatSummary, CreateAgent, CreateChat,
+    CreateMessage, CreateReaction, CreateUser, FallbackBackend, FallbackStats, ListMessage,
+    ListModels, ListModeration, MarkRead, MessageEdit, MessageHit, ModerationItem,
+    ModerationStatus, PatchAgent, ReadReceipt, ReviewModeration, SearchMessages, SigninUser,
+    UpdateAgent, UpdateMessage,
 };
 
 pub(crate) trait OpenApiRouter {
@@ -26,19 +31,42 @@ pub(crate) trait OpenApiRouter {
             create_chat_handler,
             list_chat_handler,
             get_chat_handler,
+            mark_read_handler,
             list_message_handler,
+            list_thread_handler,
+            update_message_handler,
+            delete_message_handler,
+            list_message_edits_handler,
+            add_reaction_handler,
+            remove_reaction_handler,
 
             list_agent_handler,
             create_agent_handler,
-            update_agent_handler
+            update_agent_handler,
+            get_agent_handler,
+            patch_agent_handler,
+            delete_agent_handler,
+            list_agent_versions_handler,
+            rollback_agent_handler,
+            list_models_handler,
+            list_fallbacks_handler,
+
+            list_moderation_handler,
+            review_moderation_handler,
+
+            search_messages_handler
         ),
         modifiers(&SecurityAddon),
         components(
             schemas(
-                User, Message, Chat, ChatType, Workspace,
+                User, Message, Chat, ChatType, ChatSummary, MarkRead, ReadReceipt, Workspace,
                 CreateChat, CreateMessage, CreateUser, ErrorOutput, AuthOutput,
-                ListMessage, SigninUser, AuthOutput,
-                CreateAgent, UpdateAgent, ChatAgent, AgentType
+                ListMessage, UpdateMessage, MessageEdit, SigninUser, AuthOutput,
+                CreateReaction, ReactionSummary,
+                CreateAgent, UpdateAgent, PatchAgent, AgentVersion, ChatAgent, AgentType,
+                AdapterType, ListModels, FallbackStats, FallbackBackend,
+                ModerationItem, ModerationStatus, ListModeration, ReviewModeration,
+                SearchMessages, MessageHit
             )
         ),
         tags(
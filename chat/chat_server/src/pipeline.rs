@@ -0,0 +1,450 @@
+use std::time::{Duration, Instant};
+
+use ai_sdk::CompletionResult;
+use anyhow::anyhow;
+use chat_core::{AgentContext, AgentDecision, AgentError, AgentType, ChatAgent, Invocation};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    agent::{failure_policy, trigger, AgentVariant},
+    AppState,
+};
+
+/// What the pipeline does when an agent fails, from `args.on_failure`. It defaults to
+/// blocking the message for the agents which check or rewrite it, and to skipping the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFailure {
+    /// reject the message with the error of the agent
+    Block,
+    /// ignore the agent and go on with the next one
+    Skip,
+    /// drop the modifications made so far and go on with the original content, redactions
+    /// excluded
+    Fallback,
+}
+
+/// When an agent runs, from `args.trigger`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// on every message of the chat
+    #[default]
+    Always,
+    /// only on the messages mentioning it, e.g. `@translator bonjour`
+    Mention,
+}
+
+impl OnFailure {
+    pub fn default_for(agent_type: &AgentType) -> Self {
+        match agent_type {
+            AgentType::Moderation | AgentType::Proxy => OnFailure::Block,
+            AgentType::Reply | AgentType::Tap => OnFailure::Skip,
+        }
+    }
+}
+
+/// The agents a message invokes: the always-on ones, and the ones it mentions. An agent with
+/// invalid args is always selected, so that the pipeline applies its failure policy.
+pub fn select_agents(agents: Vec<ChatAgent>, invocation: &Invocation) -> Vec<ChatAgent> {
+    agents
+        .into_iter()
+        .filter(|agent| {
+            trigger(&agent.args).unwrap_or_default() == Trigger::Always
+                || invocation.names.contains(&agent.name)
+        })
+        .collect()
+}
+
+/// The agents of a chat, run in order on a new message: moderation agents first on what the
+/// sender wrote, then proxy agents, each one modifying the output of the previous one, then reply
+/// agents on the final content, then tap agents. Agents of the same type run in the order they
+/// were created.
+pub struct AgentPipeline {
+    stages: Vec<Stage>,
+}
+
+struct Stage {
+    agent: ChatAgent,
+    /// the error of an agent which can't be built, e.g. with invalid args, fails every run
+    variant: Result<AgentVariant, AgentError>,
+    on_failure: OnFailure,
+}
+
+/// The outcome of one agent
+#[derive(Debug)]
+pub struct AgentRun {
+    pub agent: ChatAgent,
+    /// `None` when the agent failed
+    pub decision: Option<AgentDecision>,
+    pub error: Option<String>,
+    pub completion: Option<CompletionResult>,
+    pub latency: Duration,
+    /// the content a moderation agent redacted, kept for audit only
+    pub redacted_from: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct PipelineOutput {
+    /// the content after the redactions of moderation agents, `None` when nothing is redacted.
+    /// It replaces what the sender wrote, which must not be shown to anyone
+    pub redacted: Option<String>,
+    /// the content after all proxy agents, `None` when it is unchanged
+    pub modified_content: Option<String>,
+    /// replies of the reply agents, with the agent which made them
+    pub replies: Vec<(ChatAgent, String)>,
+    pub runs: Vec<AgentRun>,
+    /// the error of an agent which blocked the message
+    pub blocked: Option<AgentError>,
+    /// why a moderation agent rejected the message
+    pub rejected: Option<String>,
+    /// moderation agents which flagged the message for review, with the reason
+    pub flags: Vec<(ChatAgent, String)>,
+}
+
+impl AgentPipeline {
+    pub fn new(mut agents: Vec<ChatAgent>, state: &AppState) -> Self {
+        agents.sort_by(|a, b| {
+            a.r#type
+                .partial_cmp(&b.r#type)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.id.cmp(&b.id))
+        });
+        let stages = agents
+            .into_iter()
+            .map(|agent| {
+                let variant = AgentVariant::try_new(agent.clone(), state);
+                if let Err(e) = &variant {
+                    warn!("agent {} can't be built: {}", agent.id, e);
+                }
+                Stage {
+                    on_failure: failure_policy(&agent.r#type, &agent.args),
+                    variant,
+                    agent,
+                }
+            })
+            .collect();
+        Self { stages }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub async fn run(&self, content: &str, ctx: &AgentContext) -> PipelineOutput {
+        let mut output = PipelineOutput::default();
+        let mut current = content.to_string();
+        // what the other agents start from, and go back to on failure
+        let mut accepted = content.to_string();
+        for stage in &self.stages {
+            let start = Instant::now();
+            let ret = match &stage.variant {
+                Ok(variant) => variant.run(&current, ctx).await,
+                Err(e) => Err(copy_error(e)),
+            };
+            let mut run = AgentRun {
+                agent: stage.agent.clone(),
+                decision: None,
+                error: None,
+                completion: None,
+                latency: start.elapsed(),
+                redacted_from: None,
+            };
+            match ret {
+                Ok(ret) => {
+                    let rejected = match &ret.decision {
+                        AgentDecision::Modify(s) => {
+                            current = s.clone();
+                            None
+                        }
+                        AgentDecision::Redact(s) => {
+                            run.redacted_from = Some(std::mem::replace(&mut current, s.clone()));
+                            accepted = s.clone();
+                            output.redacted = Some(s.clone());
+                            None
+                        }
+                        AgentDecision::Reply(s) => {
+                            output.replies.push((stage.agent.clone(), s.clone()));
+                            None
+                        }
+                        AgentDecision::Flag(reason) => {
+                            output.flags.push((stage.agent.clone(), reason.clone()));
+                            None
+                        }
+                        AgentDecision::Delete(reason) => Some(reason.clone()),
+                        AgentDecision::None => None,
+                    };
+                    run.decision = Some(ret.decision);
+                    run.completion = ret.completion;
+                    output.runs.push(run);
+                    if rejected.is_some() {
+                        output.rejected = rejected;
+                        break;
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "agent {} failed, {:?} the message: {}",
+                        stage.agent.id, stage.on_failure, e
+                    );
+                    run.error = Some(e.to_string());
+                    output.runs.push(run);
+                    match stage.on_failure {
+                        OnFailure::Block => {
+                            output.blocked = Some(e);
+                            break;
+                        }
+                        OnFailure::Skip => {}
+                        OnFailure::Fallback => current = accepted.clone(),
+                    }
+                }
+            }
+        }
+        if current != accepted {
+            output.modified_content = Some(current);
+        }
+        output
+    }
+}
+
+// an agent error isn't Clone because of the anyhow error it may wrap
+fn copy_error(e: &AgentError) -> AgentError {
+    match e {
+        AgentError::Ai { kind, message } => AgentError::Ai {
+            kind: *kind,
+            message: message.clone(),
+        },
+        AgentError::AnyError(e) => AgentError::AnyError(anyhow!("{:#}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    async fn pipeline(
+        state: &AppState,
+        agents: &[(AgentType, &str, serde_json::Value)],
+    ) -> Result<AgentPipeline> {
+        let template = state.list_agents(1).await?.remove(0);
+        let agents = agents
+            .iter()
+            .enumerate()
+            .map(|(i, (r#type, prompt, args))| ChatAgent {
+                id: i as i64 + 1,
+                r#type: r#type.clone(),
+                prompt: prompt.to_string(),
+                args: sqlx::types::Json(args.clone()),
+                ..template.clone()
+            })
+            .collect();
+        Ok(AgentPipeline::new(agents, state))
+    }
+
+    #[tokio::test]
+    async fn pipeline_should_run_agents_in_order() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the mock adapter echoes the last user turn, a prompt with {{ message }} is that turn
+        let pipeline = pipeline(
+            &state,
+            &[
+                (AgentType::Tap, "observe", serde_json::json!({})),
+                (AgentType::Reply, "re: {{ message }}", serde_json::json!({})),
+                (AgentType::Proxy, "b({{ message }})", serde_json::json!({})),
+                (AgentType::Proxy, "a({{ message }})", serde_json::json!({})),
+            ],
+        )
+        .await?;
+        let output = pipeline.run("hi", &AgentContext::default()).await;
+        assert!(output.blocked.is_none());
+        // proxy b was created before proxy a
+        assert_eq!(output.modified_content.as_deref(), Some("a(b(hi))"));
+        assert_eq!(output.replies.len(), 1);
+        assert_eq!(output.replies[0].1, "re: a(b(hi))");
+        let ids: Vec<_> = output.runs.iter().map(|r| r.agent.id).collect();
+        assert_eq!(ids, vec![3, 4, 2, 1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipeline_should_moderate_first() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the mock adapter echoes the message, so the message is the verdict
+        let pipeline = pipeline(
+            &state,
+            &[
+                (AgentType::Proxy, "a({{ message }})", serde_json::json!({})),
+                (AgentType::Moderation, "No spam", serde_json::json!({})),
+            ],
+        )
+        .await?;
+        let output = pipeline
+            .run(
+                r#"{"action": "block", "reason": "spam"}"#,
+                &AgentContext::default(),
+            )
+            .await;
+        assert_eq!(output.rejected.as_deref(), Some("spam"));
+        // the proxy agent never ran
+        assert_eq!(output.runs.len(), 1);
+        assert_eq!(output.runs[0].agent.id, 2);
+
+        let msg = r#"{"action": "flag", "reason": "rude"}"#;
+        let output = pipeline.run(msg, &AgentContext::default()).await;
+        assert!(output.rejected.is_none());
+        assert_eq!(output.flags.len(), 1);
+        assert_eq!(output.flags[0].1, "rude");
+        assert_eq!(
+            output.modified_content.as_deref(),
+            Some(format!("a({})", msg).as_str())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mentioned_agents_should_be_selected() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let template = state.list_agents(1).await?.remove(0);
+        let agent = |name: &str, args: serde_json::Value| ChatAgent {
+            name: name.to_string(),
+            args: sqlx::types::Json(args),
+            ..template.clone()
+        };
+        let agents = vec![
+            agent("translator", serde_json::json!({})),
+            agent("summarize", serde_json::json!({ "trigger": "mention" })),
+        ];
+        let names = |agents: Vec<ChatAgent>| -> Vec<String> {
+            agents.into_iter().map(|a| a.name).collect()
+        };
+
+        let invocation = chat_core::resolve_mentions("hello", ["translator", "summarize"]);
+        let selected = select_agents(agents.clone(), &invocation);
+        assert_eq!(names(selected), vec!["translator"]);
+
+        let invocation =
+            chat_core::resolve_mentions("@summarize last 50", ["translator", "summarize"]);
+        let selected = select_agents(agents, &invocation);
+        assert_eq!(names(selected), vec!["translator", "summarize"]);
+        assert_eq!(invocation.content, "last 50");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipeline_should_apply_failure_policy() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let fail =
+            |policy: &str| serde_json::json!({ "max_input_tokens": 1, "on_failure": policy });
+        let agents = |policy: &str| {
+            vec![
+                (AgentType::Proxy, "a({{ message }})", serde_json::json!({})),
+                (AgentType::Proxy, "too long", fail(policy)),
+                (AgentType::Reply, "re: {{ message }}", serde_json::json!({})),
+            ]
+        };
+
+        let output = pipeline(&state, &agents("skip"))
+            .await?
+            .run("hi", &AgentContext::default())
+            .await;
+        assert!(output.blocked.is_none());
+        assert_eq!(output.modified_content.as_deref(), Some("a(hi)"));
+        assert!(output.runs[1].error.is_some());
+        assert_eq!(output.replies[0].1, "re: a(hi)");
+
+        let output = pipeline(&state, &agents("fallback"))
+            .await?
+            .run("hi", &AgentContext::default())
+            .await;
+        assert!(output.blocked.is_none());
+        assert_eq!(output.modified_content, None);
+        assert_eq!(output.replies[0].1, "re: hi");
+
+        let output = pipeline(&state, &agents("block"))
+            .await?
+            .run("hi", &AgentContext::default())
+            .await;
+        assert!(matches!(output.blocked, Some(AgentError::Ai { .. })));
+        assert_eq!(output.runs.len(), 2);
+        assert!(output.replies.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipeline_should_only_block_on_moderation_and_proxy_by_default() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let too_long = serde_json::json!({ "max_input_tokens": 1 });
+
+        let output = pipeline(
+            &state,
+            &[
+                (AgentType::Proxy, "a({{ message }})", serde_json::json!({})),
+                (AgentType::Reply, "too long", too_long.clone()),
+            ],
+        )
+        .await?
+        .run("hi", &AgentContext::default())
+        .await;
+        assert!(output.blocked.is_none());
+        assert!(output.runs[1].error.is_some());
+        assert_eq!(output.modified_content.as_deref(), Some("a(hi)"));
+
+        let output = pipeline(&state, &[(AgentType::Proxy, "too long", too_long)])
+            .await?
+            .run("hi", &AgentContext::default())
+            .await;
+        assert!(output.blocked.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipeline_should_apply_failure_policy_to_broken_agents() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let broken = |policy: Option<&str>| match policy {
+            Some(policy) => serde_json::json!({ "temperature": "hot", "on_failure": policy }),
+            None => serde_json::json!({ "temperature": "hot" }),
+        };
+
+        let pipeline_with = |r#type: AgentType, args: serde_json::Value| {
+            let state = state.clone();
+            async move {
+                pipeline(
+                    &state,
+                    &[
+                        (r#type, "broken", args),
+                        (AgentType::Reply, "re: {{ message }}", serde_json::json!({})),
+                    ],
+                )
+                .await
+            }
+        };
+
+        let output = pipeline_with(AgentType::Proxy, broken(Some("skip")))
+            .await?
+            .run("hi", &AgentContext::default())
+            .await;
+        assert!(output.blocked.is_none());
+        assert!(output.runs[0].error.is_some());
+        assert_eq!(output.replies[0].1, "re: hi");
+
+        let output = pipeline_with(AgentType::Proxy, broken(None))
+            .await?
+            .run("hi", &AgentContext::default())
+            .await;
+        assert!(output.blocked.is_some());
+        assert!(output.replies.is_empty());
+
+        // the policy of invalid args can't be read, the default of the type applies
+        let output = pipeline_with(AgentType::Reply, serde_json::json!({ "history": "all" }))
+            .await?
+            .run("hi", &AgentContext::default())
+            .await;
+        assert!(output.blocked.is_none());
+        assert!(output.runs[0].error.is_some());
+        assert_eq!(output.replies.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use std::sync::OnceLock;
+
+use anyhow::bail;
+use chat_core::AgentContext;
+use minijinja::Environment;
+use serde::Serialize;
+
+/// Variables a prompt template can use
+const VARIABLES: &[&str] = &["args", "message", "sender", "chat", "workspace", "history"];
+
+/// The prompt of an agent, rendered as a jinja2 template, e.g.
+/// `Translate the message of {{ sender }} to {{ args.language }}: {{ message }}`.
+#[derive(Debug, Clone)]
+pub struct PromptTemplate {
+    source: String,
+    has_message: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct PromptVars<'a> {
+    args: &'a serde_json::Value,
+    message: &'a str,
+    sender: &'a str,
+    chat: &'a str,
+    workspace: &'a str,
+    history: Vec<HistoryMessage<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct HistoryMessage<'a> {
+    sender: &'a str,
+    content: &'a str,
+}
+
+impl PromptTemplate {
+    /// Parse the template, it fails on a syntax error or an unknown variable
+    pub fn new(source: impl Into<String>) -> anyhow::Result<Self> {
+        let source = source.into();
+        let variables = env()
+            .template_from_str(&source)?
+            .undeclared_variables(false);
+        let mut unknown: Vec<_> = variables
+            .iter()
+            .filter(|v| !VARIABLES.contains(&v.as_str()))
+            .map(|v| v.as_str())
+            .collect();
+        if !unknown.is_empty() {
+            unknown.sort();
+            bail!(
+                "unknown variables in prompt: {}, available ones are {}",
+                unknown.join(", "),
+                VARIABLES.join(", ")
+            );
+        }
+        Ok(Self {
+            has_message: variables.contains("message"),
+            source,
+        })
+    }
+
+    pub fn render(
+        &self,
+        args: &serde_json::Value,
+        msg: &str,
+        ctx: &AgentContext,
+    ) -> anyhow::Result<String> {
+        let history = ctx
+            .history
+            .iter()
+            .map(|m| HistoryMessage {
+                sender: ctx.sender_name(m.sender_id).unwrap_or_default(),
+                content: &m.content,
+            })
+            .collect();
+        let vars = PromptVars {
+            args,
+            message: msg,
+            sender: ctx
+                .sender
+                .as_ref()
+                .map(|u| u.fullname.as_str())
+                .unwrap_or_default(),
+            chat: ctx
+                .chat
+                .as_ref()
+                .and_then(|c| c.name.as_deref())
+                .unwrap_or_default(),
+            workspace: ctx
+                .workspace
+                .as_ref()
+                .map(|w| w.name.as_str())
+                .unwrap_or_default(),
+            history,
+        };
+        Ok(env().render_str(&self.source, vars)?)
+    }
+
+    /// Whether the template includes the message itself, or only instructions about it
+    pub fn has_message(&self) -> bool {
+        self.has_message
+    }
+}
+
+fn env() -> &'static Environment<'static> {
+    static ENV: OnceLock<Environment<'static>> = OnceLock::new();
+    ENV.get_or_init(Environment::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use chat_core::{Chat, ChatType, ChatUser, Message, User};
+    use chrono::Utc;
+    use serde_json::json;
+
+    use super::*;
+
+    fn message(id: i64, sender_id: i64, content: &str) -> Message {
+        Message {
+            id,
+            chat_id: 1,
+            sender_id,
+            modified_content: None,
+            content: content.to_string(),
+            files: vec![],
+            created_at: Utc::now(),
+            edited_at: None,
+            deleted_at: None,
+            reply_to: None,
+            thread_id: None,
+            reply_count: 0,
+            reactions: vec![],
+        }
+    }
+
+    #[test]
+    fn prompt_template_should_render() -> anyhow::Result<()> {
+        let ctx = AgentContext {
+            chat: Some(Chat {
+                id: 1,
+                ws_id: 1,
+                name: Some("general".to_string()),
+                r#type: ChatType::PublicChannel,
+                members: vec![1, 2],
+                agents: vec![],
+                created_at: Utc::now(),
+            }),
+            sender: Some(User::new(1, "Tyr Chen", "tchen@acme.org")),
+            members: vec![
+                ChatUser {
+                    id: 1,
+                    fullname: "Tyr Chen".to_string(),
+                    email: "tchen@acme.org".to_string(),
+                    is_bot: false,
+                },
+                ChatUser {
+                    id: 2,
+                    fullname: "Alice".to_string(),
+                    email: "alice@acme.org".to_string(),
+                    is_bot: false,
+                },
+            ],
+            history: vec![message(1, 2, "hi"), message(2, 1, "hello")],
+            ..Default::default()
+        };
+        let template = PromptTemplate::new(
+            "In {{ chat }}:\n{% for m in history %}{{ m.sender }}: {{ m.content }}\n{% endfor %}\
+            Translate what {{ sender }} said to {{ args.language }}: {{ message }}",
+        )?;
+        let prompt = template.render(&json!({ "language": "French" }), "good morning", &ctx)?;
+        assert_eq!(
+            prompt,
+            "In general:\nAlice: hi\nTyr Chen: hello\nTranslate what Tyr Chen said to French: good morning"
+        );
+
+        assert!(template.has_message());
+
+        let template = PromptTemplate::new("Translate to French")?;
+        let prompt = template.render(&json!({}), "good morning", &AgentContext::default())?;
+        assert_eq!(prompt, "Translate to French");
+        assert!(!template.has_message());
+        Ok(())
+    }
+
+    #[test]
+    fn prompt_template_should_reject_invalid_templates() {
+        let err = PromptTemplate::new("Translate {{ message }").unwrap_err();
+        assert!(err.to_string().contains("syntax error"));
+
+        let err = PromptTemplate::new("Translate {{ msg }} for {{ user }}").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("unknown variables in prompt: msg, user"));
+    }
+}
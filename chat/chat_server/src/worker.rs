@@ -0,0 +1,241 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use chat_core::{resolve_mentions, AgentType, ChatAgent, Message};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::{agent::AgentVariant, pipeline::AgentRun, AgentJob, AppError, AppState, ChatFile};
+
+/// Spawn the workers running the jobs of tap agents, as configured in `jobs.workers`
+pub fn spawn_workers(state: &AppState) -> Vec<JoinHandle<()>> {
+    let workers = state.config.jobs.workers;
+    if workers > 0 {
+        info!("Starting {} agent job workers", workers);
+    }
+    (0..workers)
+        .map(|i| {
+            let state = state.clone();
+            tokio::spawn(async move { run_worker(i, state).await })
+        })
+        .collect()
+}
+
+async fn run_worker(id: usize, state: AppState) {
+    let interval = Duration::from_millis(state.config.jobs.poll_interval_ms);
+    loop {
+        match state.run_next_job().await {
+            // look for the next job right away while the queue is not empty
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(e) => warn!("agent job worker {} failed to poll the queue: {}", id, e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+impl AppState {
+    /// Claim and run the next job, returns false when there is no job to run
+    pub async fn run_next_job(&self) -> Result<bool, AppError> {
+        let Some(job) = self.claim_job().await? else {
+            return Ok(false);
+        };
+        if let Err(e) = self.run_job(&job).await {
+            match self.fail_job(&job, &e.to_string()).await? {
+                Some(status) => warn!(
+                    "agent job {} failed on attempt {}, {:?}: {}",
+                    job.id, job.attempts, status, e
+                ),
+                None => warn!(
+                    "agent job {} failed on attempt {} after it was taken over: {}",
+                    job.id, job.attempts, e
+                ),
+            }
+        }
+        Ok(true)
+    }
+
+    async fn run_job(&self, job: &AgentJob) -> Result<(), AppError> {
+        let agent: Option<ChatAgent> = sqlx::query_as(
+            r#"
+            SELECT * FROM chat_agents WHERE id = $1
+        "#,
+        )
+        .bind(job.agent_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, modified_content, files, created_at, edited_at,
+                deleted_at, reply_to, thread_id, reply_count
+            FROM messages
+            WHERE id = $1
+        "#,
+        )
+        .bind(job.message_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        // the agent may have been changed to another type, or the message deleted, since the job
+        // was enqueued
+        let (Some(agent), Some(message)) = (agent, message) else {
+            return self.complete_job(job, None).await;
+        };
+        if agent.r#type != AgentType::Tap || message.deleted_at.is_some() {
+            return self.complete_job(job, None).await;
+        }
+
+        let AgentVariant::Tap(tap) = AgentVariant::try_new(agent.clone(), self)? else {
+            warn!(
+                "agent {} of job {} is not run as a tap agent",
+                agent.id, job.id
+            );
+            return self.complete_job(job, None).await;
+        };
+        let files = message
+            .files
+            .iter()
+            .map(|s| ChatFile::from_str(s))
+            .collect::<Result<Vec<_>, _>>()?;
+        let ctx = self
+            .agent_context(
+                message.chat_id as _,
+                message.sender_id as _,
+                &files,
+                Some(message.id as _),
+                message.thread_id.map(|id| id as _),
+            )
+            .await?;
+        // taps see what the other members see, without the mention which invoked them
+        let content = message
+            .modified_content
+            .as_ref()
+            .unwrap_or(&message.content);
+        let content = resolve_mentions(content, [agent.name.as_str()]).content;
+
+        let start = Instant::now();
+        let ret = tap.run(&content, &ctx).await;
+        let mut run = AgentRun {
+            agent: agent.clone(),
+            decision: None,
+            error: None,
+            completion: None,
+            latency: start.elapsed(),
+            redacted_from: None,
+        };
+        let ret = match ret {
+            Ok(output) => {
+                run.decision = Some(output.decision);
+                run.completion = output.completion;
+                Ok(())
+            }
+            Err(e) => {
+                run.error = Some(e.to_string());
+                Err(e)
+            }
+        };
+        let result = run
+            .completion
+            .as_ref()
+            .map(|c| (tap.task, c.content.clone()));
+        if let Some(completion) = &run.completion {
+            // usage is for accounting only, it should not fail the job
+            if let Err(e) = self.record_usage(&agent, completion).await {
+                warn!("failed to record usage of agent {}: {}", agent.id, e);
+            }
+        }
+        if let Err(e) = self
+            .record_decisions(job.chat_id as _, Some(message.id), &[run])
+            .await
+        {
+            warn!(
+                "failed to record agent decisions on message {}: {}",
+                message.id, e
+            );
+        }
+        ret?;
+        self.complete_job(job, result).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateAgent, CreateMessage, DecisionType, JobStatus, TapTask};
+    use anyhow::Result;
+    use chat_core::AdapterType;
+
+    #[tokio::test]
+    async fn tap_agent_should_run_in_a_job() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateAgent::new(
+            "summary",
+            AgentType::Tap,
+            AdapterType::Ollama,
+            "llama3.2",
+            "",
+            serde_json::json!({ "task": "summarize" }),
+        );
+        let agent = state.create_agent(input, 1).await?;
+
+        let input = CreateMessage {
+            content: "let's meet tomorrow".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        // the tap agent doesn't run in the request, only the translation agent did
+        let decisions = state.list_decisions(message.id as _).await?;
+        assert_eq!(decisions.len(), 1);
+        let jobs = state.list_jobs(message.id as _).await?;
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].agent_id, agent.id);
+        assert_eq!(jobs[0].status, JobStatus::Pending);
+
+        assert!(state.run_next_job().await?);
+        assert!(!state.run_next_job().await?);
+
+        let jobs = state.list_jobs(message.id as _).await?;
+        assert_eq!(jobs[0].status, JobStatus::Done);
+        let results = state.list_agent_results(message.id as _).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].task, TapTask::Summarize);
+        // the mock adapter echoes the message
+        assert_eq!(results[0].content, "let's meet tomorrow");
+        let decisions = state.list_decisions(message.id as _).await?;
+        assert_eq!(decisions.len(), 2);
+        assert_eq!(decisions[1].decision, DecisionType::None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn message_should_not_be_sent_without_its_jobs() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateAgent::new(
+            "summary",
+            AgentType::Tap,
+            AdapterType::Ollama,
+            "llama3.2",
+            "",
+            serde_json::json!({ "task": "summarize" }),
+        );
+        state.create_agent(input, 1).await?;
+        // enqueueing the job fails
+        sqlx::query("ALTER TABLE agent_jobs ADD CONSTRAINT no_jobs CHECK (FALSE) NOT VALID")
+            .execute(&state.pool)
+            .await?;
+
+        let input = CreateMessage {
+            content: "let's meet tomorrow".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        assert!(state.create_message(input, 1, 1).await.is_err());
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM messages WHERE chat_id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(count, 10);
+        Ok(())
+    }
+}
@@ -201,6 +201,7 @@ impl ChatServer {
         let body = serde_json::to_string(&CreateMessage {
             content: "hello world".to_string(),
             files: files.clone(),
+            reply_to: None,
         })?;
 
         let res = self
@@ -0,0 +1,26 @@
+-- token usage of every completion made by an agent, to attribute llm spend to workspaces
+CREATE TABLE IF NOT EXISTS agent_usages (
+    id BIGSERIAL PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id),
+    chat_id BIGINT NOT NULL REFERENCES chats(id),
+    -- keep the usage when the agent is removed
+    agent_id BIGINT REFERENCES chat_agents(id) ON DELETE SET NULL,
+    adapter adapter_type NOT NULL,
+    -- the model reported by the provider
+    model VARCHAR(255) NOT NULL,
+    prompt_tokens INT NOT NULL,
+    completion_tokens INT NOT NULL,
+    total_tokens INT NOT NULL,
+    -- in USD, computed from the configured model prices when the usage is recorded
+    cost DOUBLE PRECISION NOT NULL DEFAULT 0,
+    finish_reason VARCHAR(32),
+    latency_ms INT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for agent usages for ws_id and created_at, to sum the usage of a period
+CREATE INDEX IF NOT EXISTS agent_usages_ws_id_created_at_index ON agent_usages(ws_id, created_at DESC);
+
+CREATE INDEX IF NOT EXISTS agent_usages_chat_id_index ON agent_usages(chat_id);
+
+CREATE INDEX IF NOT EXISTS agent_usages_agent_id_index ON agent_usages(agent_id);
@@ -0,0 +1,20 @@
+-- decisions of the agents which processed a message, to audit what they did
+CREATE TYPE agent_decision_type AS ENUM ('modify', 'reply', 'delete', 'none', 'failed');
+
+CREATE TABLE IF NOT EXISTS agent_decisions (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id),
+    -- null when the message was blocked by a failed agent
+    message_id BIGINT REFERENCES messages(id) ON DELETE CASCADE,
+    agent_id BIGINT REFERENCES chat_agents(id) ON DELETE SET NULL,
+    decision agent_decision_type NOT NULL,
+    -- the modified content or the reply
+    content TEXT,
+    error TEXT,
+    latency_ms INT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS agent_decisions_message_id_index ON agent_decisions(message_id);
+
+CREATE INDEX IF NOT EXISTS agent_decisions_chat_id_created_at_index ON agent_decisions(chat_id, created_at DESC);
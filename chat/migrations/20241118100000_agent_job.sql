@@ -0,0 +1,44 @@
+-- tap agents process messages out of the request path, every new message enqueues a job per tap
+-- agent of the chat, which workers pick with FOR UPDATE SKIP LOCKED
+CREATE TYPE agent_job_status AS ENUM ('pending', 'running', 'done', 'failed');
+
+CREATE TYPE agent_task AS ENUM ('summarize', 'tag', 'action_items', 'custom');
+
+CREATE TABLE IF NOT EXISTS agent_jobs (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id),
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    agent_id BIGINT NOT NULL REFERENCES chat_agents(id) ON DELETE CASCADE,
+    status agent_job_status NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    -- not picked before this time, to back off failed attempts
+    run_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- when a worker picked the job, a running job locked for too long is picked again
+    locked_at TIMESTAMPTZ,
+    -- the error of the last failed attempt
+    error TEXT,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- workers only look for pending and running jobs
+CREATE INDEX IF NOT EXISTS agent_jobs_run_at_index ON agent_jobs(run_at)
+    WHERE status IN ('pending', 'running');
+
+CREATE INDEX IF NOT EXISTS agent_jobs_message_id_index ON agent_jobs(message_id);
+
+-- what tap agents made of a message: a summary, tags, action items or anything their prompt asks
+CREATE TABLE IF NOT EXISTS agent_results (
+    id BIGSERIAL PRIMARY KEY,
+    job_id BIGINT REFERENCES agent_jobs(id) ON DELETE SET NULL,
+    chat_id BIGINT NOT NULL REFERENCES chats(id),
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    agent_id BIGINT REFERENCES chat_agents(id) ON DELETE SET NULL,
+    task agent_task NOT NULL,
+    content TEXT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS agent_results_message_id_index ON agent_results(message_id);
+
+CREATE INDEX IF NOT EXISTS agent_results_chat_id_task_index ON agent_results(chat_id, task);
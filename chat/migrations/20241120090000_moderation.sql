@@ -0,0 +1,29 @@
+-- moderation agents check messages before they are sent, flagged messages wait in a queue for the
+-- admins of the workspace
+ALTER TYPE agent_type ADD VALUE IF NOT EXISTS 'moderation' BEFORE 'proxy';
+
+-- agent_decisions.content is the redacted message, or the reason of a block or a flag
+ALTER TYPE agent_decision_type ADD VALUE IF NOT EXISTS 'redact';
+
+ALTER TYPE agent_decision_type ADD VALUE IF NOT EXISTS 'flag';
+
+CREATE TYPE moderation_status AS ENUM ('pending', 'approved', 'removed');
+
+CREATE TABLE IF NOT EXISTS moderation_queue (
+    id BIGSERIAL PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id),
+    chat_id BIGINT NOT NULL REFERENCES chats(id),
+    -- null once the message is removed
+    message_id BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+    agent_id BIGINT REFERENCES chat_agents(id) ON DELETE SET NULL,
+    sender_id BIGINT NOT NULL REFERENCES users(id),
+    -- what the sender wrote, kept after the message is removed
+    content TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    status moderation_status NOT NULL DEFAULT 'pending',
+    reviewed_by BIGINT REFERENCES users(id),
+    reviewed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS moderation_queue_ws_id_status_index ON moderation_queue(ws_id, status, id DESC);
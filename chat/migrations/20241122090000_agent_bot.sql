@@ -0,0 +1,2 @@
+-- every agent posts its replies as its own bot user, created along with the agent
+ALTER TABLE chat_agents ADD COLUMN bot_id BIGINT REFERENCES users(id);
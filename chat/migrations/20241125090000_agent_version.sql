@@ -0,0 +1,20 @@
+-- agents can be disabled without deleting them
+ALTER TABLE chat_agents ADD COLUMN enabled BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- every configuration an agent had, to roll back a change
+CREATE TABLE IF NOT EXISTS chat_agent_versions (
+    id BIGSERIAL PRIMARY KEY,
+    agent_id BIGINT NOT NULL REFERENCES chat_agents(id) ON DELETE CASCADE,
+    version INT NOT NULL,
+    type agent_type NOT NULL,
+    adapter adapter_type NOT NULL,
+    model VARCHAR(255) NOT NULL,
+    prompt TEXT NOT NULL,
+    args JSONB NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (agent_id, version)
+);
+
+-- the current configuration of existing agents is their first version
+INSERT INTO chat_agent_versions (agent_id, version, type, adapter, model, prompt, args)
+    SELECT id, 1, type, adapter, model, prompt, args FROM chat_agents;
@@ -0,0 +1,47 @@
+-- messages can be edited and deleted by their sender. A deleted message is kept as a tombstone
+-- without its content, so that the history still shows where it was
+ALTER TABLE messages
+    ADD COLUMN edited_at TIMESTAMPTZ,
+    ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- what a message said before each edit
+CREATE TABLE IF NOT EXISTS message_edits (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    modified_content TEXT,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS message_edits_message_id_index ON message_edits(message_id);
+
+-- notify the members of the chat when a message is created, edited or deleted. Messages removed
+-- by moderators are tombstones too, messages are never deleted for real
+CREATE OR REPLACE FUNCTION add_to_message()
+    RETURNS TRIGGER AS $$
+DECLARE USERS BIGINT[];
+BEGIN
+    SELECT members INTO USERS FROM chats WHERE id = NEW.chat_id;
+    IF TG_OP = 'INSERT' THEN
+        RAISE NOTICE 'add_to_message: %', NEW;
+        PERFORM
+            pg_notify('chat_message_created', json_build_object('message', NEW, 'members', USERS)::TEXT);
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        PERFORM
+            pg_notify('chat_message_deleted', json_build_object('message', NEW, 'members', USERS)::TEXT);
+    ELSIF NEW.deleted_at IS NULL AND (OLD.content IS DISTINCT FROM NEW.content
+        OR OLD.modified_content IS DISTINCT FROM NEW.modified_content) THEN
+        PERFORM
+            pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', USERS)::TEXT);
+    END IF;
+    RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS add_to_message_trigger ON messages;
+
+CREATE TRIGGER add_to_message_trigger
+    AFTER INSERT OR UPDATE ON messages
+    FOR EACH ROW
+    EXECUTE FUNCTION add_to_message();
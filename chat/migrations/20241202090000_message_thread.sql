@@ -0,0 +1,31 @@
+-- threaded replies: a reply points to the message it answers and to the root of its thread,
+-- which is the message it answers when that one is not in a thread itself
+ALTER TABLE messages
+    ADD COLUMN reply_to BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+    ADD COLUMN thread_id BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+    -- replies in the thread of a root message, deleted ones excluded
+    ADD COLUMN reply_count INT NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS messages_thread_id_index ON messages(thread_id, id DESC)
+    WHERE thread_id IS NOT NULL;
+
+CREATE OR REPLACE FUNCTION update_thread_reply_count()
+    RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' AND NEW.thread_id IS NOT NULL THEN
+        UPDATE messages SET reply_count = reply_count + 1 WHERE id = NEW.thread_id;
+    ELSIF TG_OP = 'UPDATE' AND NEW.thread_id IS NOT NULL
+        AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        UPDATE messages SET reply_count = reply_count - 1 WHERE id = NEW.thread_id;
+    ELSIF TG_OP = 'DELETE' AND OLD.thread_id IS NOT NULL AND OLD.deleted_at IS NULL THEN
+        UPDATE messages SET reply_count = reply_count - 1 WHERE id = OLD.thread_id;
+    END IF;
+    RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER update_thread_reply_count_trigger
+    AFTER INSERT OR UPDATE OF deleted_at OR DELETE ON messages
+    FOR EACH ROW
+    EXECUTE FUNCTION update_thread_reply_count();
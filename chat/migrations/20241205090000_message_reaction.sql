@@ -0,0 +1,34 @@
+-- emoji reactions, a user reacts to a message with an emoji at most once
+CREATE TABLE IF NOT EXISTS message_reactions (
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    emoji VARCHAR(64) NOT NULL,
+    -- the chat of the message, to notify its members
+    chat_id BIGINT NOT NULL REFERENCES chats(id),
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, user_id, emoji)
+);
+
+-- notify the members of the chat when a reaction is added or removed
+CREATE OR REPLACE FUNCTION reaction_changed()
+    RETURNS TRIGGER AS $$
+DECLARE USERS BIGINT[];
+DECLARE REACTION message_reactions;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        REACTION := NEW;
+    ELSE
+        REACTION := OLD;
+    END IF;
+    SELECT members INTO USERS FROM chats WHERE id = REACTION.chat_id;
+    PERFORM
+        pg_notify('chat_reaction_changed', json_build_object('op', TG_OP, 'reaction', REACTION, 'members', USERS)::TEXT);
+    RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER reaction_changed_trigger
+    AFTER INSERT OR DELETE ON message_reactions
+    FOR EACH ROW
+    EXECUTE FUNCTION reaction_changed();
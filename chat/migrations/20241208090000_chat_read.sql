@@ -0,0 +1,29 @@
+-- the last message each member has read in a chat, messages after it are unread
+CREATE TABLE IF NOT EXISTS chat_reads (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    last_read_id BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, user_id)
+);
+
+-- notify the members of the chat when a member read further, so that the other devices of the
+-- member catch up too
+CREATE OR REPLACE FUNCTION read_receipt()
+    RETURNS TRIGGER AS $$
+DECLARE USERS BIGINT[];
+BEGIN
+    IF TG_OP = 'INSERT' OR OLD.last_read_id IS DISTINCT FROM NEW.last_read_id THEN
+        SELECT members INTO USERS FROM chats WHERE id = NEW.chat_id;
+        PERFORM
+            pg_notify('chat_read_receipt', json_build_object('receipt', NEW, 'members', USERS)::TEXT);
+    END IF;
+    RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER read_receipt_trigger
+    AFTER INSERT OR UPDATE ON chat_reads
+    FOR EACH ROW
+    EXECUTE FUNCTION read_receipt();
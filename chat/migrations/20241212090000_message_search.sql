@@ -0,0 +1,20 @@
+-- full-text search over messages, the modified content (e.g. a translation) is searchable too.
+-- the simple configuration doesn't stem, so that it works the same for any language
+CREATE OR REPLACE FUNCTION message_search_vector(content TEXT, modified_content TEXT)
+    RETURNS tsvector AS $$
+    SELECT to_tsvector('simple'::regconfig, content || ' ' || COALESCE(modified_content, ''))
+$$
+LANGUAGE sql
+IMMUTABLE;
+
+CREATE INDEX IF NOT EXISTS messages_search_index
+    ON messages USING GIN(message_search_vector(content, modified_content));
+
+-- escape text to be embedded in html, e.g. the content of a search snippet around its <mark> tags
+CREATE OR REPLACE FUNCTION html_escape(content TEXT)
+    RETURNS TEXT AS $$
+    SELECT replace(replace(replace(replace(replace(content,
+        '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;')
+$$
+LANGUAGE sql
+IMMUTABLE;
@@ -16,6 +16,33 @@ pub enum AppEvent {
     AddToChat(Chat),
     RemoveFromChat(Chat),
     NewMessage(Message),
+    /// a reply in the thread of a message, the root has one more reply
+    NewThreadReply(Message),
+    MessageUpdated(Message),
+    /// the message is a tombstone, without content
+    MessageDeleted(Message),
+    ReactionChanged(ReactionChanged),
+    /// a member read the chat further, sent to the other devices of the member as well
+    ReadReceipt(ReadReceipt),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionChanged {
+    /// true when the reaction is added, false when it is removed
+    pub added: bool,
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub user_id: i64,
+    pub emoji: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ReadReceipt {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub last_read_id: i64,
 }
 
 #[derive(Debug)]
@@ -33,7 +60,28 @@ struct ChatUpdated {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct ChatMessageCreated {
+struct ChatReactionChanged {
+    op: String,
+    reaction: Reaction,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Reaction {
+    chat_id: i64,
+    message_id: i64,
+    user_id: i64,
+    emoji: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatReadReceipt {
+    receipt: ReadReceipt,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatMessageChanged {
     members: Vec<i64>,
     message: Message,
 }
@@ -42,6 +90,10 @@ pub async fn setup_pg_listener(state: AppState) -> Result<()> {
     let mut listener = PgListener::connect(&state.config.server.db_url).await?;
     listener.listen("chat_updated").await?;
     listener.listen("chat_message_created").await?;
+    listener.listen("chat_message_updated").await?;
+    listener.listen("chat_message_deleted").await?;
+    listener.listen("chat_reaction_changed").await?;
+    listener.listen("chat_read_receipt").await?;
 
     let mut stream = listener.into_stream();
     tokio::spawn(async move {
@@ -82,12 +134,44 @@ impl Notification {
                     event: Arc::new(event),
                 })
             }
-            "chat_message_created" => {
-                let payload: ChatMessageCreated = serde_json::from_str(payload)?;
+            "chat_message_created" | "chat_message_updated" | "chat_message_deleted" => {
+                let payload: ChatMessageChanged = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                let event = match r#type {
+                    "chat_message_created" if payload.message.thread_id.is_some() => {
+                        AppEvent::NewThreadReply(payload.message)
+                    }
+                    "chat_message_created" => AppEvent::NewMessage(payload.message),
+                    "chat_message_updated" => AppEvent::MessageUpdated(payload.message),
+                    _ => AppEvent::MessageDeleted(payload.message),
+                };
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(event),
+                })
+            }
+            "chat_reaction_changed" => {
+                let payload: ChatReactionChanged = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                let reaction = payload.reaction;
+                let event = ReactionChanged {
+                    added: payload.op == "INSERT",
+                    chat_id: reaction.chat_id,
+                    message_id: reaction.message_id,
+                    user_id: reaction.user_id,
+                    emoji: reaction.emoji,
+                };
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(AppEvent::ReactionChanged(event)),
+                })
+            }
+            "chat_read_receipt" => {
+                let payload: ChatReadReceipt = serde_json::from_str(payload)?;
                 let user_ids = payload.members.iter().map(|v| *v as u64).collect();
                 Ok(Self {
                     user_ids,
-                    event: Arc::new(AppEvent::NewMessage(payload.message)),
+                    event: Arc::new(AppEvent::ReadReceipt(payload.receipt)),
                 })
             }
             _ => anyhow::bail!("Invalid notification type"),
@@ -38,6 +38,11 @@ pub(crate) async fn sse_handler(
                 AppEvent::AddToChat(_) => "AddToChat",
                 AppEvent::RemoveFromChat(_) => "RemoveFromChat",
                 AppEvent::NewMessage(_) => "NewMessage",
+                AppEvent::NewThreadReply(_) => "NewThreadReply",
+                AppEvent::MessageUpdated(_) => "MessageUpdated",
+                AppEvent::MessageDeleted(_) => "MessageDeleted",
+                AppEvent::ReactionChanged(_) => "ReactionChanged",
+                AppEvent::ReadReceipt(_) => "ReadReceipt",
             };
             let v = serde_json::to_string(&v).expect("Failed to serialize event");
             yield Ok(Event::default().data(v).event(name))